@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+
+use crate::auto_tagger::TaggerSettings;
+use crate::external_captioner::CaptionerSettings;
+use crate::booru_tag_manager::TagCategory;
+
+const APP_DIR_NAME: &str = "DatasetEditor";
+const SETTINGS_FILE_NAME: &str = "settings.json";
+
+// Per-user settings shared by all datasets, stored in the OS config directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppSettings {
+    // Tag database CSVs in precedence order, reloaded at startup
+    pub tag_sources: Vec<PathBuf>,
+    // sRGB overrides; categories without an entry use their default color
+    pub category_colors: HashMap<TagCategory, [u8; 3]>,
+    // Offline tag wiki used for description tooltips
+    pub wiki_dump: Option<PathBuf>,
+    // `tag,translation` CSV for searching by localized names
+    pub translation_file: Option<PathBuf>,
+    // `tag,other_tag,count` table for related-tag suggestions beyond the dataset
+    pub cooccurrence_table: Option<PathBuf>,
+    // CLIP BPE merge list for caption token counts
+    pub clip_tokenizer: Option<PathBuf>,
+    // Local ONNX tagger model and how its predictions are applied
+    pub auto_tagger: TaggerSettings,
+    // Subprocess captioner speaking the JSON-lines protocol
+    pub external_captioner: CaptionerSettings,
+}
+
+impl AppSettings {
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(APP_DIR_NAME).join(SETTINGS_FILE_NAME))
+    }
+
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                eprintln!("Ignoring invalid settings {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn category_color(&self, category: TagCategory) -> [u8; 3] {
+        self.category_colors.get(&category).copied().unwrap_or_else(|| category.default_color())
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let path = Self::path().ok_or("No config directory available")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
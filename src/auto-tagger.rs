@@ -0,0 +1,261 @@
+// Local WD14-style (SmilingWolf) ONNX tagger run on the CPU with tract. The model takes a square
+// BGR image of 0-255 floats in NHWC layout and returns one sigmoid probability per label; labels
+// come from the `selected_tags.csv` shipped next to `model.onnx`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use csv::ReaderBuilder;
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use tract_onnx::prelude::*;
+use tract_onnx::tract_hir::infer::Factoid;
+
+const LABELS_FILE_NAME: &str = "selected_tags.csv";
+// Used when the model does not declare its input size
+const DEFAULT_INPUT_SIZE: usize = 448;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelKind {
+    Rating,
+    General,
+    Character,
+}
+
+#[derive(Debug, Clone)]
+pub struct Prediction {
+    pub name: String,
+    pub kind: LabelKind,
+    pub confidence: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MergeMode {
+    // Add predicted tags the image does not have yet
+    #[default]
+    Append,
+    // Replace the image's tags with the predictions
+    Replace,
+    // Only offer the predictions for review
+    Suggest,
+}
+
+impl MergeMode {
+    pub const ALL: [MergeMode; 3] = [MergeMode::Append, MergeMode::Replace, MergeMode::Suggest];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            MergeMode::Append => "Append",
+            MergeMode::Replace => "Replace",
+            MergeMode::Suggest => "Suggest only",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TaggerSettings {
+    pub model: Option<PathBuf>,
+    pub general_threshold: f32,
+    pub character_threshold: f32,
+    pub include_rating: bool,
+    pub merge_mode: MergeMode,
+    // Tags below this confidence are queued for review
+    pub review_below: f32,
+}
+
+impl Default for TaggerSettings {
+    fn default() -> Self {
+        Self {
+            model: None,
+            general_threshold: 0.35,
+            character_threshold: 0.85,
+            include_rating: false,
+            merge_mode: MergeMode::default(),
+            review_below: 0.7,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Label {
+    name: String,
+    kind: LabelKind,
+}
+
+pub struct TaggerModel {
+    plan: TypedRunnableModel<TypedModel>,
+    size: usize,
+    labels: Vec<Label>,
+}
+
+impl TaggerModel {
+    pub fn load(model_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let labels_path = model_path.with_file_name(LABELS_FILE_NAME);
+        let labels = load_labels(&labels_path)
+            .map_err(|err| format!("{}: {}", labels_path.display(), err))?;
+
+        let model = tract_onnx::onnx().model_for_path(model_path)?;
+        let size = model.input_fact(0)?
+            .shape
+            .dims()
+            .nth(1)
+            .and_then(|dim| dim.concretize())
+            .and_then(|dim| dim.as_i64())
+            .map_or(DEFAULT_INPUT_SIZE, |size| size as usize);
+        let plan = model
+            .with_input_fact(0, f32::fact([1, size, size, 3]).into())?
+            .into_optimized()?
+            .into_runnable()?;
+
+        Ok(Self { plan, size, labels })
+    }
+
+    pub fn label_count(&self) -> usize {
+        self.labels.len()
+    }
+
+    // Predictions above the thresholds, most confident first
+    pub fn predict(&self, image: &DynamicImage, settings: &TaggerSettings) -> Result<Vec<Prediction>, Box<dyn std::error::Error>> {
+        let input = self.preprocess(image);
+        let outputs = self.plan.run(tvec!(input.into()))?;
+        let probabilities = outputs[0].to_array_view::<f32>()?;
+
+        Ok(select_predictions(&self.labels, probabilities.iter().copied(), settings))
+    }
+
+    // Pads to a white square, resizes to the model's input and converts to BGR floats
+    fn preprocess(&self, image: &DynamicImage) -> Tensor {
+        let (width, height) = image.dimensions();
+        let side = width.max(height);
+        let mut square = RgbaImage::from_pixel(side, side, Rgba([255, 255, 255, 255]));
+        image::imageops::overlay(&mut square, &image.to_rgba8(), ((side - width) / 2) as i64, ((side - height) / 2) as i64);
+        let resized = image::imageops::resize(
+            &square,
+            self.size as u32,
+            self.size as u32,
+            image::imageops::FilterType::CatmullRom,
+        );
+
+        tract_ndarray::Array4::from_shape_fn((1, self.size, self.size, 3), |(_, y, x, c)| {
+            resized.get_pixel(x as u32, y as u32)[2 - c] as f32
+        })
+        .into()
+    }
+}
+
+// Keeps the labels whose confidence clears their kind's threshold, most confident first
+fn select_predictions(labels: &[Label], probabilities: impl Iterator<Item = f32>, settings: &TaggerSettings) -> Vec<Prediction> {
+    let mut predictions: Vec<Prediction> = labels.iter()
+        .zip(probabilities)
+        .filter(|(label, confidence)| match label.kind {
+            LabelKind::Rating => settings.include_rating,
+            LabelKind::General => *confidence >= settings.general_threshold,
+            LabelKind::Character => *confidence >= settings.character_threshold,
+        })
+        .map(|(label, confidence)| {
+            // Ratings are bare words in the label list
+            let name = match label.kind {
+                LabelKind::Rating => format!("rating:{}", label.name),
+                _ => label.name.clone(),
+            };
+            Prediction { name, kind: label.kind, confidence }
+        })
+        .collect();
+
+    // Only the most likely rating is meaningful
+    if let Some(best) = predictions.iter()
+        .filter(|p| p.kind == LabelKind::Rating)
+        .max_by(|a, b| a.confidence.total_cmp(&b.confidence))
+        .map(|p| p.name.clone())
+    {
+        predictions.retain(|p| p.kind != LabelKind::Rating || p.name == best);
+    }
+    predictions.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    predictions
+}
+
+// `tag_id,name,category,count`; category 9 is a rating, 4 a character, anything else general
+fn load_labels(path: &Path) -> Result<Vec<Label>, Box<dyn std::error::Error>> {
+    let contents = fs::read_to_string(path)?;
+    let mut rdr = ReaderBuilder::new().has_headers(true).from_reader(contents.as_bytes());
+    let headers = rdr.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|h| h.trim() == name);
+    let name_col = column("name").ok_or("missing `name` column")?;
+    let category_col = column("category").ok_or("missing `category` column")?;
+
+    let mut labels = Vec::new();
+    for record in rdr.records() {
+        let record = record?;
+        let kind = match record.get(category_col).map(str::trim) {
+            Some("9") => LabelKind::Rating,
+            Some("4") => LabelKind::Character,
+            _ => LabelKind::General,
+        };
+        labels.push(Label { name: record.get(name_col).unwrap_or_default().to_string(), kind });
+    }
+    Ok(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LABELS: &str = "tag_id,name,category,count\n\
+        9999999,general,9,807331\n\
+        9999998,explicit,9,397466\n\
+        470575,1girl,0,4225150\n\
+        212816,solo,0,3570879\n\
+        1303225,hatsune_miku,4,98521\n\
+        16751,smile,0,1558917\n";
+
+    fn labels() -> Vec<Label> {
+        let path = std::env::temp_dir().join(format!("auto-tagger-labels-{}.csv", std::process::id()));
+        fs::write(&path, LABELS).unwrap();
+        let labels = load_labels(&path);
+        let _ = fs::remove_file(&path);
+        labels.unwrap()
+    }
+
+    fn names(predictions: &[Prediction]) -> Vec<&str> {
+        predictions.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    #[test]
+    fn reads_label_kinds_from_the_category_column() {
+        let labels = labels();
+        let kinds: Vec<(&str, LabelKind)> = labels.iter().map(|l| (l.name.as_str(), l.kind)).collect();
+        assert_eq!(kinds, [
+            ("general", LabelKind::Rating),
+            ("explicit", LabelKind::Rating),
+            ("1girl", LabelKind::General),
+            ("solo", LabelKind::General),
+            ("hatsune_miku", LabelKind::Character),
+            ("smile", LabelKind::General),
+        ]);
+    }
+
+    #[test]
+    fn rejects_label_files_without_the_needed_columns() {
+        let path = std::env::temp_dir().join(format!("auto-tagger-bad-labels-{}.csv", std::process::id()));
+        fs::write(&path, "tag_id,name,count\n1,solo,5\n").unwrap();
+        let result = load_labels(&path);
+        let _ = fs::remove_file(&path);
+        assert!(result.unwrap_err().to_string().contains("category"));
+    }
+
+    #[test]
+    fn filters_by_kind_threshold_and_keeps_one_rating() {
+        let labels = labels();
+        let probabilities = [0.2, 0.7, 0.95, 0.4, 0.8, 0.3];
+        let settings = TaggerSettings { general_threshold: 0.35, character_threshold: 0.85, ..Default::default() };
+
+        // The character is under its own threshold even though it clears the general one
+        let predictions = select_predictions(&labels, probabilities.into_iter(), &settings);
+        assert_eq!(names(&predictions), ["1girl", "solo"]);
+
+        let settings = TaggerSettings { include_rating: true, character_threshold: 0.75, ..settings };
+        let predictions = select_predictions(&labels, probabilities.into_iter(), &settings);
+        assert_eq!(names(&predictions), ["1girl", "hatsune_miku", "rating:explicit", "solo"]);
+        assert_eq!(predictions[2].kind, LabelKind::Rating);
+    }
+}
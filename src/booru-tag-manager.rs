@@ -1,21 +1,171 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::Read;
-use csv::ReaderBuilder;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use serde::{Deserialize, Serialize};
-use egui;
+
+use crate::tag_csv::{parse_tag_csv, LoadReport};
+use crate::tag_index::TagIndex;
+
+const MAX_SUGGESTIONS: usize = 10;
+
+// Site-independent tag category; raw ids differ between Danbooru and e621
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum TagCategory {
+    #[default]
+    General,
+    Artist,
+    Copyright,
+    Character,
+    Species,
+    Meta,
+    Lore,
+    // Dataset-specific tags such as character names and activation tokens
+    Custom,
+}
+
+impl TagCategory {
+    pub const ALL: [TagCategory; 8] = [
+        TagCategory::General,
+        TagCategory::Artist,
+        TagCategory::Copyright,
+        TagCategory::Character,
+        TagCategory::Species,
+        TagCategory::Meta,
+        TagCategory::Lore,
+        TagCategory::Custom,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TagCategory::General => "General",
+            TagCategory::Artist => "Artist",
+            TagCategory::Copyright => "Copyright",
+            TagCategory::Character => "Character",
+            TagCategory::Species => "Species",
+            TagCategory::Meta => "Meta",
+            TagCategory::Lore => "Lore",
+            TagCategory::Custom => "Custom",
+        }
+    }
+
+    // Roughly the colors the boorus use, as sRGB
+    pub fn default_color(&self) -> [u8; 3] {
+        match self {
+            TagCategory::General => [0x00, 0x9b, 0xe6],
+            TagCategory::Artist => [0xff, 0x8a, 0x8b],
+            TagCategory::Copyright => [0xc7, 0x97, 0xff],
+            TagCategory::Character => [0x35, 0xc6, 0x4a],
+            TagCategory::Species => [0xed, 0x5d, 0x1f],
+            TagCategory::Meta => [0xea, 0xd0, 0x84],
+            TagCategory::Lore => [0x5f, 0xb0, 0x5f],
+            TagCategory::Custom => [0x3f, 0xd0, 0xd0],
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BooruTag {
     pub name: String,
+    // Category id as written in the source CSV
     pub tag_type: i32,
+    #[serde(default)]
+    pub category: TagCategory,
+    pub post_count: u64,
     pub aliases: Vec<String>,
+    // From a description column in the tag CSV, if it has one
+    #[serde(default)]
+    pub description: Option<String>,
+    // Localized names (e.g. Japanese, Chinese) that should find this tag
+    #[serde(default)]
+    pub translations: Vec<String>,
+}
+
+// One loaded tag vocabulary, e.g. Danbooru, e621 or a team-private list
+pub struct TagSource {
+    pub name: String,
+    pub path: PathBuf,
+    pub tags: HashMap<String, BooruTag>,
+    pub report: LoadReport,
+    index: TagIndex,
+    // Lowercased localized name -> tag, for names typed in full
+    translation_targets: HashMap<String, String>,
+}
+
+impl TagSource {
+    pub fn load_from_csv(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::load_from_csv_with_progress(path, |_| {})
+    }
+
+    // Same as `load_from_csv`, reporting the fraction of the file parsed so far
+    pub fn load_from_csv_with_progress(
+        path: &Path,
+        mut progress: impl FnMut(f32),
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let (tags, report) = parse_tag_csv(&contents, &file_name, &mut progress);
+
+        let name = path.file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "tags".to_string());
+        Ok(Self::with_report(name, path, tags, report))
+    }
+
+    pub fn from_tags(name: &str, path: &Path, tags: HashMap<String, BooruTag>) -> Self {
+        let report = LoadReport { loaded: tags.len(), ..LoadReport::default() };
+        Self::with_report(name.to_string(), path, tags, report)
+    }
+
+    fn with_report(name: String, path: &Path, tags: HashMap<String, BooruTag>, report: LoadReport) -> Self {
+        let index = TagIndex::build(&tags);
+        let translation_targets = translation_targets(tags.values()
+            .map(|tag| (&tag.name, tag.translations.as_slice())));
+        Self { name, path: path.to_path_buf(), tags, report, index, translation_targets }
+    }
+}
+
+// Lowercased localized name -> canonical tag; a name listed under several tags keeps the first seen
+fn translation_targets<'a>(tags: impl Iterator<Item = (&'a String, &'a [String])>) -> HashMap<String, String> {
+    let mut targets = HashMap::new();
+    for (name, translations) in tags {
+        for translation in translations {
+            targets.entry(crate::tag_syntax::lookup_name(translation).to_lowercase()).or_insert_with(|| name.clone());
+        }
+    }
+    targets
+}
+
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub name: String,
+    pub source: String,
 }
 
 #[derive(Default)]
 pub struct BooruTagManager {
-    pub tags: HashMap<String, BooruTag>,
-    tag_suggestions: Vec<String>,
+    // Ordered by precedence: earlier sources win lookups and rank first in suggestions. Shared
+    // with lookup snapshots handed to background work.
+    sources: Vec<Arc<TagSource>>,
+    // The dataset's own vocabulary; always active and ahead of every other source
+    custom_source: Option<Arc<TagSource>>,
+    // Descriptions from an offline wiki dump, keyed by tag name
+    wiki: HashMap<String, String>,
+    // Canonical tag -> localized names from a translation file
+    translations: HashMap<String, Vec<String>>,
+    // Lowercased localized name -> canonical tag over `translations`
+    translation_targets: HashMap<String, String>,
+    // Search index over `translations`, rebuilt lazily since it ranks by the sources' post counts
+    translation_source: Option<TagSource>,
+    // Paths of the sources switched off; names can change as other sources come and go
+    disabled_sources: HashSet<PathBuf>,
+    // Bumped whenever the sources or their precedence change, for callers caching lookups
+    revision: u64,
+    tag_suggestions: Vec<Suggestion>,
     current_input: String,
     selected_suggestion: Option<usize>,
     is_focused: bool,
@@ -38,8 +188,8 @@ impl BooruTagManager {
 
         // Update current input to show selected suggestion
         if let Some(idx) = self.selected_suggestion {
-            if let Some(tag) = self.tag_suggestions.get(idx) {
-                self.current_input = tag.clone();
+            if let Some(suggestion) = self.tag_suggestions.get(idx) {
+                self.current_input = suggestion.name.clone();
             }
         }
     }
@@ -56,85 +206,225 @@ impl BooruTagManager {
 
         // Update current input to show selected suggestion
         if let Some(idx) = self.selected_suggestion {
-            if let Some(tag) = self.tag_suggestions.get(idx) {
-                self.current_input = tag.clone();
+            if let Some(suggestion) = self.tag_suggestions.get(idx) {
+                self.current_input = suggestion.name.clone();
             }
         }
     }
 
-    pub fn load_from_csv(&mut self, path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
-        let mut file = File::open(path)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
+    pub fn load_from_csv(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        self.add_source(TagSource::load_from_csv(path)?);
+        Ok(())
+    }
 
-        let mut rdr = ReaderBuilder::new()
-            .has_headers(false)
-            .from_reader(contents.as_bytes());
-
-        for result in rdr.records() {
-            let record = result?;
-            if record.len() >= 4 {
-                let name = record[0].to_string();
-                let tag_type = record[1].parse::<i32>().unwrap_or(0);
-                let aliases: Vec<String> = record[3]
-                    .split(',')
-                    .map(|s| s.trim().to_string())
-                    .collect();
-
-                self.tags.insert(name.clone(), BooruTag {
-                    name,
-                    tag_type,
-                    aliases,
-                });
-            }
+    // New sources get the lowest precedence; reloading a file replaces it in place
+    pub fn add_source(&mut self, mut source: TagSource) {
+        self.translation_source = None;
+        self.revision += 1;
+        if let Some(existing) = self.sources.iter_mut().find(|s| s.path == source.path) {
+            source.name = existing.name.clone();
+            *existing = Arc::new(source);
+            return;
         }
 
-        Ok(())
+        let base_name = source.name.clone();
+        let mut suffix = 2;
+        while self.sources.iter().any(|s| s.name == source.name) {
+            source.name = format!("{} ({})", base_name, suffix);
+            suffix += 1;
+        }
+        self.sources.push(Arc::new(source));
     }
 
-    pub fn get_tag_type(&self, tag: &str) -> Option<i32> {
-        self.tags.get(tag).map(|t| t.tag_type)
+    pub fn remove_source(&mut self, idx: usize) {
+        self.translation_source = None;
+        self.revision += 1;
+        if idx < self.sources.len() {
+            let source = self.sources.remove(idx);
+            self.disabled_sources.remove(&source.path);
+        }
     }
 
-    pub fn get_aliases(&self, tag: &str) -> Option<&Vec<String>> {
-        self.tags.get(tag).map(|t| &t.aliases)
+    pub fn move_source(&mut self, from: usize, to: usize) {
+        if from < self.sources.len() && to < self.sources.len() {
+            self.sources.swap(from, to);
+            self.revision += 1;
+        }
     }
-    pub fn update_suggestions(&mut self, input: &str) {
-        if input.is_empty() {
-            self.tag_suggestions.clear();
-            return;
+
+    pub fn sources(&self) -> &[Arc<TagSource>] {
+        &self.sources
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn is_source_active(&self, path: &Path) -> bool {
+        !self.disabled_sources.contains(path)
+    }
+
+    pub fn set_source_active(&mut self, path: &Path, active: bool) {
+        if active {
+            self.disabled_sources.remove(path);
+        } else {
+            self.disabled_sources.insert(path.to_path_buf());
         }
+        self.revision += 1;
+        self.tag_suggestions.clear();
+    }
+
+    pub fn set_disabled_sources(&mut self, paths: &[PathBuf]) {
+        self.disabled_sources = paths.iter().cloned().collect();
+        self.revision += 1;
+        self.tag_suggestions.clear();
+    }
+
+    pub fn disabled_sources(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.disabled_sources.iter().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    pub fn set_wiki(&mut self, wiki: HashMap<String, String>) {
+        self.wiki = wiki;
+    }
+
+    pub fn wiki_len(&self) -> usize {
+        self.wiki.len()
+    }
+
+    // The tag itself when known, otherwise the tag it is an alias of
+    pub fn canonical_name(&self, name: &str) -> Option<String> {
+        if self.lookup(name).is_some() {
+            return Some(name.to_string());
+        }
+        self.active_sources().find_map(|source| source.index.alias_target(name).map(str::to_string))
+    }
 
-        // Convert input spaces to underscores for matching
-        let search_input = input.replace(' ', "_");
+    // Wiki dump entries win over descriptions that came with a tag CSV
+    pub fn get_description(&self, tag: &str) -> Option<&str> {
+        self.wiki.get(tag)
+            .map(String::as_str)
+            .or_else(|| self.active_sources().find_map(|source| source.tags.get(tag)?.description.as_deref()))
+    }
+
+    pub fn set_translations(&mut self, translations: HashMap<String, Vec<String>>) {
+        self.translation_targets = translation_targets(translations.iter()
+            .map(|(name, names)| (name, names.as_slice())));
+        self.translations = translations;
+        self.translation_source = None;
+        self.tag_suggestions.clear();
+    }
+
+    pub fn translation_count(&self) -> usize {
+        self.translations.values().map(Vec::len).sum()
+    }
 
-        let mut matches: Vec<_> = self.tags.values()
-            .filter(|tag| {
-                tag.name.contains(&search_input) ||
-                    tag.aliases.iter().any(|alias| alias.contains(&search_input))
+    // The canonical tag for a localized name typed in full, e.g. `猫耳` -> `cat_ears`
+    pub fn canonical_for_translation(&self, text: &str) -> Option<String> {
+        let text = crate::tag_syntax::lookup_name(text).to_lowercase();
+        self.translation_targets.get(&text)
+            .or_else(|| self.active_sources().find_map(|source| source.translation_targets.get(&text)))
+            .cloned()
+    }
+
+    fn ensure_translation_source(&mut self) {
+        if self.translation_source.is_some() || self.translations.is_empty() {
+            return;
+        }
+        let tags = self.translations.iter()
+            .map(|(name, translations)| {
+                let tag = BooruTag {
+                    name: name.clone(),
+                    tag_type: 0,
+                    category: TagCategory::General,
+                    post_count: self.get_post_count(name).unwrap_or(0),
+                    aliases: Vec::new(),
+                    description: None,
+                    translations: translations.clone(),
+                };
+                (name.clone(), tag)
             })
-            .map(|tag| tag.name.clone())
             .collect();
+        self.translation_source = Some(TagSource::from_tags("Translations", Path::new(""), tags));
+    }
 
-        matches.sort_by(|a, b| {
-            let a_exact = a == &search_input;
-            let b_exact = b == &search_input;
-            let a_starts = a.starts_with(&search_input);
-            let b_starts = b.starts_with(&search_input);
+    pub fn set_custom_source(&mut self, source: Option<TagSource>) {
+        self.custom_source = source.map(Arc::new);
+        self.revision += 1;
+        self.tag_suggestions.clear();
+    }
 
-            if a_exact != b_exact {
-                return b_exact.cmp(&a_exact);
-            }
-            if a_starts != b_starts {
-                return b_starts.cmp(&a_starts);
+    fn active_sources(&self) -> impl Iterator<Item = &TagSource> {
+        self.shared_active_sources().map(Arc::as_ref)
+    }
+
+    fn shared_active_sources(&self) -> impl Iterator<Item = &Arc<TagSource>> {
+        self.custom_source.iter()
+            .chain(self.sources.iter().filter(|source| self.is_source_active(&source.path)))
+    }
+
+    // The active sources as they are now, for lookups that outlive a borrow of the manager
+    pub fn lookup_snapshot(&self) -> TagLookup {
+        TagLookup { sources: self.shared_active_sources().cloned().collect() }
+    }
+
+    pub fn has_tags(&self) -> bool {
+        self.sources.iter().any(|source| !source.tags.is_empty())
+    }
+
+    // The tag from the highest-precedence active source that knows it
+    pub fn lookup(&self, tag: &str) -> Option<(&BooruTag, &TagSource)> {
+        self.active_sources().find_map(|source| source.tags.get(tag).map(|t| (t, source)))
+    }
+
+    pub fn get_category(&self, tag: &str) -> Option<TagCategory> {
+        self.lookup(tag).map(|(t, _)| t.category)
+    }
+
+    pub fn get_post_count(&self, tag: &str) -> Option<u64> {
+        self.lookup(tag).map(|(t, _)| t.post_count)
+    }
+
+    pub fn update_suggestions(&mut self, input: &str) {
+        if input.is_empty() {
+            self.tag_suggestions.clear();
+            return;
+        }
+
+        // Strip weights/escapes and convert spaces to underscores for matching
+        let search_input = crate::tag_syntax::lookup_name(input);
+        if search_input.is_empty() {
+            self.tag_suggestions.clear();
+            return;
+        }
+
+        // Better matches first; between equal matches the higher-precedence source wins.
+        // Translations only add names and rank after every source.
+        self.ensure_translation_source();
+        let mut hits = Vec::new();
+        for (precedence, source) in self.active_sources().chain(self.translation_source.as_ref()).enumerate() {
+            for (rank, (tag_id, tier)) in source.index.search(&search_input, MAX_SUGGESTIONS).into_iter().enumerate() {
+                hits.push((tier, precedence, rank, source.index.name(tag_id), source.name.as_str()));
             }
-            a.cmp(b)
-        });
+        }
+        hits.sort_by_key(|&(tier, precedence, rank, _, _)| (tier, precedence, rank));
 
-        self.tag_suggestions = matches.into_iter().take(10).collect();
+        let mut seen = HashSet::new();
+        let suggestions: Vec<Suggestion> = hits.into_iter()
+            .filter(|(_, _, _, name, _)| seen.insert(*name))
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, _, _, name, source)| Suggestion { name: name.to_string(), source: source.to_string() })
+            .collect();
+        self.tag_suggestions = suggestions;
     }
 
-    pub fn draw_tag_editor(&mut self, ui: &mut egui::Ui) -> Option<String> {
+    pub fn draw_tag_editor(
+        &mut self,
+        ui: &mut egui::Ui,
+        category_color: impl Fn(TagCategory) -> egui::Color32,
+    ) -> Option<String> {
         let mut selected_tag = None;
 
         // Give the text input a consistent ID
@@ -155,20 +445,24 @@ impl BooruTagManager {
         self.is_focused = response.has_focus();
 
         // Handle Enter key to add a tag
-        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
-            if !self.current_input.trim().is_empty() {
-                println!("Enter pressed with input: {}", self.current_input);
-                selected_tag = Some(self.current_input.clone());
-                self.current_input.clear();
-                self.tag_suggestions.clear();
-                // Request focus back after selection
-                ui.ctx().memory_mut(|mem| mem.request_focus(text_edit_id));
-            }
+        if response.lost_focus()
+            && ui.input(|i| i.key_pressed(egui::Key::Enter))
+            && !self.current_input.trim().is_empty()
+        {
+            println!("Enter pressed with input: {}", self.current_input);
+            // Localized names typed in full are replaced by the canonical tag
+            let input = self.current_input.clone();
+            selected_tag = Some(self.canonical_for_translation(&input).unwrap_or(input));
+            self.current_input.clear();
+            self.tag_suggestions.clear();
+            // Request focus back after selection
+            ui.ctx().memory_mut(|mem| mem.request_focus(text_edit_id));
         }
 
         // Display suggestions in a pop-up
         if !self.tag_suggestions.is_empty() {
             let suggestions = self.tag_suggestions.clone();
+            let show_sources = self.active_sources().count() > 1;
 
             egui::Window::new("Tag Suggestions")
                 .fixed_size([300.0, 300.0])
@@ -180,14 +474,35 @@ impl BooruTagManager {
                             let is_selected = self.selected_suggestion == Some(idx);
 
                             let text = if is_selected {
-                                egui::RichText::new(suggestion).strong()
+                                egui::RichText::new(&suggestion.name).strong()
                                     .background_color(ui.style().visuals.selection.bg_fill)
                             } else {
-                                egui::RichText::new(suggestion)
+                                egui::RichText::new(&suggestion.name)
                             };
 
-                            if ui.add(egui::Label::new(text).sense(egui::Sense::click())).clicked() {
-                                selected_tag = Some(suggestion.clone());
+                            let clicked = ui.horizontal(|ui| {
+                                if let Some(category) = self.get_category(&suggestion.name) {
+                                    ui.colored_label(category_color(category), "●")
+                                        .on_hover_text(category.label());
+                                }
+                                let mut label = ui.add(egui::Label::new(text).sense(egui::Sense::click()));
+                                if let Some(description) = self.get_description(&suggestion.name) {
+                                    label = label.on_hover_text(description);
+                                }
+                                let clicked = label.clicked();
+                                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                    if let Some(count) = self.get_post_count(&suggestion.name) {
+                                        ui.weak(format_post_count(count));
+                                    }
+                                    if show_sources {
+                                        ui.weak(&suggestion.source);
+                                    }
+                                });
+                                clicked
+                            }).inner;
+
+                            if clicked {
+                                selected_tag = Some(suggestion.name.clone());
                                 self.current_input.clear();
                                 self.tag_suggestions.clear();
                                 // Request focus back to the text input after selection
@@ -202,4 +517,125 @@ impl BooruTagManager {
     }
 
 
-}
\ No newline at end of file
+}
+
+// Compact post count for display, e.g. 5975112 -> "6.0M"
+pub fn format_post_count(count: u64) -> String {
+    match count {
+        0..=999 => count.to_string(),
+        1_000..=999_999 => format!("{:.1}k", count as f64 / 1_000.0),
+        _ => format!("{:.1}M", count as f64 / 1_000_000.0),
+    }
+}
+
+// Tag lookups against a fixed set of sources, in precedence order
+#[derive(Clone, Default)]
+pub struct TagLookup {
+    sources: Vec<Arc<TagSource>>,
+}
+
+impl TagLookup {
+    pub fn lookup(&self, tag: &str) -> Option<&BooruTag> {
+        self.sources.iter().find_map(|source| source.tags.get(tag))
+    }
+
+    // The tag itself when known, otherwise the tag it is an alias of
+    pub fn canonical_name(&self, name: &str) -> Option<String> {
+        if self.lookup(name).is_some() {
+            return Some(name.to_string());
+        }
+        self.sources.iter().find_map(|source| source.index.alias_target(name).map(str::to_string))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUERIES: [(&str, Option<&str>); 8] = [
+        ("a", None),
+        ("1girl", Some("1girl")),
+        ("long_ha", Some("long_hair")),
+        ("oppai", Some("breasts")),
+        ("_hair", None),
+        ("lnog_hair", Some("long_hair")),
+        ("smlie", Some("smile")),
+        ("qqqqzzzz", None),
+    ];
+
+    fn bundled_tags() -> Option<BooruTagManager> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("danbooru-12-10-24-underscore.csv");
+        let mut manager = BooruTagManager::new();
+        manager.load_from_csv(&path).ok()?;
+        Some(manager)
+    }
+
+    #[test]
+    fn suggests_from_the_bundled_tags() {
+        let Some(mut manager) = bundled_tags() else {
+            return;
+        };
+        for (query, expected) in QUERIES {
+            manager.update_suggestions(query);
+            assert!(manager.tag_suggestions.len() <= MAX_SUGGESTIONS);
+            if let Some(expected) = expected {
+                assert!(manager.tag_suggestions.iter().any(|s| s.name == expected), "'{}' should suggest {}", query, expected);
+            }
+        }
+    }
+
+    fn tag(name: &str, translations: &[&str]) -> BooruTag {
+        BooruTag {
+            name: name.to_string(),
+            tag_type: 0,
+            category: TagCategory::General,
+            post_count: 0,
+            aliases: Vec::new(),
+            description: None,
+            translations: translations.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn maps_full_translations_to_canonical_tags() {
+        let mut manager = BooruTagManager::new();
+        let tags = [tag("cat_ears", &["猫耳", "Nekomimi"]), tag("smile", &["笑顔"])];
+        manager.add_source(TagSource::from_tags("tags", Path::new("tags.csv"), tags.into_iter()
+            .map(|tag| (tag.name.clone(), tag))
+            .collect()));
+        manager.set_translations(HashMap::from([
+            ("smile".to_string(), vec!["微笑み".to_string()]),
+            ("open_mouth".to_string(), vec!["Open Mouth".to_string()]),
+        ]));
+
+        assert_eq!(manager.canonical_for_translation("猫耳").as_deref(), Some("cat_ears"));
+        assert_eq!(manager.canonical_for_translation("nekomimi").as_deref(), Some("cat_ears"));
+        assert_eq!(manager.canonical_for_translation("微笑み").as_deref(), Some("smile"));
+        assert_eq!(manager.canonical_for_translation("笑顔").as_deref(), Some("smile"));
+        assert_eq!(manager.canonical_for_translation("(open mouth:1.2)").as_deref(), Some("open_mouth"));
+        assert_eq!(manager.canonical_for_translation("猫"), None);
+        assert_eq!(manager.canonical_for_translation("cat_ears"), None);
+
+        manager.set_translations(HashMap::new());
+        assert_eq!(manager.canonical_for_translation("微笑み"), None);
+        assert_eq!(manager.canonical_for_translation("猫耳").as_deref(), Some("cat_ears"));
+    }
+
+    // Suggestions run on every keystroke, so each lookup has to stay well under a millisecond.
+    // Timing depends on the machine; run with `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn suggestions_stay_under_half_a_millisecond() {
+        const ITERATIONS: u32 = 200;
+        const BUDGET: std::time::Duration = std::time::Duration::from_micros(500);
+        let mut manager = bundled_tags().expect("the bundled tag CSV");
+        for (query, _) in QUERIES {
+            let start = std::time::Instant::now();
+            for _ in 0..ITERATIONS {
+                manager.update_suggestions(query);
+            }
+            let per_query = start.elapsed() / ITERATIONS;
+            assert!(per_query < BUDGET, "'{}' took {:?} per lookup", query, per_query);
+        }
+    }
+}
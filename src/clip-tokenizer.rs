@@ -0,0 +1,224 @@
+// CLIP's byte-level BPE, used to count how many tokens a caption costs. Counting only needs the
+// merge list: `merges.txt` from a Hugging Face CLIP checkpoint (picking the `vocab.json` next to it
+// works too) or OpenAI's `bpe_simple_vocab_16e6.txt`, optionally gzipped.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use flate2::read::GzDecoder;
+use regex::Regex;
+
+// CLIP's 77-token context minus the start and end tokens
+pub const DEFAULT_TOKEN_LIMIT: usize = 75;
+// CLIP uses the first 49152 - 256 byte tokens - 2 special tokens merges of OpenAI's list
+const MERGE_COUNT: usize = 49152 - 256 - 2;
+const PATTERN: &str = r"(?i)<\|startoftext\|>|<\|endoftext\|>|'s|'t|'re|'ve|'m|'ll|'d|\p{L}+|\p{N}|[^\s\p{L}\p{N}]+";
+
+pub struct ClipTokenizer {
+    ranks: HashMap<(String, String), u32>,
+    byte_chars: [char; 256],
+    pattern: Regex,
+    // Word -> token count
+    cache: Mutex<HashMap<String, usize>>,
+}
+
+impl ClipTokenizer {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = merges_path(path);
+        let mut contents = String::new();
+        if path.extension().is_some_and(|ext| ext == "gz") {
+            GzDecoder::new(fs::File::open(&path)?).read_to_string(&mut contents)?;
+        } else {
+            contents = fs::read_to_string(&path)?;
+        }
+
+        let tokenizer = Self::from_merges(&contents)?;
+        if tokenizer.ranks.is_empty() {
+            return Err(format!("No BPE merges found in {}", path.display()).into());
+        }
+        Ok(tokenizer)
+    }
+
+    fn from_merges(contents: &str) -> Result<Self, regex::Error> {
+        // Both formats start with a `#version` line; merges themselves may begin with `#`
+        let mut lines = contents.lines().peekable();
+        lines.next_if(|line| line.contains("#version"));
+        let ranks: HashMap<(String, String), u32> = lines
+            .filter_map(|line| line.split_once(' '))
+            .take(MERGE_COUNT)
+            .enumerate()
+            .map(|(rank, (a, b))| ((a.to_string(), b.to_string()), rank as u32))
+            .collect();
+
+        Ok(Self {
+            ranks,
+            byte_chars: bytes_to_unicode(),
+            pattern: Regex::new(PATTERN)?,
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn merge_count(&self) -> usize {
+        self.ranks.len()
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        let text = text.to_lowercase();
+        self.pattern.find_iter(&text).map(|word| self.word_tokens(word.as_str())).sum()
+    }
+
+    // Token count of the tag line `tags.join(", ")` up to and including each tag
+    pub fn tag_token_ends(&self, tags: &[String]) -> Vec<usize> {
+        let mut line = String::new();
+        let mut tag_ends = Vec::with_capacity(tags.len());
+        for (idx, tag) in tags.iter().enumerate() {
+            if idx > 0 {
+                line.push_str(", ");
+            }
+            line.push_str(&tag.to_lowercase());
+            tag_ends.push(line.len());
+        }
+
+        // Words are credited to the tag they start in; a separator comma belongs to the tag before it
+        let mut ends = vec![0; tags.len()];
+        let mut tag = 0;
+        let mut total = 0;
+        for word in self.pattern.find_iter(&line) {
+            while tag + 1 < tag_ends.len() && word.start() >= tag_ends[tag] && !word.as_str().starts_with(',') {
+                ends[tag] = total;
+                tag += 1;
+            }
+            total += self.word_tokens(word.as_str());
+        }
+        for end in &mut ends[tag.min(tags.len())..] {
+            *end = total;
+        }
+        ends
+    }
+
+    fn word_tokens(&self, word: &str) -> usize {
+        if let Some(&count) = self.cache.lock().unwrap().get(word) {
+            return count;
+        }
+        let count = self.bpe(word);
+        self.cache.lock().unwrap().insert(word.to_string(), count);
+        count
+    }
+
+    fn bpe(&self, word: &str) -> usize {
+        let mut parts: Vec<String> = word.bytes().map(|b| self.byte_chars[b as usize].to_string()).collect();
+        if let Some(last) = parts.last_mut() {
+            last.push_str("</w>");
+        }
+
+        while parts.len() > 1 {
+            // The lowest-ranked adjacent pair is merged everywhere it occurs
+            let best = parts.windows(2)
+                .filter_map(|pair| self.ranks.get(&(pair[0].clone(), pair[1].clone())).map(|&rank| (rank, pair)))
+                .min_by_key(|(rank, _)| *rank)
+                .map(|(_, pair)| (pair[0].clone(), pair[1].clone()));
+            let Some((first, second)) = best else {
+                break;
+            };
+
+            let mut merged = Vec::with_capacity(parts.len());
+            let mut idx = 0;
+            while idx < parts.len() {
+                if idx + 1 < parts.len() && parts[idx] == first && parts[idx + 1] == second {
+                    merged.push(format!("{}{}", first, second));
+                    idx += 2;
+                } else {
+                    merged.push(std::mem::take(&mut parts[idx]));
+                    idx += 1;
+                }
+            }
+            parts = merged;
+        }
+        parts.len()
+    }
+}
+
+fn merges_path(path: &Path) -> PathBuf {
+    if path.file_name().is_some_and(|name| name == "vocab.json") {
+        return path.with_file_name("merges.txt");
+    }
+    path.to_path_buf()
+}
+
+// GPT-2/CLIP byte to printable character mapping used by the merge list
+fn bytes_to_unicode() -> [char; 256] {
+    let printable = |b: u32| (0x21..=0x7e).contains(&b) || (0xa1..=0xac).contains(&b) || (0xae..=0xff).contains(&b);
+    let mut chars = ['\0'; 256];
+    let mut extra = 0;
+    for b in 0..256u32 {
+        let code = if printable(b) {
+            b
+        } else {
+            extra += 1;
+            255 + extra
+        };
+        chars[b as usize] = char::from_u32(code).unwrap_or('\u{fffd}');
+    }
+    chars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Enough merges to spell `hello` and `##` as single tokens
+    const MERGES: &str = "#version: 0.2\nh e\nl l\nhe ll\nhell o</w>\n# #</w>\n";
+
+    fn tokenizer() -> ClipTokenizer {
+        ClipTokenizer::from_merges(MERGES).unwrap()
+    }
+
+    #[test]
+    fn maps_bytes_like_gpt2() {
+        let chars = bytes_to_unicode();
+        assert_eq!(chars[b'!' as usize], '!');
+        assert_eq!(chars[0xe9], 'é');
+        // Unprintable bytes are moved past 255 in order
+        assert_eq!(chars[0], '\u{100}');
+        assert_eq!(chars[b' ' as usize], '\u{120}');
+        assert_eq!(chars[0xad], '\u{143}');
+
+        let mut unique = chars.to_vec();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 256);
+    }
+
+    #[test]
+    fn merges_by_rank() {
+        let tokenizer = tokenizer();
+        assert_eq!(tokenizer.merge_count(), 5);
+        // The version line is skipped, but a merge starting with `#` is not
+        assert_eq!(tokenizer.count("##"), 1);
+
+        assert_eq!(tokenizer.count("Hello"), 1);
+        assert_eq!(tokenizer.count("hell"), 3);
+        assert_eq!(tokenizer.count("hello hi"), 3);
+        assert_eq!(tokenizer.count("hello, hello"), 3);
+        assert_eq!(tokenizer.count(""), 0);
+    }
+
+    #[test]
+    fn credits_separators_to_the_tag_before() {
+        let tokenizer = tokenizer();
+        let tags = ["hello", "hi", "Hello world"].map(String::from);
+        // hello , | hi , | hello w o r l d
+        assert_eq!(tokenizer.tag_token_ends(&tags), [2, 5, 11]);
+        assert_eq!(tokenizer.tag_token_ends(&tags[..1]), [1]);
+        assert!(tokenizer.tag_token_ends(&[]).is_empty());
+        assert_eq!(*tokenizer.tag_token_ends(&tags).last().unwrap(), tokenizer.count(&tags.join(", ")));
+    }
+
+    #[test]
+    fn finds_merges_next_to_vocab() {
+        assert_eq!(merges_path(Path::new("clip/vocab.json")), Path::new("clip/merges.txt"));
+        assert_eq!(merges_path(Path::new("bpe.txt.gz")), Path::new("bpe.txt.gz"));
+    }
+}
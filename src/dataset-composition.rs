@@ -0,0 +1,152 @@
+// Dataset composition weighted by kohya's folder repeats: a `10_alice` folder is read 10 times per
+// epoch, so each of its images counts as 10 samples.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::tag_syntax::lookup_name;
+
+pub struct FolderStats {
+    pub path: PathBuf,
+    pub name: String,
+    pub repeats: u32,
+    pub images: usize,
+}
+
+impl FolderStats {
+    pub fn effective(&self) -> usize {
+        self.images * self.repeats as usize
+    }
+}
+
+pub struct TagStats {
+    pub name: String,
+    pub images: usize,
+    // Images weighted by their folder's repeats
+    pub effective: usize,
+}
+
+pub struct Composition {
+    pub folders: Vec<FolderStats>,
+    // Most effective samples first
+    pub tags: Vec<TagStats>,
+    pub total_effective: usize,
+}
+
+// `10_alice` -> (10, "alice")
+pub fn parse_repeats(folder: &Path) -> Option<(u32, String)> {
+    let name = folder.file_name()?.to_str()?;
+    let (repeats, concept) = name.split_once('_')?;
+    let repeats = repeats.parse().ok().filter(|&repeats| repeats > 0)?;
+    Some((repeats, concept.to_string()))
+}
+
+impl Composition {
+    // One entry per folder: its path, repeats and each image's tags
+    pub fn build(folders: Vec<(PathBuf, u32, Vec<&[String]>)>) -> Self {
+        let mut tags: HashMap<String, TagStats> = HashMap::new();
+        let mut stats = Vec::new();
+        for (path, repeats, images) in folders {
+            for image_tags in &images {
+                let mut names: Vec<String> = image_tags.iter().map(|tag| lookup_name(tag)).collect();
+                names.sort_unstable();
+                names.dedup();
+                for name in names {
+                    let entry = tags.entry(name.clone()).or_insert(TagStats { name, images: 0, effective: 0 });
+                    entry.images += 1;
+                    entry.effective += repeats as usize;
+                }
+            }
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            stats.push(FolderStats { path, name, repeats, images: images.len() });
+        }
+
+        let mut tags: Vec<TagStats> = tags.into_values().collect();
+        tags.sort_by(|a, b| b.effective.cmp(&a.effective).then_with(|| a.name.cmp(&b.name)));
+        let total_effective = stats.iter().map(FolderStats::effective).sum();
+        Self { folders: stats, tags, total_effective }
+    }
+
+    // Repeats per folder whose effective counts follow `weights` as closely as whole repeats
+    // allow, scaled so the folder needing the fewest repeats gets one
+    pub fn suggest_repeats(&self, weights: &[f32]) -> Vec<u32> {
+        let total_weight: f32 = weights.iter().sum();
+        let scale = self.folders.iter()
+            .zip(weights)
+            .filter(|(folder, &weight)| folder.images > 0 && weight > 0.0)
+            .map(|(folder, &weight)| folder.images as f32 / (weight / total_weight))
+            .fold(0.0, f32::max);
+
+        self.folders.iter()
+            .zip(weights)
+            .map(|(folder, &weight)| {
+                if folder.images == 0 || weight <= 0.0 {
+                    return folder.repeats;
+                }
+                let share = weight / total_weight;
+                ((share * scale / folder.images as f32).round() as u32).max(1)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(name: &str, repeats: u32, images: usize) -> FolderStats {
+        FolderStats { path: PathBuf::from(name), name: name.to_string(), repeats, images }
+    }
+
+    fn from_folders(folders: Vec<FolderStats>) -> Composition {
+        let total_effective = folders.iter().map(FolderStats::effective).sum();
+        Composition { folders, tags: Vec::new(), total_effective }
+    }
+
+    #[test]
+    fn parses_kohya_folder_names() {
+        assert_eq!(parse_repeats(Path::new("train/10_alice")), Some((10, "alice".to_string())));
+        assert_eq!(parse_repeats(Path::new("3_my_concept")), Some((3, "my_concept".to_string())));
+        assert_eq!(parse_repeats(Path::new("0_alice")), None);
+        assert_eq!(parse_repeats(Path::new("x_alice")), None);
+        assert_eq!(parse_repeats(Path::new("alice")), None);
+    }
+
+    #[test]
+    fn counts_tags_by_effective_samples() {
+        let alice = [vec!["alice".to_string(), "smile".to_string(), "smile".to_string()]];
+        let bob = [vec!["bob".to_string(), "(smile:1.1)".to_string()], vec!["bob".to_string()]];
+        let composition = Composition::build(vec![
+            (PathBuf::from("10_alice"), 10, alice.iter().map(Vec::as_slice).collect()),
+            (PathBuf::from("2_bob"), 2, bob.iter().map(Vec::as_slice).collect()),
+        ]);
+        assert_eq!(composition.total_effective, 14);
+        let tags: Vec<(&str, usize, usize)> = composition.tags.iter()
+            .map(|tag| (tag.name.as_str(), tag.images, tag.effective))
+            .collect();
+        assert_eq!(tags, [("smile", 2, 12), ("alice", 1, 10), ("bob", 2, 4)]);
+    }
+
+    #[test]
+    fn suggested_repeats_balance_weighted_folders() {
+        let composition = from_folders(vec![
+            folder("1_small", 1, 10),
+            folder("1_large", 1, 40),
+            folder("5_empty", 5, 0),
+            folder("3_ignored", 3, 20),
+        ]);
+
+        // The largest folder needs the fewest repeats and gets one; empty and zero-weight folders keep theirs
+        let repeats = composition.suggest_repeats(&[1.0, 1.0, 1.0, 0.0]);
+        assert_eq!(repeats, [4, 1, 5, 3]);
+
+        let repeats = composition.suggest_repeats(&[2.0, 1.0, 0.0, 0.0]);
+        assert_eq!(repeats, [8, 1, 5, 3]);
+
+        // Here the tiny folder with a tiny weight is the one needing the fewest repeats
+        let lopsided = from_folders(vec![folder("1_a", 1, 1000), folder("1_b", 1, 2)]);
+        assert_eq!(lopsided.suggest_repeats(&[1.0, 0.001]), [2, 1]);
+
+        assert_eq!(composition.suggest_repeats(&[0.0; 4]), [1, 1, 5, 3]);
+    }
+}
@@ -0,0 +1,434 @@
+// Dataset validation: caption files, image files and tag problems collected into one report that
+// can be exported as JSON or CSV, e.g. to fail a CI job.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use image::ImageReader;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::dataset_settings::{DatasetSettings, VariantStorage};
+use crate::tag_syntax::lookup_name;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintKind {
+    MissingCaption,
+    EmptyCaption,
+    UnreadableCaption,
+    OrphanCaption,
+    CorruptImage,
+    SmallResolution,
+    ExtremeAspectRatio,
+    DuplicateTag,
+    UnknownTag,
+}
+
+impl LintKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            LintKind::MissingCaption => "Missing captions",
+            LintKind::EmptyCaption => "Empty caption files",
+            LintKind::UnreadableCaption => "Unreadable caption files",
+            LintKind::OrphanCaption => "Captions without an image",
+            LintKind::CorruptImage => "Unreadable images",
+            LintKind::SmallResolution => "Small resolution",
+            LintKind::ExtremeAspectRatio => "Extreme aspect ratio",
+            LintKind::DuplicateTag => "Duplicate tags",
+            LintKind::UnknownTag => "Tags not in the database",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LintOptions {
+    // Images whose shorter side is below this are reported
+    pub min_side: u32,
+    // Longer side divided by shorter side
+    pub max_aspect_ratio: f32,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        Self {
+            min_side: 512,
+            max_aspect_ratio: 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LintIssue {
+    pub kind: LintKind,
+    pub path: PathBuf,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LintReport {
+    pub images: usize,
+    pub issues: Vec<LintIssue>,
+}
+
+// An image as the editor currently holds it
+pub struct LintImage<'a> {
+    pub path: &'a Path,
+    pub tags: &'a [String],
+}
+
+impl LintReport {
+    pub fn counts(&self) -> BTreeMap<LintKind, usize> {
+        let mut counts = BTreeMap::new();
+        for issue in &self.issues {
+            *counts.entry(issue.kind).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    // Decode failures found elsewhere, e.g. while caching thumbnails, for images the header check passed
+    pub fn add_decode_errors<'a>(&mut self, errors: impl IntoIterator<Item = (&'a PathBuf, &'a String)>) {
+        let reported: HashSet<PathBuf> = self.issues.iter()
+            .filter(|issue| issue.kind == LintKind::CorruptImage)
+            .map(|issue| issue.path.clone())
+            .collect();
+        for (path, error) in errors {
+            if !reported.contains(path) {
+                self.issues.push(LintIssue { kind: LintKind::CorruptImage, path: path.clone(), detail: error.clone() });
+            }
+        }
+        self.sort();
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn write_csv(&self, output: impl std::io::Write) -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = csv::Writer::from_writer(output);
+        writer.write_record(["kind", "path", "detail"])?;
+        for issue in &self.issues {
+            let kind = serde_json::to_value(issue.kind)?;
+            writer.write_record([
+                kind.as_str().unwrap_or_default(),
+                &issue.path.to_string_lossy(),
+                &issue.detail,
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn sort(&mut self) {
+        self.issues.sort_by(|a, b| a.kind.cmp(&b.kind).then_with(|| a.path.cmp(&b.path)));
+    }
+}
+
+// `is_known` is left out when no tag database is loaded. `decode` fully decodes every image
+// instead of only reading its header, which catches truncated files but is much slower.
+pub fn lint_dataset(
+    dir: &Path,
+    settings: &DatasetSettings,
+    options: &LintOptions,
+    images: &[LintImage],
+    is_known: Option<&dyn Fn(&str) -> bool>,
+    decode: bool,
+) -> LintReport {
+    let mut report = LintReport { images: images.len(), issues: Vec::new() };
+
+    let mut issues: Vec<LintIssue> = images.par_iter()
+        .flat_map_iter(|image| {
+            let mut issues = check_captions(image.path, settings);
+            issues.extend(check_image(image.path, options, decode));
+            issues
+        })
+        .collect();
+    report.issues.append(&mut issues);
+
+    for image in images {
+        report.issues.extend(check_tags(image, is_known));
+    }
+    report.issues.extend(find_orphan_captions(dir, settings, images));
+    report.sort();
+    report
+}
+
+// Every configured caption variant, whether it is kept in numbered sidecars or the JSON file
+fn check_captions(image_path: &Path, settings: &DatasetSettings) -> Vec<LintIssue> {
+    let sidecar_variants = match settings.variant_storage {
+        VariantStorage::NumberedSidecars => settings.caption_variants.len(),
+        VariantStorage::Json => 1,
+    };
+
+    let mut issues = Vec::new();
+    for (variant, name) in settings.caption_variants.iter().enumerate().take(sidecar_variants) {
+        let sidecars: Vec<PathBuf> = ["txt", "caption"].iter()
+            .map(|extension| DatasetSettings::sidecar_path(image_path, variant, extension))
+            .filter(|path| path.exists())
+            .collect();
+        if sidecars.is_empty() {
+            let detail = match variant {
+                0 => "no .txt or .caption file".to_string(),
+                _ => format!("no .txt or .caption file for the '{}' variant", name),
+            };
+            issues.push(issue(LintKind::MissingCaption, image_path, detail));
+            continue;
+        }
+        issues.extend(sidecars.iter().filter_map(|path| match fs::read_to_string(path) {
+            Ok(contents) if contents.trim().is_empty() => Some(issue(LintKind::EmptyCaption, path, "file is empty".to_string())),
+            Ok(_) => None,
+            Err(err) => Some(issue(LintKind::UnreadableCaption, path, err.to_string())),
+        }));
+    }
+
+    if settings.variant_storage == VariantStorage::Json && settings.caption_variants.len() > 1 {
+        issues.extend(check_variants_json(image_path, &settings.caption_variants[1..]));
+    }
+    issues
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct StoredVariant {
+    tags: Vec<String>,
+    caption: String,
+}
+
+fn check_variants_json(image_path: &Path, names: &[String]) -> Vec<LintIssue> {
+    let path = DatasetSettings::variants_json_path(image_path);
+    if !path.exists() {
+        return vec![issue(LintKind::MissingCaption, image_path, format!("no {} for the extra variants", path.file_name().unwrap_or_default().to_string_lossy()))];
+    }
+    let stored: HashMap<String, StoredVariant> = match fs::read_to_string(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|contents| serde_json::from_str(&contents).map_err(|err| err.to_string()))
+    {
+        Ok(stored) => stored,
+        Err(err) => return vec![issue(LintKind::UnreadableCaption, &path, err)],
+    };
+
+    names.iter()
+        .filter_map(|name| match stored.get(name) {
+            None => Some(issue(LintKind::MissingCaption, &path, format!("no '{}' variant", name))),
+            Some(variant) if variant.tags.is_empty() && variant.caption.trim().is_empty() => {
+                Some(issue(LintKind::EmptyCaption, &path, format!("the '{}' variant is empty", name)))
+            }
+            Some(_) => None,
+        })
+        .collect()
+}
+
+fn issue(kind: LintKind, path: &Path, detail: String) -> LintIssue {
+    LintIssue { kind, path: path.to_path_buf(), detail }
+}
+
+fn check_image(path: &Path, options: &LintOptions, decode: bool) -> Vec<LintIssue> {
+    let issue = |kind, detail: String| LintIssue { kind, path: path.to_path_buf(), detail };
+    let dimensions = ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(image::ImageError::IoError)
+        .and_then(|reader| if decode {
+            reader.decode().map(|img| (img.width(), img.height()))
+        } else {
+            reader.into_dimensions()
+        });
+
+    let (width, height) = match dimensions {
+        Ok(dimensions) => dimensions,
+        Err(err) => return vec![issue(LintKind::CorruptImage, err.to_string())],
+    };
+    let (short, long) = (width.min(height), width.max(height));
+
+    let mut issues = Vec::new();
+    if short < options.min_side {
+        issues.push(issue(LintKind::SmallResolution, format!("{}x{}", width, height)));
+    }
+    let ratio = long as f32 / short.max(1) as f32;
+    if ratio > options.max_aspect_ratio {
+        issues.push(issue(LintKind::ExtremeAspectRatio, format!("{}x{} ({:.2}:1)", width, height, ratio)));
+    }
+    issues
+}
+
+fn check_tags(image: &LintImage, is_known: Option<&dyn Fn(&str) -> bool>) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut order = Vec::new();
+    for tag in image.tags {
+        let name = lookup_name(tag);
+        let count = counts.entry(name.clone()).or_insert(0);
+        if *count == 0 {
+            order.push(name);
+        }
+        *count += 1;
+    }
+
+    let duplicates: Vec<String> = order.iter()
+        .filter(|name| counts[*name] > 1)
+        .map(|name| format!("{} (x{})", name, counts[name]))
+        .collect();
+    if !duplicates.is_empty() {
+        issues.push(LintIssue {
+            kind: LintKind::DuplicateTag,
+            path: image.path.to_path_buf(),
+            detail: duplicates.join(", "),
+        });
+    }
+
+    if let Some(is_known) = is_known {
+        let unknown: Vec<&str> = order.iter()
+            .filter(|name| !is_known(name))
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            issues.push(LintIssue {
+                kind: LintKind::UnknownTag,
+                path: image.path.to_path_buf(),
+                detail: unknown.join(", "),
+            });
+        }
+    }
+    issues
+}
+
+// `.txt` and `.caption` files in the dataset directory that belong to none of its images
+fn find_orphan_captions(dir: &Path, settings: &DatasetSettings, images: &[LintImage]) -> Vec<LintIssue> {
+    let owned: HashSet<PathBuf> = images.iter()
+        .flat_map(|image| settings.sidecar_paths(image.path))
+        .collect();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| matches!(path.extension().and_then(|e| e.to_str()), Some("txt" | "caption")))
+        .filter(|path| !owned.contains(path))
+        .map(|path| LintIssue { kind: LintKind::OrphanCaption, path, detail: "no matching image".to_string() })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory per test, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("dataset-lint-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn kinds(issues: &[LintIssue]) -> Vec<LintKind> {
+        issues.iter().map(|issue| issue.kind).collect()
+    }
+
+    #[test]
+    fn checks_default_sidecars() {
+        let dir = TempDir::new("default");
+        let settings = DatasetSettings::default();
+        let image = dir.0.join("a.png");
+
+        assert_eq!(kinds(&check_captions(&image, &settings)), [LintKind::MissingCaption]);
+
+        fs::write(dir.0.join("a.txt"), "  \n").unwrap();
+        assert_eq!(kinds(&check_captions(&image, &settings)), [LintKind::EmptyCaption]);
+
+        fs::write(dir.0.join("a.txt"), [0xff, 0xfe, 0x00]).unwrap();
+        assert_eq!(kinds(&check_captions(&image, &settings)), [LintKind::UnreadableCaption]);
+
+        fs::write(dir.0.join("a.txt"), "1girl, solo").unwrap();
+        fs::write(dir.0.join("a.caption"), "").unwrap();
+        let issues = check_captions(&image, &settings);
+        assert_eq!(kinds(&issues), [LintKind::EmptyCaption]);
+        assert_eq!(issues[0].path, dir.0.join("a.caption"));
+    }
+
+    #[test]
+    fn checks_numbered_variants() {
+        let dir = TempDir::new("numbered");
+        let settings = DatasetSettings {
+            caption_variants: vec!["default".to_string(), "short".to_string(), "long".to_string()],
+            ..DatasetSettings::default()
+        };
+        let image = dir.0.join("a.png");
+        fs::write(dir.0.join("a.txt"), "1girl").unwrap();
+        fs::write(dir.0.join("a.1.txt"), "").unwrap();
+
+        let issues = check_captions(&image, &settings);
+        assert_eq!(kinds(&issues), [LintKind::EmptyCaption, LintKind::MissingCaption]);
+        assert_eq!(issues[0].path, dir.0.join("a.1.txt"));
+        assert!(issues[1].detail.contains("'long'"));
+    }
+
+    #[test]
+    fn checks_json_variants() {
+        let dir = TempDir::new("json");
+        let settings = DatasetSettings {
+            caption_variants: vec!["default".to_string(), "short".to_string(), "long".to_string()],
+            variant_storage: VariantStorage::Json,
+            ..DatasetSettings::default()
+        };
+        let image = dir.0.join("a.png");
+        fs::write(dir.0.join("a.txt"), "1girl").unwrap();
+        assert_eq!(kinds(&check_captions(&image, &settings)), [LintKind::MissingCaption]);
+
+        let json = DatasetSettings::variants_json_path(&image);
+        fs::write(&json, "{not json").unwrap();
+        assert_eq!(kinds(&check_captions(&image, &settings)), [LintKind::UnreadableCaption]);
+
+        fs::write(&json, r#"{"short": {"tags": [], "caption": " "}}"#).unwrap();
+        let issues = check_captions(&image, &settings);
+        assert_eq!(kinds(&issues), [LintKind::EmptyCaption, LintKind::MissingCaption]);
+        assert!(issues[1].detail.contains("'long'"));
+
+        fs::write(&json, r#"{"short": {"tags": ["solo"]}, "long": {"caption": "A girl."}}"#).unwrap();
+        assert!(check_captions(&image, &settings).is_empty());
+    }
+
+    #[test]
+    fn counts_a_dataset() {
+        let dir = TempDir::new("dataset");
+        let settings = DatasetSettings::default();
+        let (large, small) = (dir.0.join("a.png"), dir.0.join("b.png"));
+        image::RgbImage::new(600, 600).save(&large).unwrap();
+        image::RgbImage::new(100, 100).save(&small).unwrap();
+        fs::write(dir.0.join("a.txt"), "solo, solo").unwrap();
+        fs::write(dir.0.join("b.txt"), "").unwrap();
+        fs::write(dir.0.join("c.txt"), "orphan").unwrap();
+
+        let tags = (vec!["solo".to_string(), "solo".to_string()], Vec::new());
+        let images = [
+            LintImage { path: &large, tags: &tags.0 },
+            LintImage { path: &small, tags: &tags.1 },
+        ];
+        let report = lint_dataset(&dir.0, &settings, &LintOptions::default(), &images, None, false);
+
+        assert_eq!(report.images, 2);
+        let counts: Vec<(LintKind, usize)> = report.counts().into_iter().collect();
+        assert_eq!(counts, [
+            (LintKind::EmptyCaption, 1),
+            (LintKind::OrphanCaption, 1),
+            (LintKind::SmallResolution, 1),
+            (LintKind::DuplicateTag, 1),
+        ]);
+    }
+}
@@ -0,0 +1,272 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::clip_tokenizer::DEFAULT_TOKEN_LIMIT;
+use crate::dataset_lint::LintOptions;
+use crate::tag_policy::TagPolicy;
+use crate::tag_syntax::TagStyle;
+
+// Stored inside the dataset directory so settings travel with the dataset
+const SETTINGS_FILE_NAME: &str = ".dataset-editor.json";
+// The dataset's custom tag vocabulary, in the same CSV layout as the tag databases
+const CUSTOM_TAGS_FILE_NAME: &str = "custom-tags.csv";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CaptionFormat {
+    // Tags in `.txt`, prose in `.caption`
+    #[default]
+    SeparateFiles,
+    // Tag line first, prose on the following lines of the `.txt`
+    TagsThenCaption,
+    // Prose first, tag line as the last line of the `.txt`
+    CaptionThenTags,
+}
+
+impl CaptionFormat {
+    pub const ALL: [CaptionFormat; 3] = [
+        CaptionFormat::SeparateFiles,
+        CaptionFormat::TagsThenCaption,
+        CaptionFormat::CaptionThenTags,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CaptionFormat::SeparateFiles => "Separate files (.txt + .caption)",
+            CaptionFormat::TagsThenCaption => "Tags, then caption (.txt)",
+            CaptionFormat::CaptionThenTags => "Caption, then tags (.txt)",
+        }
+    }
+
+    // Splits the contents of a `.txt` sidecar into (tag line, caption). Only the final line break is
+    // dropped before splitting, so an empty tag line keeps its place next to the caption.
+    pub fn split<'a>(&self, content: &'a str) -> (&'a str, &'a str) {
+        let content = content.strip_suffix('\n')
+            .map(|content| content.strip_suffix('\r').unwrap_or(content))
+            .unwrap_or(content);
+        match self {
+            CaptionFormat::SeparateFiles => (content.trim(), ""),
+            CaptionFormat::TagsThenCaption => match content.split_once('\n') {
+                Some((tags, caption)) => (tags.trim(), caption.trim()),
+                None => (content.trim(), ""),
+            },
+            CaptionFormat::CaptionThenTags => match content.rsplit_once('\n') {
+                Some((caption, tags)) => (tags.trim(), caption.trim()),
+                None => (content.trim(), ""),
+            },
+        }
+    }
+
+    // Builds the `.txt` contents; the caption is only embedded for the combined formats. The tag
+    // line is written even when empty, so `split` finds the caption on its own line.
+    pub fn compose(&self, tags: &[String], caption: &str) -> String {
+        let tag_line = tags.join(", ");
+        let caption = caption.trim();
+        if caption.is_empty() {
+            return tag_line;
+        }
+
+        match self {
+            CaptionFormat::SeparateFiles => tag_line,
+            CaptionFormat::TagsThenCaption => format!("{}\n{}", tag_line, caption),
+            CaptionFormat::CaptionThenTags => format!("{}\n{}\n", caption, tag_line),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum VariantStorage {
+    // `image.1.txt`, `image.2.txt`, ... next to the default `image.txt`
+    #[default]
+    NumberedSidecars,
+    // All extra variants in a single `image.captions.json`
+    Json,
+}
+
+impl VariantStorage {
+    pub const ALL: [VariantStorage; 2] = [VariantStorage::NumberedSidecars, VariantStorage::Json];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            VariantStorage::NumberedSidecars => "Numbered sidecars",
+            VariantStorage::Json => "JSON file",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatasetSettings {
+    pub caption_format: CaptionFormat,
+    // The first variant is always stored in the plain `.txt`/`.caption` sidecars
+    pub caption_variants: Vec<String>,
+    pub variant_storage: VariantStorage,
+    // Output style enforced when tags are added and saved
+    pub tag_style: TagStyle,
+    // Mirrors kohya's `keep_tokens`, `shuffle_caption` and dropout options for previews
+    pub keep_tokens: usize,
+    pub shuffle_caption: bool,
+    pub caption_dropout_rate: f32,
+    pub tag_dropout_rate: f32,
+    // Tokens the trainer reads from a caption, kohya's `max_token_length`
+    pub token_limit: usize,
+    // Files of the tag sources switched off for this dataset
+    pub disabled_tag_sources: Vec<PathBuf>,
+    // Tag rule file last used with this dataset
+    pub rules_file: Option<PathBuf>,
+    pub tag_policy: TagPolicy,
+    // Activation tag last applied to every image; the policy requires it
+    pub activation_tag: Option<String>,
+    // Thresholds for the dataset validation report
+    pub lint: LintOptions,
+}
+
+impl Default for DatasetSettings {
+    fn default() -> Self {
+        Self {
+            caption_format: CaptionFormat::default(),
+            caption_variants: vec!["default".to_string()],
+            variant_storage: VariantStorage::default(),
+            tag_style: TagStyle::default(),
+            keep_tokens: 0,
+            shuffle_caption: true,
+            caption_dropout_rate: 0.0,
+            tag_dropout_rate: 0.0,
+            token_limit: DEFAULT_TOKEN_LIMIT,
+            disabled_tag_sources: Vec::new(),
+            rules_file: None,
+            tag_policy: TagPolicy::default(),
+            activation_tag: None,
+            lint: LintOptions::default(),
+        }
+    }
+}
+
+impl DatasetSettings {
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(SETTINGS_FILE_NAME);
+        let mut settings = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+                eprintln!("Ignoring invalid dataset settings {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+
+        if settings.caption_variants.is_empty() {
+            settings.caption_variants.push("default".to_string());
+        }
+        settings
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(dir.join(SETTINGS_FILE_NAME), contents)?;
+        Ok(())
+    }
+
+    pub fn custom_tags_path(dir: &Path) -> PathBuf {
+        dir.join(CUSTOM_TAGS_FILE_NAME)
+    }
+
+    pub fn sidecar_path(image_path: &Path, variant: usize, extension: &str) -> PathBuf {
+        if variant == 0 {
+            image_path.with_extension(extension)
+        } else {
+            image_path.with_extension(format!("{}.{}", variant, extension))
+        }
+    }
+
+    pub fn variants_json_path(image_path: &Path) -> PathBuf {
+        image_path.with_extension("captions.json")
+    }
+
+    // Every caption file belonging to an image under the current variant layout
+    pub fn sidecar_paths(&self, image_path: &Path) -> Vec<PathBuf> {
+        let sidecar_variants = match self.variant_storage {
+            VariantStorage::NumberedSidecars => self.caption_variants.len(),
+            VariantStorage::Json => 1,
+        };
+
+        let mut paths = Vec::new();
+        for variant in 0..sidecar_variants {
+            paths.push(Self::sidecar_path(image_path, variant, "txt"));
+            paths.push(Self::sidecar_path(image_path, variant, "caption"));
+        }
+        if self.variant_storage == VariantStorage::Json {
+            paths.push(Self::variants_json_path(image_path));
+        }
+        paths
+    }
+
+    // What the trainer does to tags past `keep_tokens`, or None when they stay as written
+    pub fn unkept_tag_fate(&self) -> Option<&'static str> {
+        match (self.shuffle_caption, self.tag_dropout_rate > 0.0) {
+            (true, true) => Some("shuffled or dropped"),
+            (true, false) => Some("shuffled"),
+            (false, true) => Some("dropped"),
+            (false, false) => None,
+        }
+    }
+
+    // One caption as the trainer would see it in a random step; empty when dropped out
+    pub fn simulate_caption(&self, tags: &[String], rng: &mut impl Rng) -> String {
+        if rng.gen::<f32>() < self.caption_dropout_rate {
+            return String::new();
+        }
+
+        let keep = self.keep_tokens.min(tags.len());
+        let (fixed, rest) = tags.split_at(keep);
+        let mut rest: Vec<&String> = rest.iter()
+            .filter(|_| rng.gen::<f32>() >= self.tag_dropout_rate)
+            .collect();
+        if self.shuffle_caption {
+            rest.shuffle(rng);
+        }
+
+        fixed.iter().chain(rest).map(String::as_str).collect::<Vec<_>>().join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combined_formats_round_trip() {
+        let tags = vec!["1girl".to_string(), "solo".to_string()];
+        let cases: [(&[String], &str); 4] = [
+            (&tags, ""),
+            (&[], "A girl standing alone."),
+            (&tags, "A girl standing alone.\nIn a field."),
+            (&[], ""),
+        ];
+        for format in [CaptionFormat::TagsThenCaption, CaptionFormat::CaptionThenTags] {
+            for (tags, caption) in cases {
+                let content = format.compose(tags, caption);
+                assert_eq!(format.split(&content), (tags.join(", ").as_str(), caption), "{:?}", content);
+            }
+        }
+    }
+
+    #[test]
+    fn separate_files_keep_only_tags() {
+        let format = CaptionFormat::SeparateFiles;
+        let tags = vec!["1girl".to_string(), "solo".to_string()];
+        assert_eq!(format.split(&format.compose(&tags, "")), ("1girl, solo", ""));
+        assert_eq!(format.split(&format.compose(&tags, "A girl.")), ("1girl, solo", ""));
+        assert_eq!(format.split(&format.compose(&[], "A girl.")), ("", ""));
+        assert_eq!(format.split(&format.compose(&[], "")), ("", ""));
+    }
+
+    #[test]
+    fn splits_files_from_other_tools() {
+        // Trailing line breaks and Windows line endings
+        assert_eq!(CaptionFormat::TagsThenCaption.split("1girl\r\nA girl.\r\n"), ("1girl", "A girl."));
+        assert_eq!(CaptionFormat::CaptionThenTags.split("A girl.\n1girl\n"), ("1girl", "A girl."));
+        assert_eq!(CaptionFormat::CaptionThenTags.split("1girl"), ("1girl", ""));
+        assert_eq!(CaptionFormat::TagsThenCaption.split("1girl, solo\n"), ("1girl, solo", ""));
+    }
+}
@@ -0,0 +1,217 @@
+// External captioners run as a subprocess speaking JSON lines: the editor writes one request per
+// image to its stdin and waits for one response line on its stdout before sending the next.
+// Closing stdin tells the captioner to exit. The protocol is described in the README.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+use crate::auto_tagger::MergeMode;
+
+// How long the captioner gets to exit after stdin is closed
+const EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+// How often a wait checks whether the run was cancelled
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptionerSettings {
+    // Run through the system shell, e.g. `python caption.py --model large`
+    pub command: String,
+    pub merge_mode: MergeMode,
+    // Seconds to wait for each response; the first one includes loading the model
+    pub timeout_secs: u64,
+}
+
+impl Default for CaptionerSettings {
+    fn default() -> Self {
+        Self {
+            command: String::new(),
+            merge_mode: MergeMode::default(),
+            timeout_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CaptionRequest {
+    pub id: usize,
+    pub image: PathBuf,
+    pub tags: Vec<String>,
+    pub caption: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CaptionResponse {
+    // Echo of the request id; checked when present
+    pub id: Option<usize>,
+    pub tags: Option<Vec<String>>,
+    // Optional per-tag confidences in 0..=1
+    pub confidences: HashMap<String, f32>,
+    pub caption: Option<String>,
+    // Set when the captioner could not handle this image
+    pub error: Option<String>,
+}
+
+pub struct CaptionerProcess {
+    child: Child,
+    stdin: ChildStdin,
+    // stdout lines, read on their own thread so waits can time out
+    lines: Receiver<std::io::Result<String>>,
+    timeout: Duration,
+    cancel: Arc<AtomicBool>,
+}
+
+impl CaptionerProcess {
+    // Waits give up after `timeout` per response, or as soon as `cancel` is set
+    pub fn spawn(
+        command: &str,
+        working_dir: &Path,
+        timeout: Duration,
+        cancel: Arc<AtomicBool>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // stderr is inherited so the captioner's own logging ends up next to the editor's
+        let mut child = shell_command(command)
+            .current_dir(working_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or("captioner has no stdin")?;
+        let stdout = child.stdout.take().ok_or("captioner has no stdout")?;
+
+        let (tx, lines) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self { child, stdin, lines, timeout, cancel })
+    }
+
+    // Sends one request and waits for its response. Lines that are not JSON objects are treated
+    // as stray logging and passed on to stderr.
+    pub fn caption(&mut self, request: &CaptionRequest) -> Result<CaptionResponse, Box<dyn std::error::Error>> {
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes())?;
+        self.stdin.flush()?;
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let line = match self.lines.recv_timeout(POLL_INTERVAL) {
+                Ok(line) => line?,
+                Err(RecvTimeoutError::Disconnected) => return Err("captioner exited before answering".into()),
+                Err(RecvTimeoutError::Timeout) if self.cancel.load(Ordering::Relaxed) => return Err("cancelled".into()),
+                Err(RecvTimeoutError::Timeout) if Instant::now() >= deadline => {
+                    return Err(format!("no response within {} s", self.timeout.as_secs()).into());
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+            };
+            let line = line.trim();
+            if !line.starts_with('{') {
+                if !line.is_empty() {
+                    eprintln!("captioner: {}", line);
+                }
+                continue;
+            }
+
+            let response: CaptionResponse = serde_json::from_str(line)
+                .map_err(|err| format!("invalid response `{}`: {}", line, err))?;
+            if response.id.is_some_and(|id| id != request.id) {
+                return Err(format!("expected the response to request {}, got {:?}", request.id, response.id).into());
+            }
+            return Ok(response);
+        }
+    }
+
+    // Closes stdin and waits for the captioner to exit on its own, killing it when it does not
+    pub fn finish(self) -> Result<(), Box<dyn std::error::Error>> {
+        let Self { mut child, stdin, .. } = self;
+        drop(stdin);
+        let deadline = Instant::now() + EXIT_TIMEOUT;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("captioner did not exit within {} s and was killed", EXIT_TIMEOUT.as_secs()).into());
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        if !status.success() {
+            return Err(format!("captioner exited with {}", status).into());
+        }
+        Ok(())
+    }
+
+    pub fn kill(mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn spawn(command: &str, timeout: Duration) -> CaptionerProcess {
+        CaptionerProcess::spawn(command, &std::env::temp_dir(), timeout, Arc::new(AtomicBool::new(false))).unwrap()
+    }
+
+    fn request(id: usize) -> CaptionRequest {
+        CaptionRequest { id, image: PathBuf::from("a.png"), tags: Vec::new(), caption: String::new() }
+    }
+
+    #[test]
+    fn answers_each_request() {
+        let mut process = spawn(r#"while read line; do echo loading; echo '{"caption": "a cat"}'; done"#, Duration::from_secs(10));
+        for id in 0..2 {
+            let response = process.caption(&request(id)).unwrap();
+            assert_eq!(response.caption.as_deref(), Some("a cat"));
+        }
+        process.finish().unwrap();
+    }
+
+    #[test]
+    fn gives_up_on_a_silent_captioner() {
+        let mut process = spawn("read line; sleep 30", Duration::from_millis(300));
+        let started = Instant::now();
+        let err = process.caption(&request(0)).unwrap_err();
+        assert!(err.to_string().starts_with("no response"), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        process.kill();
+    }
+
+    #[test]
+    fn stops_waiting_when_cancelled() {
+        let cancel = Arc::new(AtomicBool::new(true));
+        let mut process = CaptionerProcess::spawn("sleep 30", &std::env::temp_dir(), Duration::from_secs(30), cancel).unwrap();
+        assert_eq!(process.caption(&request(0)).unwrap_err().to_string(), "cancelled");
+        process.kill();
+    }
+}
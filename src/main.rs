@@ -1,23 +1,114 @@
 use eframe::egui;
 use image::ImageReader;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
-use rfd;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use rayon::prelude::*;
 #[path = "booru-tag-manager.rs"]
 mod booru_tag_manager;
+#[path = "dataset-settings.rs"]
+mod dataset_settings;
+#[path = "tag-syntax.rs"]
+mod tag_syntax;
+#[path = "tag-index.rs"]
+mod tag_index;
+#[path = "app-settings.rs"]
+mod app_settings;
+#[path = "tag-csv.rs"]
+mod tag_csv;
+#[path = "tag-wiki.rs"]
+mod tag_wiki;
+#[path = "tag-cooccurrence.rs"]
+mod tag_cooccurrence;
+#[path = "tag-rules.rs"]
+mod tag_rules;
+#[path = "tag-policy.rs"]
+mod tag_policy;
+#[path = "dataset-lint.rs"]
+mod dataset_lint;
+#[path = "clip-tokenizer.rs"]
+mod clip_tokenizer;
+#[path = "dataset-composition.rs"]
+mod dataset_composition;
+#[path = "auto-tagger.rs"]
+mod auto_tagger;
+#[path = "tag-confidence.rs"]
+mod tag_confidence;
+#[path = "external-captioner.rs"]
+mod external_captioner;
+#[path = "tag-script.rs"]
+mod tag_script;
 
-use booru_tag_manager::BooruTagManager;
+use app_settings::AppSettings;
+use auto_tagger::{MergeMode, Prediction, TaggerModel};
+use clip_tokenizer::ClipTokenizer;
+use booru_tag_manager::{BooruTag, BooruTagManager, TagCategory, TagSource};
+use tag_cooccurrence::{CooccurrenceMatrix, RelatedTag};
+use tag_csv::CategoryScheme;
+use tag_policy::PolicyViolation;
+use tag_rules::{RuleChange, RuleSet};
+use tag_confidence::Confidences;
+use external_captioner::{CaptionRequest, CaptionResponse, CaptionerProcess};
+use tag_script::{ScriptImage, ScriptRun};
+use dataset_composition::Composition;
+use dataset_lint::{LintImage, LintReport};
+use dataset_settings::{CaptionFormat, DatasetSettings, VariantStorage};
+use tag_syntax::{ParenStyle, WeightedTag, WordSeparator};
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct CaptionVariant {
+    tags: Vec<String>,
+    caption: String,
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 struct ImageData {
     path: PathBuf,
     tags: Vec<String>,
+    caption: String,
+    // One slot per dataset variant; the active variant lives in `tags`/`caption` instead
+    variants: Vec<CaptionVariant>,
     hash: Option<Vec<u8>>,
+    // Auto-tagger confidences of tags nobody has reviewed yet, by lookup name
+    confidences: Confidences,
+}
+
+impl ImageData {
+    fn switch_variant(&mut self, from: usize, to: usize) {
+        if from == to {
+            return;
+        }
+        self.variants[from] = CaptionVariant {
+            tags: std::mem::take(&mut self.tags),
+            caption: std::mem::take(&mut self.caption),
+        };
+        let next = std::mem::take(&mut self.variants[to]);
+        self.tags = next.tags;
+        self.caption = next.caption;
+    }
+
+    fn variant(&self, idx: usize, active: usize) -> (&[String], &str) {
+        if idx == active {
+            (&self.tags, &self.caption)
+        } else {
+            (&self.variants[idx].tags, &self.variants[idx].caption)
+        }
+    }
+
+    fn variant_tag_lists_mut(&mut self, active: usize, all_variants: bool) -> Vec<&mut Vec<String>> {
+        let mut lists = vec![&mut self.tags];
+        if all_variants {
+            lists.extend(self.variants.iter_mut()
+                .enumerate()
+                .filter(|(idx, _)| *idx != active)
+                .map(|(_, variant)| &mut variant.tags));
+        }
+        lists
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -31,6 +122,33 @@ enum SortType {
 enum TagAction {
     Add(String),
     Remove(String),
+    Rename { idx: usize, text: String },
+    Reweight { idx: usize, text: String },
+    Move { from: usize, to: usize },
+    RemoveFromAll(String),
+    Search(String),
+    AddToVocabulary(String),
+}
+
+// Categories offered for dataset-specific tags
+const CUSTOM_TAG_CATEGORIES: [TagCategory; 5] = [
+    TagCategory::Custom,
+    TagCategory::Artist,
+    TagCategory::Copyright,
+    TagCategory::Character,
+    TagCategory::Meta,
+];
+
+struct CustomTagForm {
+    name: String,
+    category: TagCategory,
+    aliases: String,
+}
+
+impl Default for CustomTagForm {
+    fn default() -> Self {
+        Self { name: String::new(), category: TagCategory::Custom, aliases: String::new() }
+    }
 }
 
 #[derive(Clone)]
@@ -48,53 +166,194 @@ enum CacheMessage {
     },
 }
 
-#[derive(Clone)]
-enum CacheProgress {
-    Started { idx: usize },
-    Loading { idx: usize },
-    Resizing { idx: usize },
-    Completed { idx: usize },
-    Error { idx: usize },
+enum TagDatabaseMessage {
+    Progress(f32),
+    Loaded { source: Box<TagSource> },
+    Error { path: PathBuf, error: String },
+    Finished,
+}
+
+// Outcome of running a rule file over the dataset
+struct RuleReport {
+    dry_run: bool,
+    images: Vec<(PathBuf, Vec<RuleChange>)>,
+}
+
+enum AutoTagMessage {
+    Tagged { path: PathBuf, predictions: Vec<Prediction> },
+    Failed { path: PathBuf, error: String },
+    Finished,
+}
+
+enum ScriptMessage {
+    // Images done so far
+    Progress(usize),
+    Finished(ScriptRun),
+}
+
+enum CaptionerMessage {
+    Captioned { path: PathBuf, response: CaptionResponse },
+    Failed { path: PathBuf, error: String },
+    // Carries the error that ended the run early, if any
+    Finished(Option<String>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum AutoTagScope {
+    CurrentImage,
+    SearchResults,
+    AllImages,
 }
 
+// A tag offered by the auto tagger or an external captioner
 #[derive(Clone)]
-enum DuplicateMessage {
-    Found { duplicates: HashMap<PathBuf, Vec<PathBuf>> },
+struct ProposedTag {
+    name: String,
+    confidence: Option<f32>,
+}
+
+// Related tag suggestions for the current image
+struct RelatedTags {
+    // Image index, tags revision and tag database revision they were computed at
+    revision: (usize, u64, u64),
+    dataset: Vec<RelatedTag>,
+    booru: Vec<RelatedTag>,
+}
+
+// Dataset-wide tag counts for the statistics view
+struct TagStatistics {
+    // Tags revision and tag database revision the counts were taken at
+    revision: (u64, u64),
+    // (category, distinct tags, uses) in display order
+    by_category: Vec<(Option<TagCategory>, usize, usize)>,
+    // Most used tags first
+    top: Vec<(String, usize, Option<TagCategory>)>,
+}
+
+// Images holding tags below the review threshold, least certain first
+struct ReviewQueue {
+    images: Vec<PathBuf>,
+    position: usize,
+}
+
+// Tags of the images in a composition folder other than the open one
+struct FolderTags {
+    path: PathBuf,
+    repeats: u32,
+    images: Vec<Vec<String>>,
+}
+
+enum WikiMessage {
+    Loaded { path: PathBuf, descriptions: HashMap<String, String> },
+    Error { path: PathBuf, error: String },
 }
 
+enum TagSourceAction {
+    Toggle(usize, bool),
+    MoveUp(usize),
+    MoveDown(usize),
+    Remove(usize),
+    // Index into the unavailable sources
+    Retry(usize),
+    Forget(usize),
+}
+
+const BUNDLED_TAG_CSV: &str = "danbooru-12-10-24-underscore.csv";
+
 struct ImageTagger {
     current_dir: Option<PathBuf>,
+    dataset_settings: DatasetSettings,
+    active_variant: usize,
+    bulk_all_variants: bool,
+    new_variant_name: String,
+    style_report: Option<Vec<(String, String, usize)>>,
+    // Name of the tag source whose load report is open
+    tag_source_report: Option<String>,
+    simulated_captions: Vec<String>,
     images: Vec<ImageData>,
     current_image_idx: usize,
-    new_tag: String,
     search_tag: String,
+    // Chip being renamed in the tag view and its edited text
+    renaming_tag: Option<(usize, String)>,
+    // The dataset's own vocabulary, saved to `custom-tags.csv`
+    custom_tags: Vec<BooruTag>,
+    custom_tag_form: CustomTagForm,
+    // Rebuilt on load, save and refresh rather than on every edit
+    dataset_cooccurrence: CooccurrenceMatrix,
+    booru_cooccurrence: CooccurrenceMatrix,
+    // Cleared when either co-occurrence matrix changes
+    related_tags: Option<RelatedTags>,
+    rule_report: Option<RuleReport>,
+    // Images breaking the dataset's tag policy, by index
+    policy_report: Option<Vec<(usize, PolicyViolation)>>,
+    // Decode errors from background caching, reported by dataset validation
+    image_errors: HashMap<PathBuf, String>,
+    lint_report: Option<LintReport>,
+    clip_tokenizer: Option<ClipTokenizer>,
+    // (image index, tags revision) and the running token count after each of its tags
+    tag_token_ends: Option<((usize, u64), Vec<usize>)>,
+    // (image index, tag line tokens), longest first
+    token_report: Option<Vec<(usize, usize)>>,
+    token_report_show_all: bool,
+    composition: Option<Composition>,
+    // The other composition folders of the open directory, read in the background
+    composition_receiver: Option<std::sync::mpsc::Receiver<(PathBuf, Vec<FolderTags>)>>,
+    auto_tagger: Option<Arc<TaggerModel>>,
+    // The model file and its load result while a tagger model loads
+    tagger_load_receiver: Option<std::sync::mpsc::Receiver<(PathBuf, Result<TaggerModel, String>)>>,
+    auto_tag_receiver: Option<std::sync::mpsc::Receiver<AutoTagMessage>>,
+    // (images done, images failed, images queued) while a tagging run is in progress
+    auto_tag_progress: Option<(usize, usize, usize)>,
+    auto_tag_cancel: Arc<AtomicBool>,
+    auto_tag_scope: AutoTagScope,
+    // Predictions waiting for review when merging is suggest-only
+    auto_tag_suggestions: HashMap<PathBuf, Vec<ProposedTag>>,
+    caption_suggestions: HashMap<PathBuf, String>,
+    captioner_receiver: Option<std::sync::mpsc::Receiver<CaptionerMessage>>,
+    // (images done, images failed, images queued) while a captioner runs
+    captioner_progress: Option<(usize, usize, usize)>,
+    captioner_cancel: Arc<AtomicBool>,
+    captioner_scope: AutoTagScope,
+    review_queue: Option<ReviewQueue>,
+    script_console_open: bool,
+    script_source: String,
+    // Where the console's script was last opened from or saved to
+    script_path: Option<PathBuf>,
+    script_scope: AutoTagScope,
+    // Changes of the last script run, waiting to be applied
+    script_preview: Option<ScriptRun>,
+    script_receiver: Option<std::sync::mpsc::Receiver<ScriptMessage>>,
+    // (images done, images queued) while a script runs
+    script_progress: Option<(usize, usize)>,
+    script_cancel: Arc<AtomicBool>,
+    // Relative target share per composition folder
+    composition_weights: Vec<f32>,
+    // None shows tags of every category
+    composition_category: Option<TagCategory>,
     prepend_tags: bool,
-    sort_ascending: bool,
-    sort_by_frequency: bool,
     current_texture: Option<egui::TextureHandle>,
-    tag_to_remove: Option<String>,
     show_tag_stats: bool,
+    tag_statistics: Option<TagStatistics>,
     modified_files: HashMap<PathBuf, bool>,
+    // Bumped on every tag edit, sort and reload; dataset-wide reports cache against it
+    tags_revision: u64,
+    // (tags revision, activation tag, keep tokens) and how many images have the tag past the kept ones
+    activation_tag_check: Option<((u64, String, usize), usize)>,
     feedback_message: Option<String>,
     feedback_timer: Option<std::time::Instant>,
     feedback_duration: f32,
-    current_sorting: Option<fn(&String, &String) -> std::cmp::Ordering>,
     image_cache: HashMap<usize, egui::TextureHandle>,
-    last_logged_image_idx: Option<usize>,
-    state_changed: bool,
     decoded_receiver: Option<std::sync::mpsc::Receiver<CacheMessage>>,
     cache_progress: f32,
     total_images_to_cache: usize,
     cached_images_count: Arc<Mutex<usize>>,
     is_caching: bool,
     activation_tag: String,
-    progress_receiver: Option<std::sync::mpsc::Receiver<CacheProgress>>,
-    duplicate_images: HashMap<PathBuf, Vec<PathBuf>>,
-    images_to_delete: HashSet<PathBuf>,
-    feedback_tx: Option<std::sync::mpsc::Sender<DuplicateMessage>>,
-    duplicate_rx: Option<std::sync::mpsc::Receiver<DuplicateMessage>>,
     booru_manager: BooruTagManager,
-    show_tag_suggestions: bool,
+    app_settings: AppSettings,
+    tag_db_receiver: Option<std::sync::mpsc::Receiver<TagDatabaseMessage>>,
+    tag_db_progress: f32,
+    wiki_receiver: Option<std::sync::mpsc::Receiver<WikiMessage>>,
     current_sort_type: Option<SortType>,
     right_panel_width: Option<f32>,
 }
@@ -103,37 +362,77 @@ impl Default for ImageTagger {
     fn default() -> Self {
         Self {
             current_dir: None,
+            dataset_settings: DatasetSettings::default(),
+            active_variant: 0,
+            bulk_all_variants: false,
+            new_variant_name: String::new(),
+            style_report: None,
+            tag_source_report: None,
+            simulated_captions: Vec::new(),
             images: Vec::new(),
             current_image_idx: 0,
-            new_tag: String::new(),
             search_tag: String::new(),
+            renaming_tag: None,
+            custom_tags: Vec::new(),
+            custom_tag_form: CustomTagForm::default(),
+            dataset_cooccurrence: CooccurrenceMatrix::default(),
+            booru_cooccurrence: CooccurrenceMatrix::default(),
+            related_tags: None,
+            rule_report: None,
+            policy_report: None,
+            image_errors: HashMap::new(),
+            lint_report: None,
+            clip_tokenizer: None,
+            tag_token_ends: None,
+            token_report: None,
+            token_report_show_all: false,
+            composition: None,
+            composition_receiver: None,
+            auto_tagger: None,
+            tagger_load_receiver: None,
+            auto_tag_receiver: None,
+            auto_tag_progress: None,
+            auto_tag_cancel: Arc::new(AtomicBool::new(false)),
+            auto_tag_scope: AutoTagScope::CurrentImage,
+            auto_tag_suggestions: HashMap::new(),
+            caption_suggestions: HashMap::new(),
+            captioner_receiver: None,
+            captioner_progress: None,
+            captioner_cancel: Arc::new(AtomicBool::new(false)),
+            captioner_scope: AutoTagScope::CurrentImage,
+            review_queue: None,
+            script_console_open: false,
+            script_source: String::new(),
+            script_path: None,
+            script_scope: AutoTagScope::AllImages,
+            script_preview: None,
+            script_receiver: None,
+            script_progress: None,
+            script_cancel: Arc::new(AtomicBool::new(false)),
+            composition_weights: Vec::new(),
+            composition_category: Some(TagCategory::Character),
             prepend_tags: false,
-            sort_ascending: true,
-            sort_by_frequency: false,
             current_texture: None,
-            tag_to_remove: None,
             show_tag_stats: false,
+            tag_statistics: None,
             modified_files: HashMap::new(),
+            tags_revision: 0,
+            activation_tag_check: None,
             feedback_message: None,
             feedback_timer: None,
             feedback_duration: 5.0,
-            current_sorting: None,
             image_cache: HashMap::new(),
-            last_logged_image_idx: None,
-            state_changed: false,
             decoded_receiver: None,
             cache_progress: 0.0,
             total_images_to_cache: 0,
             cached_images_count: Arc::new(Mutex::new(0)),
             is_caching: false,
             activation_tag: String::new(),
-            progress_receiver: None,
-            duplicate_images: HashMap::new(),
-            images_to_delete: HashSet::new(),
-            feedback_tx: None,
-            duplicate_rx: None,
             booru_manager: BooruTagManager::new(),
-            show_tag_suggestions: false,
+            app_settings: AppSettings::default(),
+            tag_db_receiver: None,
+            tag_db_progress: 0.0,
+            wiki_receiver: None,
             current_sort_type: None,
             right_panel_width: Some(300.0),
         }
@@ -142,18 +441,1538 @@ impl Default for ImageTagger {
 
 
 impl ImageTagger {
-    fn new(_cc: &eframe::CreationContext<'_>) -> Self {
-        //let mut tagger = Self::default();
+    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        let mut tagger = Self {
+            app_settings: AppSettings::load(),
+            ..Self::default()
+        };
+
+        // Reload the user's tag sources in precedence order, falling back to the bundled one
+        let mut tag_csvs: Vec<PathBuf> = tagger.app_settings.tag_sources.iter()
+            .filter(|path| path.exists())
+            .cloned()
+            .collect();
+        if tag_csvs.is_empty() {
+            tag_csvs.extend(Self::bundled_tag_csv());
+        }
+        if !tag_csvs.is_empty() {
+            tagger.start_tag_database_load(&cc.egui_ctx, tag_csvs);
+        }
+        if let Some(path) = tagger.app_settings.wiki_dump.clone().filter(|path| path.exists()) {
+            tagger.start_wiki_load(&cc.egui_ctx, path);
+        }
+        if let Some(path) = tagger.app_settings.translation_file.clone().filter(|path| path.exists()) {
+            tagger.load_translations(path);
+        }
+        if let Some(path) = tagger.app_settings.cooccurrence_table.clone().filter(|path| path.exists()) {
+            tagger.load_cooccurrence_table(path);
+        }
+        if let Some(path) = tagger.app_settings.clip_tokenizer.clone().filter(|path| path.exists()) {
+            tagger.load_clip_tokenizer(path);
+        }
+        if let Some(path) = tagger.app_settings.auto_tagger.model.clone().filter(|path| path.exists()) {
+            tagger.start_tagger_load(&cc.egui_ctx, path);
+        }
+
+        tagger
+    }
+
+    fn bundled_tag_csv() -> Option<PathBuf> {
+        let next_to_exe = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join(BUNDLED_TAG_CSV)));
+        next_to_exe.into_iter()
+            .chain(std::iter::once(PathBuf::from(BUNDLED_TAG_CSV)))
+            .find(|path| path.exists())
+    }
+
+    // Loads the CSVs one after another; each is added below the sources already loaded
+    fn start_tag_database_load(&mut self, ctx: &egui::Context, paths: Vec<PathBuf>) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.tag_db_receiver = Some(rx);
+        self.tag_db_progress = 0.0;
+
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let total = paths.len().max(1) as f32;
+            for (idx, path) in paths.into_iter().enumerate() {
+                let path = path.canonicalize().unwrap_or(path);
+                let result = TagSource::load_from_csv_with_progress(&path, |progress| {
+                    let _ = tx.send(TagDatabaseMessage::Progress((idx as f32 + progress) / total));
+                    ctx.request_repaint();
+                });
+                let message = match result {
+                    Ok(source) => TagDatabaseMessage::Loaded { source: Box::new(source) },
+                    Err(err) => TagDatabaseMessage::Error { path, error: err.to_string() },
+                };
+                let _ = tx.send(message);
+            }
+            let _ = tx.send(TagDatabaseMessage::Finished);
+            ctx.request_repaint();
+        });
+    }
+
+    fn start_wiki_load(&mut self, ctx: &egui::Context, path: PathBuf) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.wiki_receiver = Some(rx);
+
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let message = match tag_wiki::load_wiki_dump(&path) {
+                Ok(descriptions) => WikiMessage::Loaded { path, descriptions },
+                Err(err) => WikiMessage::Error { path, error: err.to_string() },
+            };
+            let _ = tx.send(message);
+            ctx.request_repaint();
+        });
+    }
+
+    fn process_wiki_messages(&mut self) {
+        let Some(rx) = &self.wiki_receiver else {
+            return;
+        };
+        let message = match rx.try_recv() {
+            Ok(message) => message,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return,
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                self.wiki_receiver = None;
+                return;
+            }
+        };
+        self.wiki_receiver = None;
+
+        match message {
+            WikiMessage::Loaded { path, descriptions } => {
+                self.feedback_message = Some(format!(
+                    "Loaded {} tag descriptions from {}",
+                    descriptions.len(),
+                    path.display()
+                ));
+                self.booru_manager.set_wiki(descriptions);
+                self.app_settings.wiki_dump = Some(path.canonicalize().unwrap_or(path));
+                if let Err(err) = self.app_settings.save() {
+                    eprintln!("Failed to save settings: {}", err);
+                }
+            }
+            WikiMessage::Error { path, error } => {
+                self.feedback_message = Some(format!("Failed to load {}: {}", path.display(), error));
+            }
+        }
+        self.feedback_timer = Some(std::time::Instant::now());
+    }
+
+    fn draw_wiki_controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if self.wiki_receiver.is_some() {
+                ui.spinner();
+                ui.label("Loading wiki dump...");
+                return;
+            }
+            if ui.button("Load Wiki Dump").on_hover_text("JSON, JSON lines or CSV of tag descriptions").clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("Wiki Dumps", &["json", "jsonl", "csv"])
+                    .pick_file()
+                {
+                    self.start_wiki_load(ui.ctx(), path);
+                }
+            }
+            if self.booru_manager.wiki_len() > 0 {
+                ui.weak(format!("{} descriptions", self.booru_manager.wiki_len()));
+                if ui.small_button("✖").on_hover_text("Unload wiki dump").clicked() {
+                    self.booru_manager.set_wiki(HashMap::new());
+                    self.app_settings.wiki_dump = None;
+                    if let Err(err) = self.app_settings.save() {
+                        eprintln!("Failed to save settings: {}", err);
+                    }
+                }
+            }
+        });
+    }
+
+    fn load_translations(&mut self, path: PathBuf) {
+        let result = fs::read_to_string(&path)
+            .map_err(Into::into)
+            .and_then(|contents| tag_csv::parse_translation_csv(&contents));
+        match result {
+            Ok(translations) => {
+                self.booru_manager.set_translations(translations);
+                self.app_settings.translation_file = Some(path.canonicalize().unwrap_or(path));
+                if let Err(err) = self.app_settings.save() {
+                    eprintln!("Failed to save settings: {}", err);
+                }
+            }
+            Err(err) => {
+                self.feedback_message = Some(format!("Failed to load {}: {}", path.display(), err));
+                self.feedback_timer = Some(std::time::Instant::now());
+            }
+        }
+    }
+
+    // Optimizing a tagger model takes a while, so it is loaded off the UI thread
+    fn start_tagger_load(&mut self, ctx: &egui::Context, path: PathBuf) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.tagger_load_receiver = Some(rx);
+
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let result = TaggerModel::load(&path).map_err(|err| err.to_string());
+            let _ = tx.send((path, result));
+            ctx.request_repaint();
+        });
+    }
+
+    fn process_tagger_load_messages(&mut self) {
+        let Some((path, result)) = self.tagger_load_receiver.as_ref().and_then(|rx| rx.try_recv().ok()) else {
+            return;
+        };
+        self.tagger_load_receiver = None;
+        match result {
+            Ok(model) => {
+                self.feedback_message = Some(format!("Loaded tagger with {} labels", model.label_count()));
+                self.auto_tagger = Some(Arc::new(model));
+                self.app_settings.auto_tagger.model = Some(path.canonicalize().unwrap_or(path));
+                if let Err(err) = self.app_settings.save() {
+                    eprintln!("Failed to save settings: {}", err);
+                }
+            }
+            Err(error) => {
+                self.feedback_message = Some(format!("Failed to load {}: {}", path.display(), error));
+            }
+        }
+        self.feedback_timer = Some(std::time::Instant::now());
+    }
+
+    fn scope_images(&self, scope: AutoTagScope) -> Vec<usize> {
+        match scope {
+            AutoTagScope::CurrentImage if self.current_image_idx < self.images.len() => vec![self.current_image_idx],
+            AutoTagScope::CurrentImage => Vec::new(),
+            AutoTagScope::SearchResults => self.search_matches(),
+            AutoTagScope::AllImages => (0..self.images.len()).collect(),
+        }
+    }
+
+    fn start_auto_tagging(&mut self, ctx: &egui::Context) {
+        let Some(model) = self.auto_tagger.clone() else {
+            return;
+        };
+        let paths: Vec<PathBuf> = self.scope_images(self.auto_tag_scope).into_iter()
+            .map(|idx| self.images[idx].path.clone())
+            .collect();
+        if paths.is_empty() {
+            return;
+        }
 
-        // if let Err(err) = tagger.booru_manager.load_from_csv(
-        //     std::path::Path::new("danbooru-12-10-24-underscore.csv")
-        // ) {
-        //     eprintln!("Failed to load Booru database: {}", err);
-        // }
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.auto_tag_receiver = Some(rx);
+        self.auto_tag_progress = Some((0, 0, paths.len()));
+        self.auto_tag_cancel = Arc::new(AtomicBool::new(false));
+        let cancel = self.auto_tag_cancel.clone();
+        let settings = self.app_settings.auto_tagger.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            paths.into_par_iter().for_each(|path| {
+                if cancel.load(Ordering::Relaxed) {
+                    return;
+                }
+                let result = image::open(&path)
+                    .map_err(Into::into)
+                    .and_then(|img| model.predict(&img, &settings));
+                let message = match result {
+                    Ok(predictions) => AutoTagMessage::Tagged { path, predictions },
+                    Err(err) => AutoTagMessage::Failed { path, error: err.to_string() },
+                };
+                let _ = tx.send(message);
+                ctx.request_repaint();
+            });
+            let _ = tx.send(AutoTagMessage::Finished);
+            ctx.request_repaint();
+        });
+    }
+
+    fn process_auto_tag_messages(&mut self) {
+        let Some(rx) = &self.auto_tag_receiver else {
+            return;
+        };
+        let messages: Vec<AutoTagMessage> = rx.try_iter().collect();
+        for message in messages {
+            match message {
+                AutoTagMessage::Tagged { path, predictions } => {
+                    let tags = predictions.into_iter()
+                        .map(|prediction| ProposedTag { name: prediction.name, confidence: Some(prediction.confidence) })
+                        .collect();
+                    self.merge_proposed_tags(&path, tags, self.app_settings.auto_tagger.merge_mode);
+                    if let Some((done, _, _)) = &mut self.auto_tag_progress {
+                        *done += 1;
+                    }
+                    continue;
+                }
+                AutoTagMessage::Failed { path, error } => {
+                    eprintln!("Auto-tagging failed for {}: {}", path.display(), error);
+                    if let Some((done, failed, _)) = &mut self.auto_tag_progress {
+                        *done += 1;
+                        *failed += 1;
+                    }
+                    continue;
+                }
+                AutoTagMessage::Finished => {
+                    let (done, failed, total) = self.auto_tag_progress.take().unwrap_or_default();
+                    let mut message = format!("Auto-tagged {} of {} images", done - failed, total);
+                    if failed > 0 {
+                        message.push_str(&format!(", {} failed (see the console)", failed));
+                    }
+                    self.feedback_message = Some(message);
+                    self.auto_tag_receiver = None;
+                }
+            }
+            self.feedback_timer = Some(std::time::Instant::now());
+        }
+    }
+
+    // Maps proposed tags onto the loaded tag databases and the dataset's output style, then merges
+    // them into the image as the merge mode asks
+    fn merge_proposed_tags(&mut self, path: &Path, proposed: Vec<ProposedTag>, mode: MergeMode) {
+        let policy = &self.dataset_settings.tag_policy;
+        // Only tags that still need review keep their confidence
+        let review_below = self.app_settings.auto_tagger.review_below;
+        let proposed: Vec<ProposedTag> = proposed.into_iter()
+            .map(|tag| {
+                let name = self.booru_manager.canonical_name(&tag.name).unwrap_or(tag.name);
+                ProposedTag { name: self.dataset_settings.tag_style.apply(&name), ..tag }
+            })
+            .filter(|tag| !tag.name.is_empty())
+            .filter(|tag| !(policy.strip_forbidden && policy.is_forbidden(&tag.name)))
+            .collect();
+        let Some(image) = self.images.iter_mut().find(|image| image.path == path) else {
+            return;
+        };
+
+        let present: HashSet<String> = image.tags.iter().map(|tag| tag_syntax::lookup_name(tag)).collect();
+        let is_new = |tag: &ProposedTag| !present.contains(&tag_syntax::lookup_name(&tag.name));
+        match mode {
+            MergeMode::Append => {
+                let new_tags: Vec<&ProposedTag> = proposed.iter().filter(|tag| is_new(tag)).collect();
+                if new_tags.is_empty() {
+                    return;
+                }
+                for tag in new_tags {
+                    if let Some(confidence) = tag.confidence.filter(|&confidence| confidence < review_below) {
+                        image.confidences.insert(tag_syntax::lookup_name(&tag.name), confidence);
+                    }
+                    image.tags.push(tag.name.clone());
+                }
+            }
+            MergeMode::Replace => {
+                // Required tags survive a replace
+                let mut tags: Vec<String> = image.tags.iter().filter(|tag| policy.is_required(tag)).cloned().collect();
+                let mut kept: HashSet<String> = tags.iter().map(|tag| tag_syntax::lookup_name(tag)).collect();
+                for tag in &proposed {
+                    let name = tag_syntax::lookup_name(&tag.name);
+                    if kept.insert(name.clone()) {
+                        if let Some(confidence) = tag.confidence.filter(|&confidence| confidence < review_below) {
+                            image.confidences.insert(name, confidence);
+                        }
+                        tags.push(tag.name.clone());
+                    }
+                }
+                image.tags = tags;
+            }
+            MergeMode::Suggest => {
+                let pending: Vec<ProposedTag> = proposed.into_iter().filter(is_new).collect();
+                if !pending.is_empty() {
+                    self.auto_tag_suggestions.insert(image.path.clone(), pending);
+                }
+                return;
+            }
+        }
+        self.modified_files.insert(image.path.clone(), true);
+        self.tags_revision += 1;
+    }
+
+    // Append only fills in a missing caption
+    fn merge_caption(&mut self, path: &Path, caption: &str, mode: MergeMode) {
+        let caption = caption.trim();
+        let Some(image) = self.images.iter_mut().find(|image| image.path == path) else {
+            return;
+        };
+        if caption.is_empty() || image.caption.trim() == caption {
+            return;
+        }
+
+        match mode {
+            MergeMode::Append if !image.caption.trim().is_empty() => return,
+            MergeMode::Append | MergeMode::Replace => image.caption = caption.to_string(),
+            MergeMode::Suggest => {
+                self.caption_suggestions.insert(image.path.clone(), caption.to_string());
+                return;
+            }
+        }
+        self.modified_files.insert(image.path.clone(), true);
+        self.tags_revision += 1;
+    }
+
+    fn draw_auto_tagger(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Auto Tagger")
+            .id_salt("auto_tagger")
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    let loading = self.tagger_load_receiver.is_some();
+                    let idle = !loading && self.auto_tag_receiver.is_none();
+                    if ui.add_enabled(idle, egui::Button::new("Load Model"))
+                        .on_hover_text("A WD14-style model.onnx with selected_tags.csv next to it")
+                        .clicked()
+                    {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("ONNX Models", &["onnx"])
+                            .pick_file()
+                        {
+                            self.start_tagger_load(ui.ctx(), path);
+                        }
+                    }
+                    if loading {
+                        ui.spinner();
+                        ui.weak("Loading model...");
+                    } else if let Some(model) = &self.auto_tagger {
+                        ui.weak(format!("{} labels", model.label_count()));
+                    }
+                });
+
+                let search_count = self.search_matches().len();
+                let image_count = self.images.len();
+                let settings = &mut self.app_settings.auto_tagger;
+                let mut changed = false;
+                egui::Grid::new("auto_tagger_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("General threshold:");
+                    changed |= ui.add(egui::Slider::new(&mut settings.general_threshold, 0.0..=1.0)).changed();
+                    ui.end_row();
+                    ui.label("Character threshold:");
+                    changed |= ui.add(egui::Slider::new(&mut settings.character_threshold, 0.0..=1.0)).changed();
+                    ui.end_row();
+                    ui.label("Merge:");
+                    egui::ComboBox::from_id_salt("auto_tag_merge")
+                        .selected_text(settings.merge_mode.label())
+                        .show_ui(ui, |ui| {
+                            for mode in MergeMode::ALL {
+                                changed |= ui.selectable_value(&mut settings.merge_mode, mode, mode.label()).changed();
+                            }
+                        });
+                    ui.end_row();
+                    ui.label("Images:");
+                    scope_picker(ui, "auto_tag_scope", &mut self.auto_tag_scope, search_count, image_count);
+                    ui.end_row();
+                });
+                changed |= ui.checkbox(&mut settings.include_rating, "Include rating tag").changed();
+                if changed {
+                    if let Err(err) = self.app_settings.save() {
+                        eprintln!("Failed to save settings: {}", err);
+                    }
+                }
+
+                match self.auto_tag_progress {
+                    Some((done, _, total)) => {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::ProgressBar::new(done as f32 / total.max(1) as f32)
+                                .text(format!("{} / {}", done, total))
+                                .desired_width(200.0));
+                            if ui.button("Cancel").clicked() {
+                                self.auto_tag_cancel.store(true, Ordering::Relaxed);
+                            }
+                        });
+                    }
+                    None => {
+                        let ready = self.auto_tagger.is_some() && self.tagger_load_receiver.is_none() && self.auto_tag_receiver.is_none();
+                        if ui.add_enabled(ready, egui::Button::new("Run Tagger")).clicked() {
+                            self.start_auto_tagging(ui.ctx());
+                        }
+                    }
+                }
+            });
+    }
+
+    // Pending suggest-only tags and caption for the current image
+    fn draw_auto_tag_suggestions(&mut self, ui: &mut egui::Ui) {
+        let Some(path) = self.images.get(self.current_image_idx).map(|image| image.path.clone()) else {
+            return;
+        };
+        let pending = self.auto_tag_suggestions.get(&path);
+        let caption = self.caption_suggestions.get(&path);
+        if pending.is_none() && caption.is_none() {
+            return;
+        }
+
+        ui.separator();
+        let mut accepted = Vec::new();
+        let mut dismiss = false;
+        let mut use_caption = false;
+        if let Some(pending) = pending {
+            ui.label(format!("Suggested for this image ({}):", pending.len()));
+            ui.horizontal_wrapped(|ui| {
+                for tag in pending {
+                    let text = match tag.confidence {
+                        Some(confidence) => format!("{} {:.0}%", tag.name, confidence * 100.0),
+                        None => tag.name.clone(),
+                    };
+                    if ui.small_button(text).on_hover_text("Click to add").clicked() {
+                        accepted.push(tag.name.clone());
+                    }
+                }
+            });
+        }
+        if let Some(caption) = caption {
+            ui.label("Suggested caption:");
+            ui.label(egui::RichText::new(caption).italics());
+        }
+        ui.horizontal(|ui| {
+            if let Some(pending) = pending {
+                if ui.button("Add All").clicked() {
+                    accepted = pending.iter().map(|tag| tag.name.clone()).collect();
+                }
+            }
+            if caption.is_some() {
+                use_caption = ui.button("Use Caption").clicked();
+            }
+            dismiss = ui.button("Dismiss").clicked();
+        });
+
+        if let Some(pending) = self.auto_tag_suggestions.get_mut(&path) {
+            pending.retain(|tag| !accepted.contains(&tag.name));
+            if dismiss || pending.is_empty() {
+                self.auto_tag_suggestions.remove(&path);
+            }
+        }
+        if use_caption {
+            if let Some(caption) = self.caption_suggestions.remove(&path) {
+                self.merge_caption(&path, &caption, MergeMode::Replace);
+            }
+        }
+        if dismiss {
+            self.caption_suggestions.remove(&path);
+        }
+        for tag in accepted {
+            self.apply_tag_action(TagAction::Add(tag));
+        }
+    }
+
+    fn start_captioner(&mut self, ctx: &egui::Context) {
+        let command = self.app_settings.external_captioner.command.trim().to_string();
+        let Some(dir) = self.current_dir.clone() else {
+            return;
+        };
+        let requests: Vec<CaptionRequest> = self.scope_images(self.captioner_scope).into_iter()
+            .map(|idx| {
+                let image = &self.images[idx];
+                CaptionRequest {
+                    id: idx,
+                    image: image.path.canonicalize().unwrap_or_else(|_| image.path.clone()),
+                    tags: image.tags.clone(),
+                    caption: image.caption.clone(),
+                }
+            })
+            .collect();
+        if command.is_empty() || requests.is_empty() {
+            return;
+        }
+
+        // Responses refer back to the editor's own paths
+        let paths: HashMap<usize, PathBuf> = requests.iter()
+            .map(|request| (request.id, self.images[request.id].path.clone()))
+            .collect();
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.captioner_receiver = Some(rx);
+        self.captioner_progress = Some((0, 0, requests.len()));
+        // A fresh flag per run, so a run still winding down after a cancel stays cancelled
+        self.captioner_cancel = Arc::new(AtomicBool::new(false));
+        let cancel = self.captioner_cancel.clone();
+        let timeout = std::time::Duration::from_secs(self.app_settings.external_captioner.timeout_secs.max(1));
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+                let mut process = CaptionerProcess::spawn(&command, &dir, timeout, cancel.clone())?;
+                for request in &requests {
+                    if cancel.load(Ordering::Relaxed) {
+                        process.kill();
+                        return Ok(());
+                    }
+                    let path = paths[&request.id].clone();
+                    let message = match process.caption(request) {
+                        Ok(CaptionResponse { error: Some(error), .. }) => CaptionerMessage::Failed { path, error },
+                        Ok(response) => CaptionerMessage::Captioned { path, response },
+                        Err(err) => {
+                            process.kill();
+                            return if cancel.load(Ordering::Relaxed) { Ok(()) } else { Err(err) };
+                        }
+                    };
+                    let _ = tx.send(message);
+                    ctx.request_repaint();
+                }
+                process.finish()
+            })();
+            let _ = tx.send(CaptionerMessage::Finished(result.err().map(|err| err.to_string())));
+            ctx.request_repaint();
+        });
+    }
+
+    fn process_captioner_messages(&mut self) {
+        let Some(rx) = &self.captioner_receiver else {
+            return;
+        };
+        let messages: Vec<CaptionerMessage> = rx.try_iter().collect();
+        let mode = self.app_settings.external_captioner.merge_mode;
+        for message in messages {
+            match message {
+                CaptionerMessage::Captioned { path, response } => {
+                    if let Some(tags) = response.tags {
+                        let tags = tags.into_iter()
+                            .map(|name| {
+                                let confidence = response.confidences.get(&name).copied();
+                                ProposedTag { name, confidence }
+                            })
+                            .collect();
+                        self.merge_proposed_tags(&path, tags, mode);
+                    }
+                    if let Some(caption) = response.caption {
+                        self.merge_caption(&path, &caption, mode);
+                    }
+                    if let Some((done, _, _)) = &mut self.captioner_progress {
+                        *done += 1;
+                    }
+                }
+                CaptionerMessage::Failed { path, error } => {
+                    eprintln!("Captioner failed for {}: {}", path.display(), error);
+                    if let Some((done, failed, _)) = &mut self.captioner_progress {
+                        *done += 1;
+                        *failed += 1;
+                    }
+                }
+                CaptionerMessage::Finished(error) => {
+                    let (done, failed, total) = self.captioner_progress.take().unwrap_or_default();
+                    let mut message = format!("Captioned {} of {} images", done - failed, total);
+                    if failed > 0 {
+                        message.push_str(&format!(", {} failed (see the console)", failed));
+                    }
+                    if let Some(error) = error {
+                        message = format!("{}; captioner stopped: {}", message, error);
+                    }
+                    self.feedback_message = Some(message);
+                    self.feedback_timer = Some(std::time::Instant::now());
+                    self.captioner_receiver = None;
+                }
+            }
+        }
+    }
 
-        //tagger
+    fn draw_external_captioner(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("External Captioner")
+            .id_salt("external_captioner")
+            .show(ui, |ui| {
+                let search_count = self.search_matches().len();
+                let image_count = self.images.len();
+                let settings = &mut self.app_settings.external_captioner;
+                let mut changed = false;
+                egui::Grid::new("external_captioner_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Command:");
+                    changed |= ui.add(egui::TextEdit::singleline(&mut settings.command)
+                        .hint_text("python caption.py")
+                        .desired_width(200.0))
+                        .on_hover_text("Started in the dataset folder; talks JSON lines over stdin and stdout")
+                        .lost_focus();
+                    ui.end_row();
+                    ui.label("Merge:");
+                    egui::ComboBox::from_id_salt("captioner_merge")
+                        .selected_text(settings.merge_mode.label())
+                        .show_ui(ui, |ui| {
+                            for mode in MergeMode::ALL {
+                                changed |= ui.selectable_value(&mut settings.merge_mode, mode, mode.label()).changed();
+                            }
+                        });
+                    ui.end_row();
+                    ui.label("Timeout:");
+                    changed |= ui.add(egui::DragValue::new(&mut settings.timeout_secs).range(1..=3600).suffix(" s"))
+                        .on_hover_text("How long to wait for each response before stopping the captioner")
+                        .changed();
+                    ui.end_row();
+                    ui.label("Images:");
+                    scope_picker(ui, "captioner_scope", &mut self.captioner_scope, search_count, image_count);
+                    ui.end_row();
+                });
+                if changed {
+                    if let Err(err) = self.app_settings.save() {
+                        eprintln!("Failed to save settings: {}", err);
+                    }
+                }
 
-        Self::default()
+                match self.captioner_progress {
+                    Some((done, _, total)) => {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::ProgressBar::new(done as f32 / total.max(1) as f32)
+                                .text(format!("{} / {}", done, total))
+                                .desired_width(200.0));
+                            if ui.button("Cancel").on_hover_text("Stops the captioner, also mid-image").clicked() {
+                                self.captioner_cancel.store(true, Ordering::Relaxed);
+                            }
+                        });
+                    }
+                    None => {
+                        let ready = self.current_dir.is_some()
+                            && !self.app_settings.external_captioner.command.trim().is_empty();
+                        if ui.add_enabled(ready, egui::Button::new("Run Captioner")).clicked() {
+                            self.start_captioner(ui.ctx());
+                        }
+                    }
+                }
+            });
+    }
+
+    // Tags of an image still below the review threshold, least certain first
+    fn pending_review(image: &ImageData, review_below: f32) -> Vec<(String, f32)> {
+        let mut pending: Vec<(String, f32)> = image.tags.iter()
+            .filter_map(|tag| {
+                let confidence = *image.confidences.get(&tag_syntax::lookup_name(tag))?;
+                (confidence < review_below).then(|| (tag.clone(), confidence))
+            })
+            .collect();
+        pending.sort_by(|a, b| a.1.total_cmp(&b.1));
+        pending
+    }
+
+    fn start_review(&mut self, ctx: &egui::Context) {
+        let review_below = self.app_settings.auto_tagger.review_below;
+        let mut images: Vec<(f32, PathBuf)> = self.images.iter()
+            .filter_map(|image| {
+                let (_, lowest) = Self::pending_review(image, review_below).into_iter().next()?;
+                Some((lowest, image.path.clone()))
+            })
+            .collect();
+        if images.is_empty() {
+            self.review_queue = None;
+            self.feedback_message = Some(format!("No tags below {:.0}% confidence to review", review_below * 100.0));
+            self.feedback_timer = Some(std::time::Instant::now());
+            return;
+        }
+
+        images.sort_by(|a, b| a.0.total_cmp(&b.0));
+        self.review_queue = Some(ReviewQueue {
+            images: images.into_iter().map(|(_, path)| path).collect(),
+            position: 0,
+        });
+        self.show_review_image(ctx);
+    }
+
+    fn show_review_image(&mut self, ctx: &egui::Context) {
+        let Some(path) = self.review_queue.as_ref().and_then(|queue| queue.images.get(queue.position)) else {
+            return;
+        };
+        if let Some(idx) = self.images.iter().position(|image| &image.path == path) {
+            if idx != self.current_image_idx {
+                self.current_image_idx = idx;
+                self.change_image(ctx);
+            }
+        }
+    }
+
+    // Accepting keeps the tag and drops its confidence; rejecting removes the tag. Without a tag
+    // the least certain one of the image under review is taken.
+    fn review_tag(&mut self, ctx: &egui::Context, tag: Option<String>, accept: bool) {
+        let review_below = self.app_settings.auto_tagger.review_below;
+        let Some(path) = self.review_queue.as_ref().and_then(|queue| queue.images.get(queue.position)).cloned() else {
+            return;
+        };
+        let Some(idx) = self.images.iter().position(|image| image.path == path) else {
+            self.advance_review(ctx);
+            return;
+        };
+        self.show_review_image(ctx);
+
+        let tag = tag.or_else(|| Self::pending_review(&self.images[idx], review_below).into_iter().next().map(|(tag, _)| tag));
+        if let Some(tag) = tag {
+            let image = &mut self.images[idx];
+            image.confidences.remove(&tag_syntax::lookup_name(&tag));
+            self.modified_files.insert(image.path.clone(), true);
+            self.tags_revision += 1;
+            if !accept {
+                self.apply_tag_action(TagAction::Remove(tag));
+            }
+        }
+        if Self::pending_review(&self.images[idx], review_below).is_empty() {
+            self.advance_review(ctx);
+        }
+    }
+
+    fn advance_review(&mut self, ctx: &egui::Context) {
+        let review_below = self.app_settings.auto_tagger.review_below;
+        let Some(queue) = &mut self.review_queue else {
+            return;
+        };
+        queue.position += 1;
+        // Skip images that were finished some other way in the meantime
+        while let Some(path) = queue.images.get(queue.position) {
+            let pending = self.images.iter()
+                .find(|image| &image.path == path)
+                .is_some_and(|image| !Self::pending_review(image, review_below).is_empty());
+            if pending {
+                break;
+            }
+            queue.position += 1;
+        }
+
+        if queue.position >= queue.images.len() {
+            self.feedback_message = Some(format!("Reviewed {} images", queue.images.len()));
+            self.feedback_timer = Some(std::time::Instant::now());
+            self.review_queue = None;
+            return;
+        }
+        self.show_review_image(ctx);
+    }
+
+    fn draw_review(&mut self, ctx: &egui::Context) {
+        let Some(queue) = &self.review_queue else {
+            return;
+        };
+
+        let position = queue.position;
+        let total = queue.images.len();
+        let image = queue.images.get(position)
+            .and_then(|path| self.images.iter().find(|image| &image.path == path));
+        let name = image.map(|image| image.path.file_name().unwrap_or_default().to_string_lossy().into_owned());
+        let pending = image.map(|image| Self::pending_review(image, self.app_settings.auto_tagger.review_below))
+            .unwrap_or_default();
+
+        let mut open = true;
+        let mut action = None;
+        let mut go_to = false;
+        let mut skip = false;
+        let mut restart = false;
+        let mut threshold_changed = false;
+        egui::Window::new("Review Tags")
+            .open(&mut open)
+            .default_width(380.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Image {} of {}:", position + 1, total));
+                    if let Some(name) = &name {
+                        go_to = ui.link(name).on_hover_text("Go to image").clicked();
+                    }
+                });
+                ui.weak("A accepts the first tag, R rejects it, N skips to the next image");
+                ui.separator();
+
+                egui::Grid::new("review_grid").striped(true).show(ui, |ui| {
+                    for (idx, (tag, confidence)) in pending.iter().enumerate() {
+                        let mut text = egui::RichText::new(tag);
+                        if idx == 0 {
+                            text = text.strong();
+                        }
+                        ui.label(text);
+                        ui.add(egui::ProgressBar::new(*confidence)
+                            .text(format!("{:.0}%", confidence * 100.0))
+                            .desired_width(120.0));
+                        if ui.small_button("Accept").clicked() {
+                            action = Some((tag.clone(), true));
+                        }
+                        if ui.small_button("Reject").clicked() {
+                            action = Some((tag.clone(), false));
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Review below:");
+                    threshold_changed = ui.add(egui::Slider::new(&mut self.app_settings.auto_tagger.review_below, 0.0..=1.0))
+                        .changed();
+                });
+                ui.horizontal(|ui| {
+                    skip = ui.button("Skip Image").clicked();
+                    restart = ui.button("Restart")
+                        .on_hover_text("Rebuild the queue, e.g. after changing the threshold")
+                        .clicked();
+                });
+            });
+
+        if threshold_changed {
+            if let Err(err) = self.app_settings.save() {
+                eprintln!("Failed to save settings: {}", err);
+            }
+        }
+        if go_to {
+            self.show_review_image(ctx);
+        }
+        if let Some((tag, accept)) = action {
+            self.review_tag(ctx, Some(tag), accept);
+        }
+        if skip {
+            self.advance_review(ctx);
+        }
+        if restart {
+            self.start_review(ctx);
+        }
+        if !open {
+            self.review_queue = None;
+        }
+    }
+
+    // Runs the console's script over its scope in the background without touching the images, for
+    // the diff preview
+    fn preview_script(&mut self, ctx: &egui::Context) {
+        let images: Vec<ScriptImage> = self.images.iter()
+            .map(|image| ScriptImage { path: image.path.clone(), tags: image.tags.clone(), caption: image.caption.clone() })
+            .collect();
+        let targets = self.scope_images(self.script_scope);
+        let source = self.script_source.clone();
+        // Lookups see the tag databases as they were when the run started
+        let tags = self.booru_manager.lookup_snapshot();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.script_receiver = Some(rx);
+        self.script_progress = Some((0, targets.len()));
+        self.script_preview = None;
+        self.script_cancel = Arc::new(AtomicBool::new(false));
+        let cancel = self.script_cancel.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let run = tag_script::run(&source, &images, &targets, tags, cancel, |done| {
+                let _ = tx.send(ScriptMessage::Progress(done));
+                ctx.request_repaint();
+            });
+            let _ = tx.send(ScriptMessage::Finished(run));
+            ctx.request_repaint();
+        });
+    }
+
+    fn process_script_messages(&mut self) {
+        let Some(rx) = &self.script_receiver else {
+            return;
+        };
+        let messages: Vec<ScriptMessage> = rx.try_iter().collect();
+        for message in messages {
+            match message {
+                ScriptMessage::Progress(done) => {
+                    if let Some((current, _)) = &mut self.script_progress {
+                        *current = done;
+                    }
+                }
+                ScriptMessage::Finished(run) => {
+                    self.script_preview = Some(run);
+                    self.script_progress = None;
+                    self.script_receiver = None;
+                }
+            }
+        }
+    }
+
+    // Commits the previewed changes, skipping images edited since the preview and edits that
+    // would drop required tags
+    fn apply_script_changes(&mut self) {
+        let Some(run) = self.script_preview.take() else {
+            return;
+        };
+        let policy = &self.dataset_settings.tag_policy;
+        let (mut changed, mut stale, mut protected) = (0, 0, 0);
+        for change in run.changes {
+            let Some(image) = self.images.iter_mut().find(|image| image.path == change.path) else {
+                continue;
+            };
+            if image.tags != change.before_tags || image.caption != change.before_caption {
+                stale += 1;
+                continue;
+            }
+            if !policy.broken_requirements(&image.tags, &change.tags).is_empty() {
+                protected += 1;
+                continue;
+            }
+            image.tags = change.tags;
+            image.caption = change.caption;
+            self.modified_files.insert(image.path.clone(), true);
+            self.tags_revision += 1;
+            changed += 1;
+        }
+
+        let mut message = format!("Script changed {} images", changed);
+        if stale > 0 {
+            message.push_str(&format!(", skipped {} edited since the preview", stale));
+        }
+        if protected > 0 {
+            message.push_str(&format!(", skipped {} that would lose required tags", protected));
+        }
+        self.feedback_message = Some(message);
+        self.feedback_timer = Some(std::time::Instant::now());
+    }
+
+    fn draw_script_console(&mut self, ctx: &egui::Context) {
+        if !self.script_console_open {
+            return;
+        }
+
+        let mut open = true;
+        let mut preview = false;
+        let mut apply = false;
+        let mut discard = false;
+        let mut jump_to = None;
+        let search_count = self.search_matches().len();
+        let image_count = self.images.len();
+        egui::Window::new("Script Console")
+            .open(&mut open)
+            .default_width(560.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Open...").clicked() {
+                        if let Some(path) = rfd::FileDialog::new().add_filter("Rhai Scripts", &["rhai"]).pick_file() {
+                            match fs::read_to_string(&path) {
+                                Ok(source) => {
+                                    self.script_source = source;
+                                    self.script_path = Some(path);
+                                }
+                                Err(err) => {
+                                    self.feedback_message = Some(format!("Failed to read {}: {}", path.display(), err));
+                                    self.feedback_timer = Some(std::time::Instant::now());
+                                }
+                            }
+                        }
+                    }
+                    if ui.button("Save As...").clicked() {
+                        let mut dialog = rfd::FileDialog::new().add_filter("Rhai Scripts", &["rhai"]);
+                        if let Some(path) = &self.script_path {
+                            dialog = dialog.set_file_name(path.file_name().unwrap_or_default().to_string_lossy());
+                        }
+                        if let Some(path) = dialog.save_file() {
+                            if let Err(err) = fs::write(&path, &self.script_source) {
+                                self.feedback_message = Some(format!("Failed to save {}: {}", path.display(), err));
+                                self.feedback_timer = Some(std::time::Instant::now());
+                            }
+                            self.script_path = Some(path);
+                        }
+                    }
+                    if let Some(path) = &self.script_path {
+                        ui.weak(path.file_name().unwrap_or_default().to_string_lossy());
+                    }
+                });
+
+                ui.add(egui::TextEdit::multiline(&mut self.script_source)
+                    .id(egui::Id::new("script_editor"))
+                    .code_editor()
+                    .hint_text("// e.g. move artist tags to the end\nlet artists = tags.filter(|t| category(t) == \"artist\");\ntags = tags.filter(|t| category(t) != \"artist\") + artists;")
+                    .desired_rows(10)
+                    .desired_width(f32::INFINITY));
+                egui::CollapsingHeader::new("Reference").id_salt("script_reference").show(ui, |ui| {
+                    ui.label("Runs once per image. Change `tags` (array of strings) and `caption` (string) to edit it.");
+                    ui.label("Read-only: `path`, `name`, `index` and `images`, an array of #{ path, name, tags, caption } for every image.");
+                    ui.label("Lookups: category(tag), post_count(tag), is_known(tag), canonical(tag), aliases(tag), tag_name(tag).");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Images:");
+                    scope_picker(ui, "script_scope", &mut self.script_scope, search_count, image_count);
+                    let ready = !self.script_source.trim().is_empty() && self.script_progress.is_none();
+                    preview = ui.add_enabled(ready, egui::Button::new("Preview"))
+                        .on_hover_text("Run the script and show what it would change")
+                        .clicked();
+                });
+                if let Some((done, total)) = self.script_progress {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::ProgressBar::new(done as f32 / total.max(1) as f32)
+                            .text(format!("{} / {}", done, total))
+                            .desired_width(200.0));
+                        if ui.button("Cancel").clicked() {
+                            self.script_cancel.store(true, Ordering::Relaxed);
+                        }
+                    });
+                }
+
+                let Some(run) = &self.script_preview else {
+                    return;
+                };
+                ui.separator();
+                if let Some(error) = &run.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                if !run.output.is_empty() {
+                    egui::CollapsingHeader::new(format!("Output ({} lines)", run.output.len()))
+                        .id_salt("script_output")
+                        .show(ui, |ui| {
+                            egui::ScrollArea::vertical().id_salt("script_output_scroll").max_height(120.0).show(ui, |ui| {
+                                for line in &run.output {
+                                    ui.monospace(line);
+                                }
+                            });
+                        });
+                }
+                if run.error.is_none() {
+                    ui.label(format!("{} images would change", run.changes.len()));
+                }
+
+                egui::ScrollArea::vertical().id_salt("script_diff").max_height(300.0).show(ui, |ui| {
+                    for change in &run.changes {
+                        let name = change.path.file_name().unwrap_or_default().to_string_lossy();
+                        if ui.link(name).on_hover_text("Go to image").clicked() {
+                            jump_to = self.images.iter().position(|image| image.path == change.path);
+                        }
+                        if change.tags != change.before_tags {
+                            draw_tag_diff(ui, &change.before_tags, &change.tags);
+                        }
+                        if change.caption != change.before_caption {
+                            ui.colored_label(ui.visuals().error_fg_color, format!("- {}", change.before_caption));
+                            ui.colored_label(egui::Color32::from_rgb(90, 170, 90), format!("+ {}", change.caption));
+                        }
+                        ui.add_space(4.0);
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    apply = ui.add_enabled(!run.changes.is_empty(), egui::Button::new("Apply Changes")).clicked();
+                    discard = ui.button("Discard").clicked();
+                });
+            });
+
+        if preview {
+            self.preview_script(ctx);
+        }
+        if apply {
+            self.apply_script_changes();
+        }
+        if discard {
+            self.script_preview = None;
+        }
+        if let Some(idx) = jump_to {
+            self.current_image_idx = idx;
+            self.change_image(ctx);
+        }
+        if !open {
+            self.script_console_open = false;
+        }
+    }
+
+    fn load_clip_tokenizer(&mut self, path: PathBuf) {
+        match ClipTokenizer::load(&path) {
+            Ok(tokenizer) => {
+                self.clip_tokenizer = Some(tokenizer);
+                self.tag_token_ends = None;
+                self.token_report = None;
+                self.app_settings.clip_tokenizer = Some(path.canonicalize().unwrap_or(path));
+                if let Err(err) = self.app_settings.save() {
+                    eprintln!("Failed to save settings: {}", err);
+                }
+            }
+            Err(err) => {
+                self.feedback_message = Some(format!("Failed to load {}: {}", path.display(), err));
+                self.feedback_timer = Some(std::time::Instant::now());
+            }
+        }
+    }
+
+    fn draw_tokenizer_controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Load CLIP Tokenizer")
+                .on_hover_text("merges.txt (or vocab.json beside it) from a CLIP model, or bpe_simple_vocab_16e6.txt(.gz)")
+                .clicked()
+            {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("BPE Files", &["txt", "json", "gz"])
+                    .pick_file()
+                {
+                    self.load_clip_tokenizer(path);
+                }
+            }
+            if let Some(tokenizer) = &self.clip_tokenizer {
+                ui.weak(format!("{} merges", tokenizer.merge_count()));
+                if ui.small_button("Token Report").clicked() {
+                    self.build_token_report();
+                }
+                if ui.small_button("✖").on_hover_text("Unload tokenizer").clicked() {
+                    self.clip_tokenizer = None;
+                    self.tag_token_ends = None;
+                    self.token_report = None;
+                    self.app_settings.clip_tokenizer = None;
+                    if let Err(err) = self.app_settings.save() {
+                        eprintln!("Failed to save settings: {}", err);
+                    }
+                }
+            }
+        });
+    }
+
+    fn build_token_report(&mut self) {
+        let Some(tokenizer) = &self.clip_tokenizer else {
+            return;
+        };
+        let mut counts: Vec<(usize, usize)> = self.images.par_iter()
+            .enumerate()
+            .map(|(idx, image)| (idx, tokenizer.count(&image.tags.join(", "))))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        self.token_report = Some(counts);
+    }
+
+    fn draw_token_report(&mut self, ctx: &egui::Context) {
+        let Some(report) = &self.token_report else {
+            return;
+        };
+
+        let limit = self.dataset_settings.token_limit;
+        let mut open = true;
+        let mut jump_to = None;
+        let mut refresh = false;
+        let show_all = &mut self.token_report_show_all;
+        egui::Window::new("Caption Token Counts")
+            .open(&mut open)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                let over: Vec<&(usize, usize)> = report.iter().filter(|(_, count)| *count > limit).collect();
+                if !report.is_empty() {
+                    let mean = report.iter().map(|(_, count)| count).sum::<usize>() as f32 / report.len() as f32;
+                    ui.label(format!("Longest {} tokens, average {:.1}", report[0].1, mean));
+                }
+                ui.label(format!("{} of {} images exceed the {}-token limit", over.len(), report.len(), limit));
+                ui.horizontal(|ui| {
+                    ui.checkbox(show_all, "Show all images");
+                    refresh = ui.button("Refresh").clicked();
+                });
+                ui.separator();
+
+                let rows = if *show_all { report.iter().collect() } else { over };
+                egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                    egui::Grid::new("token_report_grid").striped(true).show(ui, |ui| {
+                        for &&(idx, count) in &rows {
+                            let name = self.images[idx].path.file_name().unwrap_or_default().to_string_lossy();
+                            if ui.link(name).on_hover_text("Go to image").clicked() {
+                                jump_to = Some(idx);
+                            }
+                            let text = format!("{} tokens", count);
+                            if count > limit {
+                                ui.colored_label(ui.visuals().warn_fg_color, text);
+                            } else {
+                                ui.label(text);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+
+        if let Some(idx) = jump_to {
+            self.current_image_idx = idx;
+            self.change_image(ctx);
+        }
+        if refresh {
+            self.build_token_report();
+        }
+        if !open {
+            self.token_report = None;
+        }
+    }
+
+    fn draw_translation_controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Load Translations")
+                .on_hover_text("CSV of `tag,translation` rows for searching by localized names")
+                .clicked()
+            {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("CSV Files", &["csv"])
+                    .pick_file()
+                {
+                    self.load_translations(path);
+                }
+            }
+            let count = self.booru_manager.translation_count();
+            if count > 0 {
+                ui.weak(format!("{} localized names", count));
+                if ui.small_button("✖").on_hover_text("Unload translations").clicked() {
+                    self.booru_manager.set_translations(HashMap::new());
+                    self.app_settings.translation_file = None;
+                    if let Err(err) = self.app_settings.save() {
+                        eprintln!("Failed to save settings: {}", err);
+                    }
+                }
+            }
+        });
+    }
+
+    fn rebuild_cooccurrence(&mut self) {
+        self.dataset_cooccurrence = CooccurrenceMatrix::from_tag_lists(
+            self.images.iter().map(|image| image.tags.as_slice())
+        );
+        self.related_tags = None;
+    }
+
+    fn load_cooccurrence_table(&mut self, path: PathBuf) {
+        match CooccurrenceMatrix::load_table(&path) {
+            Ok(matrix) => {
+                self.booru_cooccurrence = matrix;
+                self.related_tags = None;
+                self.app_settings.cooccurrence_table = Some(path.canonicalize().unwrap_or(path));
+                if let Err(err) = self.app_settings.save() {
+                    eprintln!("Failed to save settings: {}", err);
+                }
+            }
+            Err(err) => {
+                self.feedback_message = Some(format!("Failed to load {}: {}", path.display(), err));
+                self.feedback_timer = Some(std::time::Instant::now());
+            }
+        }
+    }
+
+    // "Frequently used with" suggestions for the current image
+    fn draw_related_tags(&mut self, ui: &mut egui::Ui) {
+        const RELATED_LIMIT: usize = 12;
+
+        let mut add = None;
+        egui::CollapsingHeader::new("Related Tags")
+            .id_salt("related_tags")
+            .default_open(true)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.small_button("⟳ Refresh").on_hover_text("Recount co-occurrences across the dataset").clicked() {
+                        self.rebuild_cooccurrence();
+                    }
+                    if ui.small_button("Load Co-occurrence Table")
+                        .on_hover_text("CSV of `tag,other_tag,count` rows, e.g. built from a booru")
+                        .clicked()
+                    {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("CSV Files", &["csv"])
+                            .pick_file()
+                        {
+                            self.load_cooccurrence_table(path);
+                        }
+                    }
+                    if !self.booru_cooccurrence.is_empty() {
+                        ui.weak(format!("{} tags", self.booru_cooccurrence.tag_count()));
+                        if ui.small_button("✖").on_hover_text("Unload table").clicked() {
+                            self.booru_cooccurrence = CooccurrenceMatrix::default();
+                            self.related_tags = None;
+                            self.app_settings.cooccurrence_table = None;
+                            if let Err(err) = self.app_settings.save() {
+                                eprintln!("Failed to save settings: {}", err);
+                            }
+                        }
+                    }
+                });
+
+                let Some(current_image) = self.images.get(self.current_image_idx) else {
+                    return;
+                };
+                let revision = (self.current_image_idx, self.tags_revision, self.booru_manager.revision());
+                if self.related_tags.as_ref().is_none_or(|related| related.revision != revision) {
+                    let tag_count = |tag: &str| self.booru_manager.get_post_count(tag);
+                    self.related_tags = Some(RelatedTags {
+                        revision,
+                        dataset: self.dataset_cooccurrence.related(&current_image.tags, RELATED_LIMIT, tag_count),
+                        booru: self.booru_cooccurrence.related(&current_image.tags, RELATED_LIMIT, tag_count),
+                    });
+                }
+                let Some(related) = &self.related_tags else {
+                    return;
+                };
+
+                let lists = [
+                    ("In this dataset", &self.dataset_cooccurrence, &related.dataset),
+                    ("In the tag database", &self.booru_cooccurrence, &related.booru),
+                ];
+                for (heading, matrix, related) in lists {
+                    if matrix.is_empty() {
+                        continue;
+                    }
+                    ui.label(heading);
+                    if related.is_empty() {
+                        ui.weak("No related tags");
+                        continue;
+                    }
+                    ui.horizontal_wrapped(|ui| {
+                        for tag in related {
+                            let mut text = egui::RichText::new(format!("+ {} {:.0}%", tag.name, tag.score * 100.0));
+                            if let Some(category) = self.tag_category(&tag.name) {
+                                text = text.color(self.category_color(category));
+                            }
+                            let hover = format!("Seen together {} times", tag.count);
+                            if ui.small_button(text).on_hover_text(hover).clicked() {
+                                add = Some(tag.name.clone());
+                            }
+                        }
+                    });
+                }
+            });
+
+        if let Some(tag) = add {
+            let tag = self.dataset_settings.tag_style.apply(&tag);
+            self.apply_tag_action(TagAction::Add(tag));
+        }
+    }
+
+    // Loaded sources take their new order; configured ones that are missing or failed to load keep
+    // their place, so a file on an unplugged drive comes back on the next start
+    fn persist_tag_sources(&mut self) {
+        let loaded: Vec<PathBuf> = self.booru_manager.sources()
+            .iter()
+            .map(|source| source.path.clone())
+            .collect();
+        let mut next_loaded = loaded.iter();
+        let mut paths = Vec::new();
+        for path in &self.app_settings.tag_sources {
+            if !loaded.contains(path) {
+                paths.push(path.clone());
+            } else if let Some(loaded_path) = next_loaded.next() {
+                paths.push(loaded_path.clone());
+            }
+        }
+        paths.extend(next_loaded.cloned());
+        self.app_settings.tag_sources = paths;
+        if let Err(err) = self.app_settings.save() {
+            eprintln!("Failed to save settings: {}", err);
+        }
+    }
+
+    fn process_tag_database_messages(&mut self) {
+        let Some(rx) = &self.tag_db_receiver else {
+            return;
+        };
+
+        let mut finished = false;
+        while let Ok(message) = rx.try_recv() {
+            match message {
+                TagDatabaseMessage::Progress(progress) => {
+                    self.tag_db_progress = progress;
+                }
+                TagDatabaseMessage::Loaded { source } => {
+                    self.feedback_message = Some(if source.report.skipped_total > 0 {
+                        format!(
+                            "Loaded {} tags from {} ({} rows skipped)",
+                            source.tags.len(),
+                            source.path.display(),
+                            source.report.skipped_total
+                        )
+                    } else {
+                        format!("Loaded {} tags from {}", source.tags.len(), source.path.display())
+                    });
+                    self.feedback_timer = Some(std::time::Instant::now());
+                    self.booru_manager.add_source(*source);
+                }
+                TagDatabaseMessage::Error { path, error } => {
+                    self.feedback_message = Some(format!("Failed to load {}: {}", path.display(), error));
+                    self.feedback_timer = Some(std::time::Instant::now());
+                }
+                TagDatabaseMessage::Finished => {
+                    finished = true;
+                }
+            }
+        }
+
+        if finished {
+            self.tag_db_receiver = None;
+            self.persist_tag_sources();
+        }
+    }
+
+    // Configured sources that are not loaded, e.g. deleted files or ones that failed to parse
+    fn unavailable_tag_sources(&self) -> Vec<PathBuf> {
+        if self.tag_db_receiver.is_some() {
+            return Vec::new();
+        }
+        self.app_settings.tag_sources.iter()
+            .filter(|path| !self.booru_manager.sources().iter().any(|source| &source.path == *path))
+            .cloned()
+            .collect()
+    }
+
+    // Sources are listed in precedence order; the active checkboxes are remembered per dataset
+    fn draw_tag_sources(&mut self, ui: &mut egui::Ui) {
+        let source_count = self.booru_manager.sources().len();
+        let unavailable = self.unavailable_tag_sources();
+        let mut action = None;
+        let mut open_report = None;
+
+        egui::CollapsingHeader::new(format!("Tag Sources ({})", source_count))
+            .id_salt("tag_sources")
+            .show(ui, |ui| {
+                for (idx, source) in self.booru_manager.sources().iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        let mut active = self.booru_manager.is_source_active(&source.path);
+                        if ui.checkbox(&mut active, &source.name)
+                            .on_hover_text(source.path.display().to_string())
+                            .changed()
+                        {
+                            action = Some(TagSourceAction::Toggle(idx, active));
+                        }
+                        ui.weak(format!("{} tags", source.tags.len()))
+                            .on_hover_text(&source.report.schema);
+                        if source.report.skipped_total > 0
+                            && ui.small_button(format!("⚠ {} skipped", source.report.skipped_total)).clicked()
+                        {
+                            open_report = Some(source.name.clone());
+                        }
+
+                        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                            if ui.small_button("✖").on_hover_text("Remove source").clicked() {
+                                action = Some(TagSourceAction::Remove(idx));
+                            }
+                            if ui.add_enabled(idx + 1 < source_count, egui::Button::new("⬇").small()).clicked() {
+                                action = Some(TagSourceAction::MoveDown(idx));
+                            }
+                            if ui.add_enabled(idx > 0, egui::Button::new("⬆").small()).clicked() {
+                                action = Some(TagSourceAction::MoveUp(idx));
+                            }
+                        });
+                    });
+                }
+                for (idx, path) in unavailable.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            format!("⚠ {}", path.file_name().unwrap_or_default().to_string_lossy()),
+                        )
+                        .on_hover_text(format!("{} is missing or could not be read", path.display()));
+                        ui.weak("unavailable");
+
+                        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                            if ui.small_button("✖").on_hover_text("Forget source").clicked() {
+                                action = Some(TagSourceAction::Forget(idx));
+                            }
+                            if ui.small_button("Retry").clicked() {
+                                action = Some(TagSourceAction::Retry(idx));
+                            }
+                        });
+                    });
+                }
+                ui.weak("Higher sources take precedence for suggestions and tag info");
+            });
+
+        if open_report.is_some() {
+            self.tag_source_report = open_report;
+        }
+
+        match action {
+            Some(TagSourceAction::Toggle(idx, active)) => {
+                let path = self.booru_manager.sources()[idx].path.clone();
+                self.booru_manager.set_source_active(&path, active);
+                self.dataset_settings.disabled_tag_sources = self.booru_manager.disabled_sources();
+                if let Some(dir) = &self.current_dir {
+                    if let Err(err) = self.dataset_settings.save(dir) {
+                        eprintln!("Failed to save dataset settings: {}", err);
+                    }
+                }
+            }
+            Some(TagSourceAction::MoveUp(idx)) => {
+                self.booru_manager.move_source(idx, idx - 1);
+                self.persist_tag_sources();
+            }
+            Some(TagSourceAction::MoveDown(idx)) => {
+                self.booru_manager.move_source(idx, idx + 1);
+                self.persist_tag_sources();
+            }
+            Some(TagSourceAction::Remove(idx)) => {
+                let path = self.booru_manager.sources()[idx].path.clone();
+                self.app_settings.tag_sources.retain(|configured| configured != &path);
+                self.booru_manager.remove_source(idx);
+                self.persist_tag_sources();
+            }
+            Some(TagSourceAction::Retry(idx)) => {
+                self.start_tag_database_load(ui.ctx(), vec![unavailable[idx].clone()]);
+            }
+            Some(TagSourceAction::Forget(idx)) => {
+                self.app_settings.tag_sources.retain(|configured| configured != &unavailable[idx]);
+                self.persist_tag_sources();
+            }
+            None => {}
+        }
     }
 
     fn apply_current_sorting(&mut self) {
@@ -189,22 +2008,57 @@ impl ImageTagger {
                         });
                     }
                 }
+                self.tags_revision += 1;
             }
         }
     }
 
-    fn load_tags_for_image(&self, image_path: &Path) -> Result<Vec<String>, std::io::Error> {
-        let tags_path = image_path.with_extension("txt");
-        if tags_path.exists() {
+    fn load_tags_for_image(&self, image_path: &Path, variant: usize) -> Result<(Vec<String>, String), std::io::Error> {
+        let format = self.dataset_settings.caption_format;
+        let tags_path = DatasetSettings::sidecar_path(image_path, variant, "txt");
+        let (tags, mut caption) = if tags_path.exists() {
             let content = fs::read_to_string(tags_path)?;
-            Ok(content
-                .split(',')
-                .map(|tag| tag.trim().to_string())
-                .filter(|tag| !tag.is_empty())
-                .collect())
+            let (tag_line, caption) = format.split(&content);
+            (Self::process_tags_text(tag_line), caption.to_string())
         } else {
-            Ok(Vec::new())
+            (Vec::new(), String::new())
+        };
+
+        let caption_path = DatasetSettings::sidecar_path(image_path, variant, "caption");
+        if format == CaptionFormat::SeparateFiles && caption_path.exists() {
+            caption = fs::read_to_string(caption_path)?.trim().to_string();
+        }
+
+        Ok((tags, caption))
+    }
+
+    // Loads the extra variants; slot 0 is left empty since it is active after loading
+    fn load_variants_for_image(&self, image_path: &Path) -> Result<Vec<CaptionVariant>, std::io::Error> {
+        let names = &self.dataset_settings.caption_variants;
+        let mut variants = vec![CaptionVariant::default(); names.len()];
+
+        match self.dataset_settings.variant_storage {
+            VariantStorage::NumberedSidecars => {
+                for (idx, variant) in variants.iter_mut().enumerate().skip(1) {
+                    let (tags, caption) = self.load_tags_for_image(image_path, idx)?;
+                    *variant = CaptionVariant { tags, caption };
+                }
+            }
+            VariantStorage::Json => {
+                let json_path = DatasetSettings::variants_json_path(image_path);
+                if json_path.exists() {
+                    let mut stored: HashMap<String, CaptionVariant> =
+                        serde_json::from_str(&fs::read_to_string(json_path)?)?;
+                    for (idx, name) in names.iter().enumerate().skip(1) {
+                        if let Some(variant) = stored.remove(name) {
+                            variants[idx] = variant;
+                        }
+                    }
+                }
+            }
         }
+
+        Ok(variants)
     }
 
     fn start_background_caching(&mut self) {
@@ -214,9 +2068,7 @@ impl ImageTagger {
         }
 
         let (tx, rx) = std::sync::mpsc::channel();
-        let (progress_tx, progress_rx) = std::sync::mpsc::channel();
         self.decoded_receiver = Some(rx);
-        self.progress_receiver = Some(progress_rx);
 
         let images = self.images.clone();
         let cached_count = self.cached_images_count.clone();
@@ -227,13 +2079,9 @@ impl ImageTagger {
                 let chunk_end = (chunk_start + chunk_size).min(total_images);
                 let chunk_indices: Vec<_> = (chunk_start..chunk_end).collect();
 
-                chunk_indices.into_par_iter().for_each_with((tx.clone(), progress_tx.clone()), |(tx, progress_tx), idx| {
-                    let _ = progress_tx.send(CacheProgress::Started { idx });
-
+                chunk_indices.into_par_iter().for_each_with(tx.clone(), |tx, idx| {
                     if let Some(image) = images.get(idx) {
                         let start = std::time::Instant::now();
-                        let _ = progress_tx.send(CacheProgress::Loading { idx });
-
                         match ImageReader::open(&image.path) {
                             Ok(img_reader) => {
                                 match img_reader.decode() {
@@ -271,7 +2119,6 @@ impl ImageTagger {
                                             println!("Decoded in {:?}: {}", start.elapsed(), image.path.display());
                                             let mut count = cached_count.lock().unwrap();
                                             *count += 1;
-                                            let _ = progress_tx.send(CacheProgress::Completed { idx });
                                         }
                                     }
                                     Err(e) => {
@@ -279,7 +2126,6 @@ impl ImageTagger {
                                             idx,
                                             error: format!("Decode error: {}", e),
                                         });
-                                        let _ = progress_tx.send(CacheProgress::Error { idx });
                                     }
                                 }
                             }
@@ -288,7 +2134,6 @@ impl ImageTagger {
                                     idx,
                                     error: format!("Open error: {}", e),
                                 });
-                                let _ = progress_tx.send(CacheProgress::Error { idx });
                             }
                         }
                     }
@@ -302,6 +2147,10 @@ impl ImageTagger {
         let mut has_text_focus = false;
         ctx.memory(|mem| {
             has_text_focus = mem.has_focus(egui::Id::new("text_editor")) ||
+                mem.has_focus(egui::Id::new("caption_editor")) ||
+                mem.has_focus(egui::Id::new("search_bar")) ||
+                mem.has_focus(egui::Id::new("tag_rename")) ||
+                mem.has_focus(egui::Id::new("script_editor")) ||
                 mem.has_focus(egui::Id::new("tag_panel"));
         });
 
@@ -315,6 +2164,16 @@ impl ImageTagger {
             }
         }
 
+        if self.review_queue.is_some() && !has_text_focus && !ctx.wants_keyboard_input() {
+            if ctx.input(|i| i.key_pressed(egui::Key::A)) {
+                self.review_tag(ctx, None, true);
+            } else if ctx.input(|i| i.key_pressed(egui::Key::R)) {
+                self.review_tag(ctx, None, false);
+            } else if ctx.input(|i| i.key_pressed(egui::Key::N)) {
+                self.advance_review(ctx);
+            }
+        }
+
         // Keyboard shortcuts
         if ctx.input(|i| i.modifiers.command && i.key_pressed(egui::Key::S)) {
             self.save_all();
@@ -333,21 +2192,13 @@ impl ImageTagger {
             ctx.request_repaint();
         }
 
-        // Process duplicate detection results
-        if let Some(rx) = &self.duplicate_rx {
-            if let Ok(DuplicateMessage::Found { duplicates }) = rx.try_recv() {
-                self.duplicate_images = duplicates;
-                let count = self.duplicate_images.values()
-                    .map(|v| v.len())
-                    .sum::<usize>();
-
-                self.feedback_message = Some(format!(
-                    "Found {} duplicate images. Click 'Remove Duplicates' to delete them.",
-                    count
-                ));
-                self.feedback_timer = Some(std::time::Instant::now());
-            }
-        }
+        self.process_tag_database_messages();
+        self.process_wiki_messages();
+        self.process_tagger_load_messages();
+        self.process_auto_tag_messages();
+        self.process_captioner_messages();
+        self.process_composition_messages();
+        self.process_script_messages();
 
         // Process cached images
         if let Some(rx) = &self.decoded_receiver {
@@ -376,6 +2227,9 @@ impl ImageTagger {
                     }
                     CacheMessage::Error { idx, error } => {
                         eprintln!("Error caching image {}: {}", idx, error);
+                        if let Some(image) = self.images.get(idx) {
+                            self.image_errors.insert(image.path.clone(), error);
+                        }
                     }
                 }
             }
@@ -386,6 +2240,90 @@ impl ImageTagger {
         self.draw_left_panel(ctx);
         self.draw_central_panel(ctx);
         self.draw_right_panel(ctx);
+        self.draw_style_report(ctx);
+        self.draw_tag_source_report(ctx);
+        self.draw_rule_report(ctx);
+        self.draw_policy_report(ctx);
+        self.draw_lint_report(ctx);
+        self.draw_token_report(ctx);
+        self.draw_composition(ctx);
+        self.draw_review(ctx);
+        self.draw_script_console(ctx);
+    }
+
+    fn draw_style_report(&mut self, ctx: &egui::Context) {
+        let mut open = self.style_report.is_some();
+        if let Some(report) = &self.style_report {
+            egui::Window::new("Tag Style Conversion")
+                .open(&mut open)
+                .default_width(450.0)
+                .show(ctx, |ui| {
+                    if report.is_empty() {
+                        ui.label("All tags already match the output style.");
+                        return;
+                    }
+
+                    let total: usize = report.iter().map(|(_, _, count)| count).sum();
+                    ui.label(format!("Converted {} occurrences of {} distinct tags:", total, report.len()));
+                    ui.separator();
+                    egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                        egui::Grid::new("style_report_grid").striped(true).show(ui, |ui| {
+                            ui.strong("Before");
+                            ui.strong("After");
+                            ui.strong("Count");
+                            ui.end_row();
+                            for (before, after, count) in report {
+                                ui.label(before);
+                                ui.label(after);
+                                ui.label(count.to_string());
+                                ui.end_row();
+                            }
+                        });
+                    });
+                });
+        }
+        if !open {
+            self.style_report = None;
+        }
+    }
+
+    fn draw_tag_source_report(&mut self, ctx: &egui::Context) {
+        let Some(name) = &self.tag_source_report else {
+            return;
+        };
+        let Some(source) = self.booru_manager.sources().iter().find(|source| &source.name == name) else {
+            self.tag_source_report = None;
+            return;
+        };
+
+        let mut open = true;
+        let report = &source.report;
+        egui::Window::new(format!("Load Report: {}", source.name))
+            .open(&mut open)
+            .default_width(450.0)
+            .show(ctx, |ui| {
+                ui.label(format!("Detected layout: {}", report.schema));
+                ui.label(format!("Loaded {} tags, skipped {} rows", report.loaded, report.skipped_total));
+                if report.skipped_total > report.skipped.len() {
+                    ui.weak(format!("Showing the first {} skipped rows", report.skipped.len()));
+                }
+                ui.separator();
+                egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                    egui::Grid::new("tag_source_report_grid").striped(true).show(ui, |ui| {
+                        ui.strong("Line");
+                        ui.strong("Reason");
+                        ui.end_row();
+                        for row in &report.skipped {
+                            ui.label(row.line.to_string());
+                            ui.label(&row.reason);
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+        if !open {
+            self.tag_source_report = None;
+        }
     }
 
     fn draw_top_panel(&mut self, ctx: &egui::Context) {
@@ -399,6 +2337,19 @@ impl ImageTagger {
                 if ui.button("Backup").clicked() {
                     self.backup_dataset();
                 }
+                if ui.add_enabled(self.current_dir.is_some(), egui::Button::new("Validate")).clicked() {
+                    self.validate_dataset();
+                }
+                let has_confidences = self.images.iter().any(|image| !image.confidences.is_empty());
+                if ui.add_enabled(has_confidences, egui::Button::new("Review Tags"))
+                    .on_hover_text("Walk the auto-tagged tags below the review threshold, least certain first")
+                    .clicked()
+                {
+                    self.start_review(ctx);
+                }
+                if ui.button("Script Console").clicked() {
+                    self.script_console_open = true;
+                }
 
                 ui.separator();
                 ui.label("Activation tag:");
@@ -458,6 +2409,8 @@ impl ImageTagger {
                         ui.label(format!("Image {}/{}", self.current_image_idx + 1, self.images.len()));
                     });
                     ui.separator();
+                    self.draw_search_bar(ui);
+                    ui.separator();
                 }
 
                 ui.heading("Current Image");
@@ -533,7 +2486,43 @@ impl ImageTagger {
         }
     }
 
+    // Enforces the dataset output style and tag policy on every modified image, right before it is
+    // written
+    fn enforce_output_rules(&mut self) {
+        let policy = &self.dataset_settings.tag_policy;
+        if policy.strip_forbidden && !policy.forbidden.is_empty() {
+            for image in &mut self.images {
+                if !*self.modified_files.get(&image.path).unwrap_or(&false) {
+                    continue;
+                }
+                for tags in image.variant_tag_lists_mut(self.active_variant, true) {
+                    policy.strip_forbidden_tags(tags);
+                }
+            }
+        }
+        let style = self.dataset_settings.tag_style;
+        if !style.is_keep() {
+            for image in &mut self.images {
+                if !*self.modified_files.get(&image.path).unwrap_or(&false) {
+                    continue;
+                }
+                for tags in image.variant_tag_lists_mut(self.active_variant, true) {
+                    for tag in tags.iter_mut() {
+                        *tag = style.apply(tag);
+                    }
+                }
+            }
+        }
+    }
+
     fn save_all(&mut self) {
+        if let Some(dir) = &self.current_dir {
+            if let Err(err) = self.dataset_settings.save(dir) {
+                eprintln!("Failed to save dataset settings: {}", err);
+            }
+        }
+
+        self.enforce_output_rules();
         for image in &self.images {
             if *self.modified_files.get(&image.path).unwrap_or(&false) {
                 if let Err(err) = self.save_tags_for_image(image) {
@@ -544,6 +2533,16 @@ impl ImageTagger {
                 }
             }
         }
+        self.rebuild_cooccurrence();
+
+        // Saved anyway; Tag Policy > Check Dataset lists them for fixing
+        let policy = &self.dataset_settings.tag_policy;
+        let missing = self.images.iter().filter(|image| !policy.missing(&image.tags).is_empty()).count();
+        if missing > 0 {
+            self.feedback_message = Some(format!("All changes saved; {} images are missing required tags (see Tag Policy)", missing));
+            self.feedback_timer = Some(std::time::Instant::now());
+            return;
+        }
         self.feedback_message = Some("All changes saved successfully!".to_string());
         self.feedback_timer = Some(std::time::Instant::now());
     }
@@ -585,53 +2584,593 @@ impl ImageTagger {
             }
 
             for image in &self.images {
-                let tags_path = image.path.with_extension("txt");
+                if let Err(err) = fs::copy(&image.path, backup_dir.join(image.path.file_name().unwrap())) {
+                    self.feedback_message = Some(format!("Error during backup: {}", err));
+                    self.feedback_timer = Some(std::time::Instant::now());
+                    self.resume_caching();
+                    return;
+                }
+
+                let confidence_path = tag_confidence::sidecar_path(&image.path);
+                for sidecar_path in self.dataset_settings.sidecar_paths(&image.path).into_iter().chain([confidence_path]) {
+                    if !sidecar_path.exists() {
+                        continue;
+                    }
+                    if let Err(err) = fs::copy(&sidecar_path, backup_dir.join(sidecar_path.file_name().unwrap())) {
+                        self.feedback_message = Some(format!("Error during backup: {}", err));
+                        self.feedback_timer = Some(std::time::Instant::now());
+                        self.resume_caching();
+                        return;
+                    }
+                }
+            }
+
+            self.current_texture = current_texture;
+            self.feedback_message = Some("Backup completed successfully!".to_string());
+            self.feedback_timer = Some(std::time::Instant::now());
+            self.resume_caching();
+        }
+    }
+
+    // Shared path for bulk edits: runs `edit` on every image's tag list (and its other caption
+    // variants when bulk edits apply to all of them) and marks the images it reports as changed.
+    // Edits that would drop a required tag are undone. Returns the number of images changed and
+    // the number left untouched to keep their required tags.
+    fn edit_all_tags(&mut self, mut edit: impl FnMut(&Path, &mut Vec<String>) -> bool) -> (usize, usize) {
+        let policy = &self.dataset_settings.tag_policy;
+        let mut modified_count = 0;
+        let mut protected_count = 0;
+        for image in &mut self.images {
+            let path = image.path.clone();
+            let mut changed = false;
+            let mut protected = false;
+            for tags in image.variant_tag_lists_mut(self.active_variant, self.bulk_all_variants) {
+                let before = tags.clone();
+                if !edit(&path, tags) {
+                    continue;
+                }
+                if policy.broken_requirements(&before, tags).is_empty() {
+                    changed = true;
+                } else {
+                    *tags = before;
+                    protected = true;
+                }
+            }
+            if changed {
+                self.modified_files.insert(path, true);
+                self.tags_revision += 1;
+                modified_count += 1;
+            }
+            if protected {
+                protected_count += 1;
+            }
+        }
+        (modified_count, protected_count)
+    }
+
+    // Evaluates the rules over every image; a dry run only collects what would change
+    fn run_rules(&mut self, rules: &RuleSet, dry_run: bool) -> RuleReport {
+        let policy = self.dataset_settings.tag_policy.clone();
+        let mut images: Vec<(PathBuf, Vec<RuleChange>)> = Vec::new();
+        self.edit_all_tags(|path, tags| {
+            let mut edited = tags.clone();
+            let mut changes = rules.apply(&mut edited);
+            if changes.is_empty() {
+                return false;
+            }
+            let broken = policy.broken_requirements(tags, &edited);
+            if !broken.is_empty() {
+                changes = vec![RuleChange {
+                    rule: "tag policy".to_string(),
+                    description: format!("skipped, would remove required {}", broken.join(", ")),
+                }];
+            }
+            match images.last_mut().filter(|(last, _)| last == path) {
+                Some((_, existing)) => existing.extend(changes),
+                None => images.push((path.to_path_buf(), changes)),
+            }
+            if dry_run || !broken.is_empty() {
+                return false;
+            }
+            *tags = edited;
+            true
+        });
+        RuleReport { dry_run, images }
+    }
+
+    fn run_rules_file(&mut self, dry_run: bool) {
+        let Some(path) = self.dataset_settings.rules_file.clone() else {
+            return;
+        };
+        match RuleSet::load(&path) {
+            Ok(rules) => {
+                let report = self.run_rules(&rules, dry_run);
+                if !dry_run {
+                    self.feedback_message = Some(format!("Rules changed {} images", report.images.len()));
+                    self.feedback_timer = Some(std::time::Instant::now());
+                }
+                self.rule_report = Some(report);
+            }
+            Err(err) => {
+                self.feedback_message = Some(format!("Failed to load rules {}: {}", path.display(), err));
+                self.feedback_timer = Some(std::time::Instant::now());
+            }
+        }
+    }
+
+    fn draw_rules(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Tag Rules")
+            .id_salt("tag_rules")
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Choose Rules File").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("Rule Files", &["toml", "json"])
+                            .pick_file()
+                        {
+                            self.dataset_settings.rules_file = Some(path);
+                            self.save_dataset_settings();
+                        }
+                    }
+                    if let Some(path) = &self.dataset_settings.rules_file {
+                        ui.weak(path.file_name().unwrap_or_default().to_string_lossy())
+                            .on_hover_text(path.display().to_string());
+                    }
+                });
+                ui.add_enabled_ui(self.dataset_settings.rules_file.is_some(), |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.button("Dry Run").clicked() {
+                            self.run_rules_file(true);
+                        }
+                        if ui.button("Apply Rules").clicked() {
+                            self.run_rules_file(false);
+                        }
+                    });
+                });
+            });
+    }
+
+    fn lint(&self, decode: bool) -> Option<LintReport> {
+        let dir = self.current_dir.as_ref()?;
+        let images: Vec<LintImage> = self.images.iter()
+            .map(|image| LintImage { path: &image.path, tags: &image.tags })
+            .collect();
+        let is_known = |name: &str| self.booru_manager.get_category(name).is_some();
+        let is_known: Option<&dyn Fn(&str) -> bool> = if self.booru_manager.has_tags() { Some(&is_known) } else { None };
+        Some(dataset_lint::lint_dataset(dir, &self.dataset_settings, &self.dataset_settings.lint, &images, is_known, decode))
+    }
+
+    fn validate_dataset(&mut self) {
+        let Some(mut report) = self.lint(false) else {
+            return;
+        };
+        report.add_decode_errors(&self.image_errors);
+        self.lint_report = Some(report);
+    }
+
+    fn export_lint_report(&mut self, json: bool) {
+        let Some(report) = &self.lint_report else {
+            return;
+        };
+        let (name, extension) = if json { ("JSON", "json") } else { ("CSV", "csv") };
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter(name, &[extension])
+            .set_file_name(format!("dataset-report.{}", extension))
+            .save_file()
+        else {
+            return;
+        };
+        let result = if json {
+            report.write_json(&path)
+        } else {
+            fs::File::create(&path).map_err(Into::into).and_then(|file| report.write_csv(file))
+        };
+        self.feedback_message = Some(match result {
+            Ok(()) => format!("Report saved to {}", path.display()),
+            Err(err) => format!("Failed to save report: {}", err),
+        });
+        self.feedback_timer = Some(std::time::Instant::now());
+    }
+
+    fn draw_lint_report(&mut self, ctx: &egui::Context) {
+        let Some(report) = &self.lint_report else {
+            return;
+        };
+
+        let mut open = true;
+        let mut jump_to = None;
+        let mut rerun = false;
+        let mut options_changed = false;
+        let mut export = None;
+        let options = &mut self.dataset_settings.lint;
+        egui::Window::new("Dataset Validation")
+            .open(&mut open)
+            .default_width(500.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Min side:");
+                    options_changed |= ui.add(egui::DragValue::new(&mut options.min_side).range(0..=8192).suffix(" px"))
+                        .changed();
+                    ui.label("Max aspect ratio:");
+                    options_changed |= ui.add(egui::DragValue::new(&mut options.max_aspect_ratio).speed(0.05).range(1.0..=20.0))
+                        .changed();
+                });
+                ui.horizontal(|ui| {
+                    rerun |= ui.button("Re-run").clicked();
+                    if ui.button("Export JSON").clicked() {
+                        export = Some(true);
+                    }
+                    if ui.button("Export CSV").clicked() {
+                        export = Some(false);
+                    }
+                });
+                if !self.modified_files.is_empty() {
+                    ui.weak("Tag checks include unsaved edits; caption file checks reflect what is on disk.");
+                }
+                ui.separator();
+
+                if report.issues.is_empty() {
+                    ui.label(format!("No problems found in {} images.", report.images));
+                    return;
+                }
+                ui.label(format!("{} problems in {} images", report.issues.len(), report.images));
+                let counts = report.counts();
+                egui::ScrollArea::vertical().max_height(450.0).show(ui, |ui| {
+                    for (kind, count) in counts {
+                        egui::CollapsingHeader::new(format!("{} ({})", kind.label(), count))
+                            .id_salt(("lint", kind))
+                            .show(ui, |ui| {
+                                for issue in report.issues.iter().filter(|issue| issue.kind == kind) {
+                                    ui.horizontal(|ui| {
+                                        let name = issue.path.file_name().unwrap_or_default().to_string_lossy();
+                                        match self.images.iter().position(|image| image.path == issue.path) {
+                                            Some(idx) => {
+                                                if ui.link(name).on_hover_text("Go to image").clicked() {
+                                                    jump_to = Some(idx);
+                                                }
+                                            }
+                                            None => {
+                                                ui.label(name).on_hover_text(issue.path.display().to_string());
+                                            }
+                                        }
+                                        ui.weak(&issue.detail);
+                                    });
+                                }
+                            });
+                    }
+                });
+            });
+
+        if let Some(idx) = jump_to {
+            self.current_image_idx = idx;
+            self.change_image(ctx);
+        }
+        if let Some(json) = export {
+            self.export_lint_report(json);
+        }
+        if options_changed {
+            self.save_dataset_settings();
+        }
+        if rerun {
+            self.validate_dataset();
+        }
+        if !open {
+            self.lint_report = None;
+        }
+    }
+
+    fn check_tag_policy(&mut self) {
+        let policy = &self.dataset_settings.tag_policy;
+        let violations = self.images.iter()
+            .enumerate()
+            .filter_map(|(idx, image)| policy.check(&image.tags).map(|violation| (idx, violation)))
+            .collect();
+        self.policy_report = Some(violations);
+    }
+
+    fn save_dataset_settings(&mut self) {
+        if let Some(dir) = &self.current_dir {
+            if let Err(err) = self.dataset_settings.save(dir) {
+                eprintln!("Failed to save dataset settings: {}", err);
+            }
+        }
+    }
+
+    fn draw_tag_policy(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Tag Policy")
+            .id_salt("tag_policy")
+            .show(ui, |ui| {
+                let policy = &mut self.dataset_settings.tag_policy;
+                let mut changed = false;
+                egui::Grid::new("tag_policy_grid").num_columns(2).show(ui, |ui| {
+                    for (label, hint, list) in [
+                        ("Forbidden:", "tag, tag", &mut policy.forbidden),
+                        ("Required:", "tag, rating:general | rating:safe", &mut policy.required),
+                    ] {
+                        ui.label(label);
+                        let mut text = list.join(", ");
+                        let response = ui.add(egui::TextEdit::singleline(&mut text)
+                            .hint_text(hint)
+                            .desired_width(200.0));
+                        if response.changed() {
+                            *list = text.split(',').map(|t| t.trim().to_string()).collect();
+                        }
+                        if response.lost_focus() {
+                            list.retain(|t| !t.is_empty());
+                            changed = true;
+                        }
+                        ui.end_row();
+                    }
+                });
+                changed |= ui.checkbox(&mut policy.strip_forbidden, "Strip forbidden tags when adding and saving")
+                    .changed();
+                if changed {
+                    self.save_dataset_settings();
+                }
+
+                ui.add_enabled_ui(!self.dataset_settings.tag_policy.is_empty(), |ui| {
+                    if ui.button("Check Dataset").clicked() {
+                        self.check_tag_policy();
+                    }
+                });
+            });
+    }
+
+    fn draw_policy_report(&mut self, ctx: &egui::Context) {
+        let Some(report) = &self.policy_report else {
+            return;
+        };
+
+        let mut open = true;
+        let mut jump_to = None;
+        let mut strip = false;
+        let mut fill = false;
+        egui::Window::new("Tag Policy Violations")
+            .open(&mut open)
+            .default_width(450.0)
+            .show(ctx, |ui| {
+                if report.is_empty() {
+                    ui.label("Every image follows the tag policy.");
+                    return;
+                }
+                ui.label(format!("{} of {} images break the tag policy", report.len(), self.images.len()));
+                ui.horizontal(|ui| {
+                    strip = ui.button("Strip Forbidden Tags").clicked();
+                    fill = ui.button("Add Missing Required Tags")
+                        .on_hover_text("Required entries with alternatives have to be chosen per image")
+                        .clicked();
+                });
+                ui.separator();
+                egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                    egui::Grid::new("policy_report_grid").striped(true).show(ui, |ui| {
+                        ui.strong("Image");
+                        ui.strong("Missing");
+                        ui.strong("Forbidden");
+                        ui.end_row();
+                        for (idx, violation) in report {
+                            let name = self.images[*idx].path.file_name().unwrap_or_default().to_string_lossy();
+                            if ui.link(name).on_hover_text("Go to image").clicked() {
+                                jump_to = Some(*idx);
+                            }
+                            ui.label(violation.missing.join(", "));
+                            ui.label(violation.forbidden.join(", "));
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+
+        if let Some(idx) = jump_to {
+            self.current_image_idx = idx;
+            self.change_image(ctx);
+        }
+        if strip || fill {
+            let policy = self.dataset_settings.tag_policy.clone();
+            let (modified_count, _) = self.edit_all_tags(|_, tags| {
+                if strip {
+                    policy.strip_forbidden_tags(tags)
+                } else {
+                    policy.add_missing_tags(tags)
+                }
+            });
+            self.feedback_message = Some(format!("Updated {} images", modified_count));
+            self.feedback_timer = Some(std::time::Instant::now());
+            self.check_tag_policy();
+        }
+        if !open {
+            self.policy_report = None;
+        }
+    }
+
+    fn draw_rule_report(&mut self, ctx: &egui::Context) {
+        let Some(report) = &self.rule_report else {
+            return;
+        };
+
+        let mut open = true;
+        let mut apply = false;
+        let title = if report.dry_run { "Rule Dry Run" } else { "Rule Results" };
+        egui::Window::new(title)
+            .open(&mut open)
+            .default_width(450.0)
+            .show(ctx, |ui| {
+                let change_count: usize = report.images.iter().map(|(_, changes)| changes.len()).sum();
+                let verb = if report.dry_run { "would change" } else { "changed" };
+                ui.label(format!("Rules {} {} images ({} edits)", verb, report.images.len(), change_count));
+                if report.dry_run && !report.images.is_empty() && ui.button("Apply These Changes").clicked() {
+                    apply = true;
+                }
+                ui.separator();
+                egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                    for (path, changes) in &report.images {
+                        ui.strong(path.file_name().unwrap_or_default().to_string_lossy());
+                        for change in changes {
+                            ui.label(format!("  [{}] {}", change.rule, change.description));
+                        }
+                    }
+                });
+            });
+
+        if !open {
+            self.rule_report = None;
+        }
+        if apply {
+            self.run_rules_file(false);
+        }
+    }
+
+    fn remove_tag_from_all(&mut self, tag: &str) {
+        let (modified_count, protected_count) = self.edit_all_tags(|_, tags| {
+            let before = tags.len();
+            tags.retain(|t| t != tag);
+            tags.len() != before
+        });
+        let mut message = format!("Removed tag '{}' from {} images", tag, modified_count);
+        if protected_count > 0 {
+            message.push_str(&format!(", kept on {} where it is required", protected_count));
+        }
+        self.feedback_message = Some(message);
+        self.feedback_timer = Some(std::time::Instant::now());
+    }
+
+    fn save_tags_for_image(&self, image_data: &ImageData) -> Result<(), std::io::Error> {
+        let names = &self.dataset_settings.caption_variants;
+        let storage = self.dataset_settings.variant_storage;
+
+        for idx in 0..names.len() {
+            if idx > 0 && storage == VariantStorage::Json {
+                break;
+            }
+            let (tags, caption) = image_data.variant(idx, self.active_variant);
+            self.save_sidecars(&image_data.path, idx, tags, caption)?;
+        }
+
+        if storage == VariantStorage::Json && names.len() > 1 {
+            let stored: HashMap<&str, CaptionVariant> = names.iter()
+                .enumerate()
+                .skip(1)
+                .map(|(idx, name)| {
+                    let (tags, caption) = image_data.variant(idx, self.active_variant);
+                    (name.as_str(), CaptionVariant { tags: tags.to_vec(), caption: caption.to_string() })
+                })
+                .collect();
+            fs::write(
+                DatasetSettings::variants_json_path(&image_data.path),
+                serde_json::to_string_pretty(&stored)?,
+            )?;
+        }
+
+        let all_tags: Vec<&String> = (0..names.len())
+            .flat_map(|idx| image_data.variant(idx, self.active_variant).0)
+            .collect();
+        tag_confidence::save_sidecar(&image_data.path, &image_data.confidences, &all_tags)
+    }
 
-                if let Err(err) = fs::copy(&image.path, backup_dir.join(image.path.file_name().unwrap())) {
-                    self.feedback_message = Some(format!("Error during backup: {}", err));
-                    self.feedback_timer = Some(std::time::Instant::now());
-                    self.resume_caching();
-                    return;
-                }
+    fn save_sidecars(&self, image_path: &Path, variant: usize, tags: &[String], caption: &str) -> Result<(), std::io::Error> {
+        let format = self.dataset_settings.caption_format;
+        let tags_path = DatasetSettings::sidecar_path(image_path, variant, "txt");
+        fs::write(tags_path, format.compose(tags, caption))?;
 
-                if let Err(err) = fs::copy(&tags_path, backup_dir.join(tags_path.file_name().unwrap())) {
-                    self.feedback_message = Some(format!("Error during backup: {}", err));
-                    self.feedback_timer = Some(std::time::Instant::now());
-                    self.resume_caching();
-                    return;
-                }
+        if format == CaptionFormat::SeparateFiles {
+            let caption_path = DatasetSettings::sidecar_path(image_path, variant, "caption");
+            if !caption.trim().is_empty() {
+                fs::write(caption_path, caption.trim())?;
+            } else if caption_path.exists() {
+                fs::remove_file(caption_path)?;
             }
+        }
+        Ok(())
+    }
 
-            self.current_texture = current_texture;
-            self.feedback_message = Some("Backup completed successfully!".to_string());
-            self.feedback_timer = Some(std::time::Instant::now());
-            self.resume_caching();
+    fn switch_variant(&mut self, idx: usize) {
+        if idx == self.active_variant || idx >= self.dataset_settings.caption_variants.len() {
+            return;
+        }
+        for image in &mut self.images {
+            image.switch_variant(self.active_variant, idx);
         }
+        self.active_variant = idx;
+        self.tags_revision += 1;
     }
-    fn get_tag_frequencies_for_image(&self, image: &ImageData) -> HashMap<String, usize> {
-        let mut freq_map: HashMap<String, usize> = HashMap::new();
-        for tag in &image.tags {
-            *freq_map.entry(tag.clone()).or_insert(0) += 1;
+
+    fn add_variant(&mut self) {
+        let name = self.new_variant_name.trim().to_string();
+        if name.is_empty() || self.dataset_settings.caption_variants.contains(&name) {
+            self.feedback_message = Some(format!("Variant name '{}' is empty or already in use", name));
+            self.feedback_timer = Some(std::time::Instant::now());
+            return;
         }
-        freq_map
+
+        self.dataset_settings.caption_variants.push(name.clone());
+        for image in &mut self.images {
+            image.variants.push(CaptionVariant::default());
+        }
+        self.new_variant_name.clear();
+        self.switch_variant(self.dataset_settings.caption_variants.len() - 1);
+        self.feedback_message = Some(format!("Added caption variant '{}'", name));
+        self.feedback_timer = Some(std::time::Instant::now());
     }
 
-    fn remove_tag_from_all(&mut self, tag: &str) {
-        let mut modified_count = 0;
+    fn remove_active_variant(&mut self) {
+        let removed = self.active_variant;
+        if removed == 0 {
+            return;
+        }
+
+        self.switch_variant(0);
+        let name = self.dataset_settings.caption_variants.remove(removed);
         for image in &mut self.images {
-            if image.tags.contains(&tag.to_string()) {
-                image.tags.retain(|t| t != tag);
-                self.modified_files.insert(image.path.clone(), true);
-                modified_count += 1;
+            image.variants.remove(removed);
+            // Numbered sidecars after the removed one shift down by one
+            self.modified_files.insert(image.path.clone(), true);
+        }
+        self.tags_revision += 1;
+
+        // The shifted variants leave the highest numbered files behind
+        let stale = self.dataset_settings.caption_variants.len();
+        let numbered = self.dataset_settings.variant_storage == VariantStorage::NumberedSidecars;
+        let clean_up = numbered && rfd::MessageDialog::new()
+            .set_title("Remove Caption Variant")
+            .set_description(format!(
+                "Rewrite the numbered caption files now and delete the unused image.{}.txt / .caption files?",
+                stale
+            ))
+            .set_buttons(rfd::MessageButtons::YesNo)
+            .show() == rfd::MessageDialogResult::Yes;
+        if !clean_up {
+            self.feedback_message = Some(format!("Removed caption variant '{}' (existing files are kept on disk)", name));
+            self.feedback_timer = Some(std::time::Instant::now());
+            return;
+        }
+
+        match self.rewrite_numbered_sidecars(stale) {
+            Ok(deleted) => {
+                self.feedback_message = Some(format!("Removed caption variant '{}' and deleted {} files", name, deleted));
+            }
+            Err(err) => {
+                self.feedback_message = Some(format!("Removed caption variant '{}', but rewriting the files failed: {}", name, err));
             }
         }
-        self.feedback_message = Some(format!("Removed tag '{}' from {} images", tag, modified_count));
         self.feedback_timer = Some(std::time::Instant::now());
     }
 
-    fn save_tags_for_image(&self, image_data: &ImageData) -> Result<(), std::io::Error> {
-        let tags_path = image_data.path.with_extension("txt");
-        fs::write(tags_path, image_data.tags.join(", "))
+    // Saves every image and the dataset settings, then deletes the sidecars of variant `stale`
+    fn rewrite_numbered_sidecars(&self, stale: usize) -> Result<usize, Box<dyn std::error::Error>> {
+        if let Some(dir) = &self.current_dir {
+            self.dataset_settings.save(dir)?;
+        }
+        let mut deleted = 0;
+        for image in &self.images {
+            self.save_tags_for_image(image)?;
+            for extension in ["txt", "caption"] {
+                let path = DatasetSettings::sidecar_path(&image.path, stale, extension);
+                if path.exists() {
+                    fs::remove_file(path)?;
+                    deleted += 1;
+                }
+            }
+        }
+        Ok(deleted)
     }
 
     fn pause_caching(&mut self) {
@@ -657,21 +3196,127 @@ impl ImageTagger {
         }
         // Apply sorting after changing image
         self.apply_current_sorting();
+        self.simulated_captions.clear();
+    }
+
+    fn convert_tags_to_style(&mut self) {
+        let style = self.dataset_settings.tag_style;
+        let mut changes: HashMap<(String, String), usize> = HashMap::new();
+        let mut modified_count = 0;
+
+        for image in &mut self.images {
+            let mut changed = false;
+            for tags in image.variant_tag_lists_mut(self.active_variant, true) {
+                for tag in tags.iter_mut() {
+                    let converted = style.apply(tag);
+                    if converted != *tag {
+                        *changes.entry((tag.clone(), converted.clone())).or_insert(0) += 1;
+                        *tag = converted;
+                        changed = true;
+                    }
+                }
+            }
+            if changed {
+                self.modified_files.insert(image.path.clone(), true);
+                self.tags_revision += 1;
+                modified_count += 1;
+            }
+        }
+
+        let mut report: Vec<_> = changes.into_iter()
+            .map(|((before, after), count)| (before, after, count))
+            .collect();
+        report.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+
+        self.feedback_message = Some(format!("Converted {} distinct tags in {} images", report.len(), modified_count));
+        self.feedback_timer = Some(std::time::Instant::now());
+        self.style_report = Some(report);
     }
 
     fn apply_activation_tag(&mut self) {
+        self.activation_tag = self.dataset_settings.tag_style.apply(self.activation_tag.trim());
         if !self.activation_tag.is_empty() {
-            for image in &mut self.images {
-                if !image.tags.contains(&self.activation_tag) {
-                    image.tags.insert(0, self.activation_tag.clone());
-                    self.modified_files.insert(image.path.clone(), true);
+            let activation_tag = self.activation_tag.clone();
+            let name = tag_syntax::lookup_name(&activation_tag);
+            self.edit_all_tags(|_, tags| {
+                if tags.iter().any(|tag| tag_syntax::lookup_name(tag) == name) {
+                    return false;
+                }
+                tags.insert(0, activation_tag.clone());
+                true
+            });
+            // Keep later edits from dropping it again, and stop requiring the one it replaces
+            let policy = &mut self.dataset_settings.tag_policy;
+            if let Some(previous) = self.dataset_settings.activation_tag.replace(activation_tag.clone()) {
+                let previous = tag_syntax::lookup_name(&previous);
+                if previous != name {
+                    policy.required.retain(|entry| tag_syntax::lookup_name(entry) != previous);
                 }
             }
-            self.feedback_message = Some("Activation tag applied to all images".to_string());
+            policy.add_required(&activation_tag);
+            self.save_dataset_settings();
+            self.feedback_message = Some("Activation tag applied to all images and marked as required".to_string());
             self.feedback_timer = Some(std::time::Instant::now());
         }
     }
 
+    // Images carrying the searched tag, compared ignoring weights, escapes and spaces vs underscores
+    fn search_matches(&self) -> Vec<usize> {
+        let query = tag_syntax::lookup_name(&self.search_tag);
+        if query.is_empty() {
+            return Vec::new();
+        }
+        self.images.iter()
+            .enumerate()
+            .filter(|(_, image)| image.tags.iter().any(|tag| tag_syntax::lookup_name(tag) == query))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    fn draw_search_bar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.search_tag)
+                .id(egui::Id::new("search_bar"))
+                .hint_text("Search tag...")
+                .desired_width(160.0));
+            if self.search_tag.is_empty() {
+                return;
+            }
+
+            let matches = self.search_matches();
+            let position = matches.iter().position(|&idx| idx == self.current_image_idx);
+            let mut target = None;
+            if ui.add_enabled(!matches.is_empty(), egui::Button::new("◀")).clicked() {
+                target = match position {
+                    Some(pos) => Some(matches[(pos + matches.len() - 1) % matches.len()]),
+                    None => matches.iter().rev().find(|&&idx| idx < self.current_image_idx)
+                        .or(matches.last())
+                        .copied(),
+                };
+            }
+            if ui.add_enabled(!matches.is_empty(), egui::Button::new("▶")).clicked() {
+                target = match position {
+                    Some(pos) => Some(matches[(pos + 1) % matches.len()]),
+                    None => matches.iter().find(|&&idx| idx > self.current_image_idx)
+                        .or(matches.first())
+                        .copied(),
+                };
+            }
+            match position {
+                Some(pos) => ui.label(format!("{}/{} matches", pos + 1, matches.len())),
+                None => ui.label(format!("{} matches", matches.len())),
+            };
+            if ui.small_button("✖").clicked() {
+                self.search_tag.clear();
+            }
+
+            if let Some(idx) = target {
+                self.current_image_idx = idx;
+                self.change_image(ui.ctx());
+            }
+        });
+    }
+
     fn previous_image(&mut self, ctx: &egui::Context) {
         if !self.images.is_empty() {
             self.current_image_idx = (self.current_image_idx + self.images.len() - 1) % self.images.len();
@@ -687,21 +3332,19 @@ impl ImageTagger {
     }
 
     fn remove_duplicates_for_all(&mut self) {
-        let images = &mut self.images;
-        for image in images.iter_mut() {
+        self.edit_all_tags(|_, tags| {
+            let before = tags.len();
             let mut seen = std::collections::HashSet::new();
-            image.tags.retain(|tag| seen.insert(tag.clone()));
-            self.modified_files.insert(image.path.clone(), true);
-        }
+            // `blue_eyes`, `blue eyes` and `(blue eyes:1.2)` are the same tag; the first one stays
+            tags.retain(|tag| seen.insert(tag_syntax::lookup_name(tag)));
+            tags.len() != before
+        });
         self.feedback_message = Some("Removed duplicate tags from all images".to_string());
         self.feedback_timer = Some(std::time::Instant::now());
     }
 
     fn process_tags_text(text: &str) -> Vec<String> {
-        text.split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect()
+        tag_syntax::split_tags(text)
     }
 
 
@@ -732,24 +3375,16 @@ impl ImageTagger {
                         }
                     });
 
+                    self.draw_category_legend(ui, &current_image.tags);
+                    self.draw_training_preview(ui, &current_image);
+
                     // Calculate available width for the middle panel
                     let total_width = ui.available_width();
                     let right_panel_width = self.right_panel_width.unwrap_or(300.0);
                     let buffer = 20.0; // Add a buffer to prevent overlap
                     let middle_panel_width = total_width - right_panel_width - buffer;
 
-                    // Wrapping tags
-                    egui::ScrollArea::vertical().show(ui, |ui| {
-                        ui.horizontal_wrapped(|ui| {
-                            ui.spacing_mut().item_spacing = egui::vec2(8.0, 8.0); // Add spacing between tags
-                            ui.set_width(middle_panel_width); // Restrict width to middle panel
-                            for tag in &current_image.tags {
-                                // Replace spaces with non-breaking spaces to prevent word wrapping
-                                let non_breaking_tag = tag.replace(' ', "\u{00A0}");
-                                ui.label(non_breaking_tag);
-                            }
-                        });
-                    });
+                    self.draw_tag_list(ui, middle_panel_width);
                 });
             } else {
                 ui.centered_and_justified(|ui| {
@@ -759,6 +3394,403 @@ impl ImageTagger {
         });
     }
 
+    fn tag_category(&self, tag: &str) -> Option<TagCategory> {
+        self.booru_manager.get_category(&tag_syntax::lookup_name(tag))
+    }
+
+    fn category_color(&self, category: TagCategory) -> egui::Color32 {
+        let [r, g, b] = self.app_settings.category_color(category);
+        egui::Color32::from_rgb(r, g, b)
+    }
+
+    // Colors of the categories present in `tags`
+    fn draw_category_legend(&self, ui: &mut egui::Ui, tags: &[String]) {
+        let present: HashSet<TagCategory> = tags.iter().filter_map(|tag| self.tag_category(tag)).collect();
+        if present.is_empty() {
+            return;
+        }
+        ui.horizontal_wrapped(|ui| {
+            for category in TagCategory::ALL.into_iter().filter(|c| present.contains(c)) {
+                ui.colored_label(self.category_color(category), "●");
+                ui.label(category.label());
+                ui.add_space(6.0);
+            }
+        });
+    }
+
+    fn draw_category_colors(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Category Colors")
+            .id_salt("category_colors")
+            .show(ui, |ui| {
+                let mut changed = false;
+                egui::Grid::new("category_colors_grid").show(ui, |ui| {
+                    for category in TagCategory::ALL {
+                        let mut rgb = self.app_settings.category_color(category);
+                        if ui.color_edit_button_srgb(&mut rgb).changed() {
+                            self.app_settings.category_colors.insert(category, rgb);
+                            changed = true;
+                        }
+                        ui.label(category.label());
+                        let is_custom = self.app_settings.category_colors.contains_key(&category);
+                        if ui.add_enabled(is_custom, egui::Button::new("Reset").small()).clicked() {
+                            self.app_settings.category_colors.remove(&category);
+                            changed = true;
+                        }
+                        ui.end_row();
+                    }
+                });
+                if changed {
+                    if let Err(err) = self.app_settings.save() {
+                        eprintln!("Failed to save settings: {}", err);
+                    }
+                }
+            });
+    }
+
+    // Covers every `N_name` folder next to the open one (or inside it, when the open directory is
+    // the training root). The open folder uses the tags in memory, the others are read from disk
+    // in the background.
+    fn build_composition(&mut self, ctx: &egui::Context) {
+        let Some(dir) = self.current_dir.clone() else {
+            return;
+        };
+        let root = match dataset_composition::parse_repeats(&dir) {
+            Some(_) => dir.parent().map(Path::to_path_buf).unwrap_or_else(|| dir.clone()),
+            None => dir.clone(),
+        };
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.composition_receiver = Some(rx);
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let mut folders: Vec<PathBuf> = fs::read_dir(&root)
+                .map(|entries| entries.filter_map(|e| e.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| path.is_dir() && dataset_composition::parse_repeats(path).is_some())
+                    .collect())
+                .unwrap_or_default();
+            folders.sort();
+
+            let others = folders.into_iter()
+                .filter(|folder| *folder != dir)
+                .map(|path| {
+                    let (repeats, _) = dataset_composition::parse_repeats(&path).unwrap_or_default();
+                    let images = Self::read_folder_tags(&path);
+                    FolderTags { path, repeats, images }
+                })
+                .collect();
+            let _ = tx.send((dir, others));
+            ctx.request_repaint();
+        });
+    }
+
+    // Tags of every image in a folder, from the plain sidecars in the folder's own caption format
+    fn read_folder_tags(folder: &Path) -> Vec<Vec<String>> {
+        let format = DatasetSettings::load(folder).caption_format;
+        let Ok(entries) = fs::read_dir(folder) else {
+            return Vec::new();
+        };
+        entries.filter_map(|e| e.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && matches!(path.extension().and_then(|e| e.to_str()),
+                Some("jpg" | "jpeg" | "png")))
+            .map(|path| {
+                let content = fs::read_to_string(DatasetSettings::sidecar_path(&path, 0, "txt")).unwrap_or_default();
+                let mut tags = Self::process_tags_text(format.split(&content).0);
+                tag_confidence::strip_inline(&mut tags);
+                tags
+            })
+            .collect()
+    }
+
+    fn process_composition_messages(&mut self) {
+        let Some(rx) = &self.composition_receiver else {
+            return;
+        };
+        let (dir, others) = match rx.try_recv() {
+            Ok(message) => message,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return,
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                self.composition_receiver = None;
+                return;
+            }
+        };
+        self.composition_receiver = None;
+
+        let mut entries: Vec<(PathBuf, u32, Vec<&[String]>)> = others.iter()
+            .map(|folder| (folder.path.clone(), folder.repeats, folder.images.iter().map(Vec::as_slice).collect()))
+            .collect();
+        if !self.images.is_empty() || entries.is_empty() {
+            let repeats = dataset_composition::parse_repeats(&dir).map_or(1, |(repeats, _)| repeats);
+            entries.push((dir, repeats, self.images.iter().map(|image| image.tags.as_slice()).collect()));
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+
+        let composition = Composition::build(entries);
+        // Keep the targets across refreshes unless the folders changed
+        if self.composition_weights.len() != composition.folders.len() {
+            self.composition_weights = vec![1.0; composition.folders.len()];
+        }
+        self.composition = Some(composition);
+    }
+
+    fn draw_composition(&mut self, ctx: &egui::Context) {
+        let Some(composition) = &self.composition else {
+            return;
+        };
+
+        let mut open = true;
+        let mut refresh = false;
+        let reading = self.composition_receiver.is_some();
+        let weights = &mut self.composition_weights;
+        let filter = &mut self.composition_category;
+        egui::Window::new("Dataset Composition")
+            .open(&mut open)
+            .default_width(550.0)
+            .show(ctx, |ui| {
+                let total = composition.total_effective.max(1) as f32;
+                ui.label(format!(
+                    "{} folders, {} samples per epoch (images × repeats)",
+                    composition.folders.len(),
+                    composition.total_effective
+                ));
+                if ui.add_enabled(!reading, egui::Button::new("Refresh")).clicked() {
+                    refresh = true;
+                }
+                ui.separator();
+
+                ui.strong("Folders");
+                ui.weak("Set a target weight per folder to get repeats that balance them.");
+                let suggested = composition.suggest_repeats(weights);
+                let suggested_total: usize = composition.folders.iter()
+                    .zip(&suggested)
+                    .map(|(folder, &repeats)| folder.images * repeats as usize)
+                    .sum();
+                egui::Grid::new("composition_folders_grid").striped(true).show(ui, |ui| {
+                    for heading in ["Folder", "Images", "Repeats", "Samples", "Share", "Target", "Suggested"] {
+                        ui.strong(heading);
+                    }
+                    ui.end_row();
+                    for ((folder, weight), &repeats) in composition.folders.iter().zip(weights.iter_mut()).zip(&suggested) {
+                        ui.label(&folder.name).on_hover_text(folder.path.display().to_string());
+                        ui.label(folder.images.to_string());
+                        ui.label(folder.repeats.to_string());
+                        ui.label(folder.effective().to_string());
+                        ui.label(format!("{:.1}%", folder.effective() as f32 / total * 100.0));
+                        ui.add(egui::DragValue::new(weight).speed(0.05).range(0.0..=100.0));
+                        let share = (folder.images * repeats as usize) as f32 / suggested_total.max(1) as f32;
+                        let text = format!("{} ({:.1}%)", repeats, share * 100.0);
+                        if repeats == folder.repeats {
+                            ui.label(text);
+                        } else {
+                            let concept = dataset_composition::parse_repeats(&folder.path)
+                                .map_or_else(|| folder.name.clone(), |(_, concept)| concept);
+                            ui.strong(text).on_hover_text(format!("Rename the folder to {}_{}", repeats, concept));
+                        }
+                        ui.end_row();
+                    }
+                });
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.strong("Tags");
+                    egui::ComboBox::from_id_salt("composition_category")
+                        .selected_text(filter.map_or("All categories", |category| category.label()))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(filter, None, "All categories");
+                            for category in TagCategory::ALL {
+                                ui.selectable_value(filter, Some(category), category.label());
+                            }
+                        });
+                });
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    egui::Grid::new("composition_tags_grid").striped(true).show(ui, |ui| {
+                        for heading in ["Tag", "Images", "Samples", "Share"] {
+                            ui.strong(heading);
+                        }
+                        ui.end_row();
+                        let rows = composition.tags.iter()
+                            .filter(|tag| filter.is_none() || self.booru_manager.get_category(&tag.name) == *filter)
+                            .take(200);
+                        for tag in rows {
+                            ui.label(&tag.name);
+                            ui.label(tag.images.to_string());
+                            ui.label(tag.effective.to_string());
+                            ui.label(format!("{:.1}%", tag.effective as f32 / total * 100.0));
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+
+        if refresh {
+            self.build_composition(ctx);
+        }
+        if !open {
+            self.composition = None;
+        }
+    }
+
+    // Tag usage across the dataset, grouped by category
+    fn draw_tag_statistics(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.toggle_value(&mut self.show_tag_stats, "📊 Tag Statistics");
+            let reading = self.composition_receiver.is_some();
+            if ui.add_enabled(self.current_dir.is_some() && !reading, egui::Button::new("Composition Report"))
+                .on_hover_text("Images and samples per folder and tag, weighted by kohya folder repeats")
+                .clicked()
+            {
+                self.build_composition(ui.ctx());
+            }
+            if reading {
+                ui.spinner();
+            }
+        });
+        if !self.show_tag_stats {
+            return;
+        }
+
+        let revision = (self.tags_revision, self.booru_manager.revision());
+        if self.tag_statistics.as_ref().is_none_or(|stats| stats.revision != revision) {
+            self.tag_statistics = Some(self.count_tags(revision));
+        }
+        let Some(stats) = &self.tag_statistics else {
+            return;
+        };
+
+        egui::Grid::new("tag_statistics_grid").striped(true).show(ui, |ui| {
+            ui.strong("Category");
+            ui.strong("Tags");
+            ui.strong("Uses");
+            ui.end_row();
+            for (category, distinct, uses) in &stats.by_category {
+                match category {
+                    Some(category) => ui.colored_label(self.category_color(*category), category.label()),
+                    None => ui.label("Not in database"),
+                };
+                ui.label(distinct.to_string());
+                ui.label(uses.to_string());
+                ui.end_row();
+            }
+        });
+
+        ui.label("Most used:");
+        ui.horizontal_wrapped(|ui| {
+            for (tag, count, category) in &stats.top {
+                let text = egui::RichText::new(format!("{} ({})", tag, count));
+                match category {
+                    Some(category) => ui.label(text.color(self.category_color(*category))),
+                    None => ui.label(text),
+                };
+            }
+        });
+    }
+
+    fn count_tags(&self, revision: (u64, u64)) -> TagStatistics {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for image in &self.images {
+            for tag in &image.tags {
+                *counts.entry(tag.as_str()).or_insert(0) += 1;
+            }
+        }
+
+        let mut by_category: HashMap<Option<TagCategory>, (usize, usize)> = HashMap::new();
+        for (tag, count) in &counts {
+            let entry = by_category.entry(self.tag_category(tag)).or_insert((0, 0));
+            entry.0 += 1;
+            entry.1 += count;
+        }
+        let by_category = TagCategory::ALL.into_iter()
+            .map(Some)
+            .chain(std::iter::once(None))
+            .filter_map(|category| by_category.get(&category).map(|&(distinct, uses)| (category, distinct, uses)))
+            .collect();
+
+        let mut top: Vec<(&str, usize)> = counts.into_iter().collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let top = top.into_iter()
+            .take(20)
+            .map(|(tag, count)| (tag.to_string(), count, self.tag_category(tag)))
+            .collect();
+
+        TagStatistics { revision, by_category, top }
+    }
+
+    fn draw_training_preview(&mut self, ui: &mut egui::Ui, current_image: &ImageData) {
+        let keep_tokens = self.dataset_settings.keep_tokens;
+        let activation_tag = self.activation_tag.trim();
+        let fate = self.dataset_settings.unkept_tag_fate();
+
+        if let Some(fate) = fate.filter(|_| keep_tokens > 0 && !activation_tag.is_empty()) {
+            let outside_kept = |tags: &[String]| {
+                tags.iter().position(|t| t == activation_tag).is_none_or(|pos| pos >= keep_tokens)
+            };
+            if outside_kept(&current_image.tags) {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    format!("⚠ Activation tag '{}' is not within the first {} kept tokens", activation_tag, keep_tokens),
+                );
+            }
+
+            let key = (self.tags_revision, activation_tag.to_string(), keep_tokens);
+            let affected = match &self.activation_tag_check {
+                Some((cached, affected)) if *cached == key => *affected,
+                _ => {
+                    let affected = self.images.iter().filter(|image| outside_kept(&image.tags)).count();
+                    self.activation_tag_check = Some((key, affected));
+                    affected
+                }
+            };
+            if affected > 0 {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    format!("⚠ {} of {} images could have the activation tag {}", affected, self.images.len(), fate),
+                );
+            }
+        }
+
+        ui.collapsing("Training Preview", |ui| {
+            let settings = &mut self.dataset_settings;
+            let mut changed = false;
+            ui.horizontal(|ui| {
+                ui.label("Keep tokens:");
+                changed |= ui.add(egui::DragValue::new(&mut settings.keep_tokens).range(0..=75)).changed();
+                changed |= ui.checkbox(&mut settings.shuffle_caption, "Shuffle caption").changed();
+                ui.label("Token limit:");
+                egui::ComboBox::from_id_salt("token_limit")
+                    .selected_text(settings.token_limit.to_string())
+                    .show_ui(ui, |ui| {
+                        for limit in [75, 150, 225] {
+                            changed |= ui.selectable_value(&mut settings.token_limit, limit, limit.to_string()).changed();
+                        }
+                    });
+            });
+            ui.horizontal(|ui| {
+                ui.label("Tag dropout:");
+                changed |= ui.add(egui::Slider::new(&mut settings.tag_dropout_rate, 0.0..=1.0)).changed();
+                ui.label("Caption dropout:");
+                changed |= ui.add(egui::Slider::new(&mut settings.caption_dropout_rate, 0.0..=1.0)).changed();
+            });
+            if changed {
+                self.save_dataset_settings();
+            }
+
+            if ui.button("Simulate Captions").clicked() {
+                let mut rng = rand::thread_rng();
+                self.simulated_captions = (0..5)
+                    .map(|_| self.dataset_settings.simulate_caption(&current_image.tags, &mut rng))
+                    .collect();
+            }
+            for caption in &self.simulated_captions {
+                if caption.is_empty() {
+                    ui.weak("(caption dropped)");
+                } else {
+                    ui.label(caption);
+                }
+            }
+        });
+    }
+
     fn draw_right_panel(&mut self, ctx: &egui::Context) {
         egui::SidePanel::right("tag_panel")
             .resizable(true)
@@ -773,41 +3805,39 @@ impl ImageTagger {
                 ui.heading("Tag Editing");
 
                 // In draw_right_panel
-                let tags_loaded = !self.booru_manager.tags.is_empty();
-                if !tags_loaded {
+                let tags_loaded = self.booru_manager.has_tags();
+                if self.tag_db_receiver.is_some() {
+                    ui.add(
+                        egui::ProgressBar::new(self.tag_db_progress)
+                            .text("Loading tag database...")
+                            .desired_width(ui.available_width())
+                    );
+                } else if !tags_loaded {
                     ui.horizontal(|ui| {
                         if ui.button("❗ Import Booru Tags CSV").clicked() {
                             if let Some(path) = rfd::FileDialog::new()
                                 .add_filter("CSV Files", &["csv"])
                                 .pick_file()
                             {
-                                if let Err(err) = self.booru_manager.load_from_csv(&path) {
-                                    self.feedback_message = Some(format!("Failed to load CSV: {}", err));
-                                    self.feedback_timer = Some(std::time::Instant::now());
-                                } else {
-                                    self.feedback_message = Some("Successfully loaded Booru tags database".to_string());
-                                    self.feedback_timer = Some(std::time::Instant::now());
-                                }
+                                self.start_tag_database_load(ui.ctx(), vec![path]);
                             }
                         }
                         ui.label("⬅ Required for tag suggestions");
                     });
                 } else {
-                    if ui.button("Import Booru Tags CSV").clicked() {
+                    if ui.button("Add Tag Source CSV").clicked() {
                         if let Some(path) = rfd::FileDialog::new()
                             .add_filter("CSV Files", &["csv"])
                             .pick_file()
                         {
-                            if let Err(err) = self.booru_manager.load_from_csv(&path) {
-                                self.feedback_message = Some(format!("Failed to load CSV: {}", err));
-                                self.feedback_timer = Some(std::time::Instant::now());
-                            } else {
-                                self.feedback_message = Some("Successfully loaded Booru tags database".to_string());
-                                self.feedback_timer = Some(std::time::Instant::now());
-                            }
+                            self.start_tag_database_load(ui.ctx(), vec![path]);
                         }
                     }
+                    self.draw_tag_sources(ui);
                 }
+                self.draw_wiki_controls(ui);
+                self.draw_translation_controls(ui);
+                self.draw_tokenizer_controls(ui);
 
                 ui.add_space(10.0);
                 ui.separator();
@@ -815,46 +3845,159 @@ impl ImageTagger {
                 // Add Booru tag section
                 ui.group(|ui| {
                     ui.heading("Add Booru Tag");
-                    if let Some(selected_tag) = self.booru_manager.draw_tag_editor(ui) {
+                    let app_settings = &self.app_settings;
+                    let category_color = |category| {
+                        let [r, g, b] = app_settings.category_color(category);
+                        egui::Color32::from_rgb(r, g, b)
+                    };
+                    if let Some(selected_tag) = self.booru_manager.draw_tag_editor(ui, category_color) {
+                        let selected_tag = self.dataset_settings.tag_style.apply(&selected_tag);
                         println!("Attempting to add tag to current image: {}", selected_tag);
 
+                        self.apply_tag_action(TagAction::Add(selected_tag));
+                    }
+                });
+
+
+                ui.add_space(10.0);
+                ui.separator();
+
+                // Tag management controls
+                ui.horizontal(|ui| {
+                    if ui.button("Remove Duplicates (Current)").clicked() {
                         if let Some(current_image) = self.images.get_mut(self.current_image_idx) {
-                            if !current_image.tags.contains(&selected_tag) {
-                                current_image.tags.push(selected_tag.clone());
-                                self.modified_files.insert(current_image.path.clone(), true);
+                            let mut seen = std::collections::HashSet::new();
+                            current_image.tags.retain(|tag| seen.insert(tag.clone()));
+                            self.modified_files.insert(current_image.path.clone(), true);
+                            self.tags_revision += 1;
+                        }
+                    }
+                    if ui.button("Remove Duplicates (All)").clicked() {
+                        self.remove_duplicates_for_all();
+                    }
+                });
+
+                ui.add_space(10.0);
+                self.draw_related_tags(ui);
+                self.draw_auto_tagger(ui);
+                self.draw_external_captioner(ui);
+                self.draw_auto_tag_suggestions(ui);
+                self.draw_custom_tags(ui);
+                self.draw_rules(ui);
+                self.draw_tag_policy(ui);
+                self.draw_tag_statistics(ui);
+                self.draw_category_colors(ui);
+
+                ui.add_space(10.0);
+                ui.separator();
+
+                let mut requested_variant = None;
+                let mut remove_variant = false;
+                let mut add_variant = false;
+                ui.horizontal(|ui| {
+                    ui.label("Variant:");
+                    let names = &self.dataset_settings.caption_variants;
+                    egui::ComboBox::from_id_salt("caption_variant")
+                        .selected_text(&names[self.active_variant])
+                        .show_ui(ui, |ui| {
+                            for (idx, name) in names.iter().enumerate() {
+                                if ui.selectable_label(idx == self.active_variant, name).clicked() {
+                                    requested_variant = Some(idx);
+                                }
+                            }
+                        });
+                    if self.active_variant > 0 && ui.button("Remove Variant").clicked() {
+                        remove_variant = true;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let response = ui.add(egui::TextEdit::singleline(&mut self.new_variant_name)
+                        .hint_text("New variant name (e.g. short)")
+                        .desired_width(180.0));
+                    if ui.button("Add Variant").clicked()
+                        || (response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)))
+                    {
+                        add_variant = true;
+                    }
+                });
+                if let Some(idx) = requested_variant {
+                    self.switch_variant(idx);
+                }
+                if remove_variant {
+                    self.remove_active_variant();
+                }
+                if add_variant {
+                    self.add_variant();
+                }
 
-                                println!("Tag added successfully! Current tags: {:?}", current_image.tags);
-                            } else {
-                                println!("Tag already exists: {}", selected_tag);
+                ui.horizontal(|ui| {
+                    ui.label("Variant storage:");
+                    let previous_storage = self.dataset_settings.variant_storage;
+                    egui::ComboBox::from_id_salt("variant_storage")
+                        .selected_text(previous_storage.label())
+                        .show_ui(ui, |ui| {
+                            for storage in VariantStorage::ALL {
+                                ui.selectable_value(&mut self.dataset_settings.variant_storage, storage, storage.label());
                             }
-                        } else {
-                            println!("No current image available to add the tag.");
+                        });
+                    if self.dataset_settings.variant_storage != previous_storage {
+                        for image in &self.images {
+                            self.modified_files.insert(image.path.clone(), true);
                         }
+                        self.tags_revision += 1;
                     }
                 });
+                ui.checkbox(&mut self.bulk_all_variants, "Bulk edits apply to all variants");
 
+                ui.collapsing("Output Style", |ui| {
+                    let style = &mut self.dataset_settings.tag_style;
+                    ui.horizontal(|ui| {
+                        ui.label("Word separator:");
+                        egui::ComboBox::from_id_salt("tag_style_separator")
+                            .selected_text(style.separator.label())
+                            .show_ui(ui, |ui| {
+                                for separator in WordSeparator::ALL {
+                                    ui.selectable_value(&mut style.separator, separator, separator.label());
+                                }
+                            });
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Parentheses:");
+                        egui::ComboBox::from_id_salt("tag_style_parentheses")
+                            .selected_text(style.parentheses.label())
+                            .show_ui(ui, |ui| {
+                                for parentheses in ParenStyle::ALL {
+                                    ui.selectable_value(&mut style.parentheses, parentheses, parentheses.label());
+                                }
+                            });
+                    });
+                    ui.label("Applied to added tags and on save.");
+                    if ui.button("Convert All Tags Now").clicked() {
+                        self.convert_tags_to_style();
+                    }
+                });
 
-                ui.add_space(10.0);
-                ui.separator();
-
-                // Tag management controls
                 ui.horizontal(|ui| {
-                    if ui.button("Remove Duplicates (Current)").clicked() {
-                        if let Some(current_image) = self.images.get_mut(self.current_image_idx) {
-                            let mut seen = std::collections::HashSet::new();
-                            current_image.tags.retain(|tag| seen.insert(tag.clone()));
-                            self.modified_files.insert(current_image.path.clone(), true);
+                    ui.label("Caption format:");
+                    let previous_format = self.dataset_settings.caption_format;
+                    egui::ComboBox::from_id_salt("caption_format")
+                        .selected_text(previous_format.label())
+                        .show_ui(ui, |ui| {
+                            for format in CaptionFormat::ALL {
+                                ui.selectable_value(&mut self.dataset_settings.caption_format, format, format.label());
+                            }
+                        });
+                    if self.dataset_settings.caption_format != previous_format {
+                        // Every sidecar has to be rewritten in the new layout
+                        for image in &self.images {
+                            self.modified_files.insert(image.path.clone(), true);
                         }
-                    }
-                    if ui.button("Remove Duplicates (All)").clicked() {
-                        self.remove_duplicates_for_all();
+                        self.tags_revision += 1;
                     }
                 });
 
-                ui.add_space(10.0);
-                ui.separator();
-
                 if let Some(current_image) = self.images.get_mut(self.current_image_idx) {
+                    ui.label("Tags:");
                     let mut tags_text = current_image.tags.join(", ");
                     let text_edit = egui::TextEdit::multiline(&mut tags_text)
                         .desired_width(ui.available_width())
@@ -864,13 +4007,30 @@ impl ImageTagger {
                         .id(egui::Id::new("text_editor"));
 
                     if ui.add(text_edit).changed() {
-                        let new_tags = tags_text
-                            .split(',')
-                            .map(|s| s.trim().to_string())
-                            .filter(|s| !s.is_empty())
-                            .collect::<Vec<_>>();
-                        current_image.tags = new_tags;
+                        let tags = Self::process_tags_text(&tags_text);
+                        let broken = self.dataset_settings.tag_policy.broken_requirements(&current_image.tags, &tags);
+                        if broken.is_empty() {
+                            current_image.tags = tags;
+                            self.modified_files.insert(current_image.path.clone(), true);
+                            self.tags_revision += 1;
+                        } else {
+                            self.feedback_message = Some(format!("{} is required in this dataset", broken.join(", ")));
+                            self.feedback_timer = Some(std::time::Instant::now());
+                        }
+                    }
+
+                    ui.add_space(10.0);
+                    ui.label("Caption:");
+                    let caption_edit = egui::TextEdit::multiline(&mut current_image.caption)
+                        .desired_width(ui.available_width())
+                        .desired_rows(4)
+                        .hint_text("Natural-language description...")
+                        .lock_focus(false)
+                        .id(egui::Id::new("caption_editor"));
+
+                    if ui.add(caption_edit).changed() {
                         self.modified_files.insert(current_image.path.clone(), true);
+                        self.tags_revision += 1;
                     }
                 }
 
@@ -878,39 +4038,116 @@ impl ImageTagger {
             });
     }
     fn load_directory(&mut self, ctx: &egui::Context, path: &Path) {
-        self.images.clear();
         self.image_cache.clear();
         self.current_image_idx = 0;
         self.cache_progress = 0.0;
         self.is_caching = false;
         *self.cached_images_count.lock().unwrap() = 0;
+        self.policy_report = None;
+        self.lint_report = None;
+        self.composition = None;
+        self.composition_receiver = None;
+        // A running captioner answers for the old dataset
+        self.captioner_cancel.store(true, Ordering::Relaxed);
+        self.captioner_receiver = None;
+        self.captioner_progress = None;
+        // So does a tagging run
+        self.auto_tag_cancel.store(true, Ordering::Relaxed);
+        self.auto_tag_receiver = None;
+        self.auto_tag_progress = None;
+        self.auto_tag_suggestions.clear();
+        self.caption_suggestions.clear();
+        self.review_queue = None;
+        self.script_preview = None;
+        self.script_cancel.store(true, Ordering::Relaxed);
+        self.script_receiver = None;
+        self.script_progress = None;
+        self.tags_revision += 1;
+        self.image_errors.clear();
+        self.read_dataset(path);
+        let with_confidences = self.images.iter().filter(|image| !image.confidences.is_empty()).count();
+        if with_confidences > 0 {
+            self.feedback_message = Some(format!("Loaded tag confidences for {} images", with_confidences));
+            self.feedback_timer = Some(std::time::Instant::now());
+        }
+        self.booru_manager.set_disabled_sources(&self.dataset_settings.disabled_tag_sources);
+
+        println!("Starting background caching for {} images...", self.images.len());
+        self.load_custom_tags(path);
+        self.total_images_to_cache = self.images.len();
+        self.is_caching = true;
+
+        self.start_background_caching();
+        self.rebuild_cooccurrence();
+
+        if !self.images.is_empty() {
+            self.current_image_idx = 0;
+            self.load_image_texture(ctx);
+        }
+    }
+
+    // Reads the dataset settings and every image's captions; no UI state involved, so the
+    // command-line tools use it too
+    fn read_dataset(&mut self, path: &Path) {
+        self.images.clear();
+        self.dataset_settings = DatasetSettings::load(path);
+        self.activation_tag = self.dataset_settings.activation_tag.clone().unwrap_or_default();
+        self.active_variant = 0;
+        self.current_dir = Some(path.to_path_buf());
 
         if let Ok(entries) = fs::read_dir(path) {
             for entry in entries.filter_map(|e| e.ok()) {
                 let path = entry.path();
                 if path.is_file() && matches!(path.extension().and_then(|e| e.to_str()),
                     Some("jpg" | "jpeg" | "png")) {
-                    let tags = self.load_tags_for_image(&path).unwrap_or_default();
+                    let (mut tags, caption) = self.load_tags_for_image(&path, 0).unwrap_or_default();
+                    let (confidences, tags_changed) = Self::load_confidences(&path, &mut tags);
+                    if tags_changed {
+                        // Saving writes the captions back without the confidences
+                        self.modified_files.insert(path.clone(), true);
+                        self.tags_revision += 1;
+                    }
+                    let variants = self.load_variants_for_image(&path).unwrap_or_else(|err| {
+                        eprintln!("Failed to load caption variants for {}: {}", path.display(), err);
+                        vec![CaptionVariant::default(); self.dataset_settings.caption_variants.len()]
+                    });
                     self.images.push(ImageData {
                         path,
                         tags,
+                        caption,
+                        variants,
                         hash: None,
+                        confidences,
                     });
                 }
             }
         }
 
-        println!("Starting background caching for {} images...", self.images.len());
-        self.current_dir = Some(path.to_path_buf());
-        self.total_images_to_cache = self.images.len();
-        self.is_caching = true;
+        self.images.sort_by(|a, b| a.path.cmp(&b.path));
+    }
 
-        self.start_background_caching();
+    // Confidences from a `tag:0.87` caption or the image's JSON sidecar. An image without tags
+    // takes the sidecar's tags; returns whether the tags changed.
+    fn load_confidences(image_path: &Path, tags: &mut Vec<String>) -> (Confidences, bool) {
+        let mut confidences = tag_confidence::strip_inline(tags).unwrap_or_default();
+        let mut changed = !confidences.is_empty();
 
-        if !self.images.is_empty() {
-            self.current_image_idx = 0;
-            self.load_image_texture(ctx);
+        let sidecar = tag_confidence::sidecar_path(image_path);
+        if sidecar.exists() {
+            match tag_confidence::load_sidecar(&sidecar) {
+                Ok(entries) => {
+                    if tags.is_empty() {
+                        *tags = entries.iter().map(|(name, _)| name.clone()).collect();
+                        changed = true;
+                    }
+                    for (name, confidence) in entries {
+                        confidences.entry(tag_syntax::lookup_name(&name)).or_insert(confidence);
+                    }
+                }
+                Err(err) => eprintln!("Ignoring {}: {}", sidecar.display(), err),
+            }
         }
+        (confidences, changed)
     }
 
     fn load_image_texture(&mut self, ctx: &egui::Context) -> bool {
@@ -961,49 +4198,464 @@ impl ImageTagger {
         false
     }
 
-    fn draw_tag_list(&mut self, ui: &mut egui::Ui) {
-        if let Some(current_image) = self.images.get(self.current_image_idx).cloned() {
-            egui::ScrollArea::vertical()
-                .auto_shrink([false, false])
-                .show(ui, |ui| {
-                    ui.horizontal_wrapped(|ui| {
-                        ui.spacing_mut().item_spacing = egui::vec2(8.0, 8.0); // Adjust spacing between tags
-                        for tag in &current_image.tags {
-                            if !tag.is_empty() { // Skip empty tags
-                                ui.group(|ui| {
-                                    ui.horizontal(|ui| {
-                                        if let Some(tag_type) = self.booru_manager.get_tag_type(tag) {
-                                            let color = match tag_type {
-                                                0 => egui::Color32::GRAY,
-                                                1 => egui::Color32::RED,
-                                                3 => egui::Color32::GREEN,
-                                                4 => egui::Color32::YELLOW,
-                                                _ => egui::Color32::WHITE,
-                                            };
-                                            ui.colored_label(color, "●");
-                                            ui.add_space(4.0);
-                                        }
-                                        ui.label(egui::RichText::new(tag).size(16.0));
-                                    });
-                                });
+    // Tags of the current image as chips: click removes, right-click opens more actions,
+    // dragging reorders. Tags missing from the loaded databases are outlined as possible typos.
+    fn draw_tag_list(&mut self, ui: &mut egui::Ui, width: f32) {
+        let Some(current_image) = self.images.get(self.current_image_idx) else {
+            return;
+        };
+        let tags = current_image.tags.clone();
+        if let Some(tokenizer) = &self.clip_tokenizer {
+            let revision = (self.current_image_idx, self.tags_revision);
+            if self.tag_token_ends.as_ref().is_none_or(|(cached, _)| *cached != revision) {
+                self.tag_token_ends = Some((revision, tokenizer.tag_token_ends(&tags)));
+            }
+        }
+        let keep_tokens = self.dataset_settings.keep_tokens;
+        let unkept_fate = self.dataset_settings.unkept_tag_fate();
+        let check_unknown = self.booru_manager.has_tags();
+        let policy = &self.dataset_settings.tag_policy;
+        let token_limit = self.dataset_settings.token_limit;
+        let token_ends = self.tag_token_ends.as_ref().map(|(_, ends)| ends.clone());
+        let confidences = current_image.confidences.clone();
+        let review_below = self.app_settings.auto_tagger.review_below;
+        if let Some(total) = token_ends.as_ref().and_then(|ends| ends.last()) {
+            let text = format!("{} / {} CLIP tokens", total, token_limit);
+            if *total > token_limit {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("⚠ {}, struck-through tags get cut off", text));
+            } else {
+                ui.weak(text);
+            }
+        }
+        let mut renaming = self.renaming_tag.take();
+        let mut actions = Vec::new();
+
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    ui.spacing_mut().item_spacing = egui::vec2(8.0, 8.0); // Add spacing between tags
+                    ui.set_width(width); // Restrict width to middle panel
+                    for (tag_idx, tag) in tags.iter().enumerate() {
+                        if keep_tokens > 0 && tag_idx == keep_tokens {
+                            let divider = ui.separator();
+                            match unkept_fate {
+                                Some(fate) => divider.on_hover_text(format!("Tags after this can be {} by the trainer", fate)),
+                                None => divider.on_hover_text("Kept tokens end here; shuffling and tag dropout are off"),
+                            };
+                        }
+
+                        if let Some((rename_idx, text)) = renaming.as_mut().filter(|(idx, _)| *idx == tag_idx) {
+                            let response = ui.add(egui::TextEdit::singleline(text)
+                                .id(egui::Id::new("tag_rename"))
+                                .desired_width(150.0));
+                            if !response.has_focus() && !response.lost_focus() {
+                                response.request_focus();
                             }
+                            if response.lost_focus() {
+                                if !ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                                    actions.push(TagAction::Rename { idx: *rename_idx, text: text.clone() });
+                                }
+                                renaming = None;
+                            }
+                            continue;
                         }
-                    });
+
+                        let mut parsed = WeightedTag::parse(tag);
+                        let category = self.tag_category(tag);
+                        let is_unknown = check_unknown && category.is_none();
+                        let is_forbidden = policy.is_forbidden(tag);
+                        let confidence = confidences.get(&tag_syntax::lookup_name(tag)).copied();
+
+                        // Replace spaces with non-breaking spaces to prevent word wrapping
+                        let mut text = egui::RichText::new(parsed.name().replace(' ', "\u{00A0}")).size(16.0);
+                        if tag_idx < keep_tokens {
+                            text = text.strong().underline();
+                        }
+                        let past_limit = token_ends.as_ref().is_some_and(|ends| ends[tag_idx] > token_limit);
+                        if past_limit {
+                            text = text.strikethrough();
+                        }
+
+                        let mut frame = egui::Frame::group(ui.style());
+                        if is_forbidden {
+                            frame = frame.stroke(egui::Stroke::new(1.5, ui.visuals().error_fg_color));
+                        } else if is_unknown {
+                            frame = frame.stroke(egui::Stroke::new(1.5, ui.visuals().warn_fg_color));
+                        }
+                        let chip = frame.show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                if let Some(category) = category {
+                                    ui.colored_label(self.category_color(category), "●")
+                                        .on_hover_text(category.label());
+                                }
+                                let mut hover = "Click to remove, right-click for more, drag to reorder".to_string();
+                                if let Some(description) = self.booru_manager.get_description(&tag_syntax::lookup_name(tag)) {
+                                    hover = format!("{}\n\n{}", description, hover);
+                                }
+                                if is_unknown {
+                                    hover.insert_str(0, "Not in the tag database, possibly a typo\n");
+                                }
+                                if let Some(confidence) = confidence {
+                                    hover.insert_str(0, &format!("Auto-tagged with {:.0}% confidence, not reviewed yet\n", confidence * 100.0));
+                                }
+                                if past_limit {
+                                    hover.insert_str(0, "Past the token limit in this order, truncated by the trainer\n");
+                                }
+                                if is_forbidden {
+                                    hover.insert_str(0, "Forbidden in this dataset\n");
+                                } else if policy.is_required(tag) {
+                                    hover.insert_str(0, "Required in this dataset\n");
+                                }
+                                let label = ui.add(egui::Label::new(text).sense(egui::Sense::click_and_drag()))
+                                    .on_hover_text(hover);
+
+                                if parsed.is_weighted() {
+                                    let mut weight = parsed.weight();
+                                    let response = ui.add(egui::DragValue::new(&mut weight)
+                                        .speed(0.01)
+                                        .range(0.0..=3.0)
+                                        .max_decimals(2));
+                                    if response.changed() {
+                                        parsed.set_weight(weight);
+                                        actions.push(TagAction::Reweight { idx: tag_idx, text: parsed.to_text() });
+                                    }
+                                }
+                                label
+                            }).inner
+                        });
+
+                        // Confidence bar along the bottom edge, highlighted when it needs review
+                        if let Some(confidence) = confidence {
+                            let rect = chip.response.rect;
+                            let bar = egui::Rect::from_min_size(
+                                egui::pos2(rect.left(), rect.bottom() - 3.0),
+                                egui::vec2(rect.width() * confidence.clamp(0.0, 1.0), 3.0),
+                            );
+                            let color = if confidence < review_below {
+                                ui.visuals().warn_fg_color
+                            } else {
+                                ui.visuals().selection.bg_fill
+                            };
+                            ui.painter().rect_filled(bar, 1.0, color);
+                        }
+
+                        let label = chip.inner;
+                        label.dnd_set_drag_payload(tag_idx);
+                        if label.clicked() {
+                            actions.push(TagAction::Remove(tag.clone()));
+                        }
+                        label.context_menu(|ui| {
+                            if ui.button("Rename").clicked() {
+                                renaming = Some((tag_idx, tag.clone()));
+                                ui.close_menu();
+                            }
+                            if ui.button("Search images with this tag").clicked() {
+                                actions.push(TagAction::Search(tag.clone()));
+                                ui.close_menu();
+                            }
+                            if ui.button("Remove from all images").clicked() {
+                                actions.push(TagAction::RemoveFromAll(tag.clone()));
+                                ui.close_menu();
+                            }
+                            if category.is_none() && ui.button("Add to custom tags").clicked() {
+                                actions.push(TagAction::AddToVocabulary(tag.clone()));
+                                ui.close_menu();
+                            }
+                        });
+
+                        // Dropping a chip inserts it before the one it is released on
+                        if chip.response.dnd_hover_payload::<usize>().is_some() {
+                            let rect = chip.response.rect;
+                            ui.painter().vline(
+                                rect.left() - 4.0,
+                                rect.y_range(),
+                                egui::Stroke::new(2.0, ui.visuals().selection.stroke.color),
+                            );
+                        }
+                        if let Some(from) = chip.response.dnd_release_payload::<usize>() {
+                            actions.push(TagAction::Move { from: *from, to: tag_idx });
+                        }
+                    }
+
+                    // Dropping past the last chip moves the tag to the end
+                    if egui::DragAndDrop::has_payload_of_type::<usize>(ui.ctx()) {
+                        let (rect, response) = ui.allocate_exact_size(egui::vec2(32.0, 24.0), egui::Sense::hover());
+                        if response.dnd_hover_payload::<usize>().is_some() {
+                            ui.painter().vline(
+                                rect.left() + 4.0,
+                                rect.y_range(),
+                                egui::Stroke::new(2.0, ui.visuals().selection.stroke.color),
+                            );
+                        }
+                        if let Some(from) = response.dnd_release_payload::<usize>() {
+                            actions.push(TagAction::Move { from: *from, to: tags.len() });
+                        }
+                    }
+                });
+            });
+
+        if self.renaming_tag.is_none() {
+            self.renaming_tag = renaming;
+        }
+        for action in actions {
+            self.apply_tag_action(action);
+        }
+    }
+
+    fn load_custom_tags(&mut self, dir: &Path) {
+        let path = DatasetSettings::custom_tags_path(dir);
+        self.custom_tags.clear();
+        if path.exists() {
+            match TagSource::load_from_csv(&path) {
+                Ok(source) => {
+                    self.custom_tags = source.tags.into_values().collect();
+                    self.custom_tags.sort_by(|a, b| a.name.cmp(&b.name));
+                    // The file stores custom tags as general ones for other tools
+                    for tag in &mut self.custom_tags {
+                        if tag.category == TagCategory::General {
+                            tag.category = TagCategory::Custom;
+                        }
+                    }
+                }
+                Err(err) => eprintln!("Failed to load custom tags {}: {}", path.display(), err),
+            }
+        }
+        self.refresh_custom_source();
+    }
+
+    fn refresh_custom_source(&mut self) {
+        let source = self.current_dir.as_ref()
+            .filter(|_| !self.custom_tags.is_empty())
+            .map(|dir| {
+                let tags = self.custom_tags.iter().map(|tag| (tag.name.clone(), tag.clone())).collect();
+                TagSource::from_tags("Custom tags", &DatasetSettings::custom_tags_path(dir), tags)
+            });
+        self.booru_manager.set_custom_source(source);
+    }
+
+    // Writes the vocabulary and makes the changes visible to suggestions and lookups
+    fn save_custom_tags(&mut self) {
+        let Some(dir) = self.current_dir.clone() else {
+            return;
+        };
+        let path = DatasetSettings::custom_tags_path(&dir);
+        let result = if self.custom_tags.is_empty() {
+            if path.exists() { fs::remove_file(&path).map_err(Into::into) } else { Ok(()) }
+        } else {
+            tag_csv::write_tag_csv(&path, &self.custom_tags)
+        };
+        if let Err(err) = result {
+            self.feedback_message = Some(format!("Failed to save custom tags: {}", err));
+            self.feedback_timer = Some(std::time::Instant::now());
+        }
+        self.refresh_custom_source();
+    }
+
+    fn add_custom_tag(&mut self) {
+        let form = std::mem::take(&mut self.custom_tag_form);
+        let name = tag_syntax::lookup_name(&form.name);
+        if name.is_empty() {
+            return;
+        }
+        let tag = BooruTag {
+            name: name.clone(),
+            tag_type: CategoryScheme::Danbooru.tag_type(form.category),
+            category: form.category,
+            post_count: 0,
+            aliases: form.aliases.split(',')
+                .map(tag_syntax::lookup_name)
+                .filter(|alias| !alias.is_empty())
+                .collect(),
+            description: None,
+            translations: Vec::new(),
+        };
+
+        match self.custom_tags.iter_mut().find(|existing| existing.name == name) {
+            Some(existing) => *existing = tag,
+            None => {
+                self.custom_tags.push(tag);
+                self.custom_tags.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+        }
+        self.save_custom_tags();
+        self.feedback_message = Some(format!("Added '{}' to the custom tags", name));
+        self.feedback_timer = Some(std::time::Instant::now());
+    }
+
+    fn draw_custom_tags(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new(format!("Custom Tags ({})", self.custom_tags.len()))
+            .id_salt("custom_tags")
+            .show(ui, |ui| {
+                if self.current_dir.is_none() {
+                    ui.label("Open a directory to edit its custom tags.");
+                    return;
+                }
+
+                let mut changed = false;
+                let mut remove = None;
+                egui::Grid::new("custom_tags_grid").striped(true).show(ui, |ui| {
+                    for (idx, tag) in self.custom_tags.iter_mut().enumerate() {
+                        ui.label(&tag.name);
+                        egui::ComboBox::from_id_salt(("custom_tag_category", idx))
+                            .selected_text(tag.category.label())
+                            .show_ui(ui, |ui| {
+                                for category in CUSTOM_TAG_CATEGORIES {
+                                    if ui.selectable_value(&mut tag.category, category, category.label()).changed() {
+                                        tag.tag_type = CategoryScheme::Danbooru.tag_type(category);
+                                        changed = true;
+                                    }
+                                }
+                            });
+                        let mut aliases = tag.aliases.join(", ");
+                        let response = ui.add(egui::TextEdit::singleline(&mut aliases)
+                            .hint_text("aliases")
+                            .desired_width(120.0));
+                        if response.changed() {
+                            tag.aliases = aliases.split(',').map(|a| a.trim().to_string()).collect();
+                        }
+                        if response.lost_focus() {
+                            tag.aliases = aliases.split(',')
+                                .map(tag_syntax::lookup_name)
+                                .filter(|alias| !alias.is_empty())
+                                .collect();
+                            changed = true;
+                        }
+                        if ui.small_button("✖").clicked() {
+                            remove = Some(idx);
+                        }
+                        ui.end_row();
+                    }
+                });
+                if let Some(idx) = remove {
+                    self.custom_tags.remove(idx);
+                    changed = true;
+                }
+                if changed {
+                    self.save_custom_tags();
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.custom_tag_form.name)
+                        .hint_text("tag name")
+                        .desired_width(120.0));
+                    egui::ComboBox::from_id_salt("new_custom_tag_category")
+                        .selected_text(self.custom_tag_form.category.label())
+                        .show_ui(ui, |ui| {
+                            for category in CUSTOM_TAG_CATEGORIES {
+                                ui.selectable_value(&mut self.custom_tag_form.category, category, category.label());
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.custom_tag_form.aliases)
+                        .hint_text("aliases, comma separated")
+                        .desired_width(180.0));
+                    if ui.add_enabled(!self.custom_tag_form.name.trim().is_empty(), egui::Button::new("Add"))
+                        .clicked()
+                    {
+                        self.add_custom_tag();
+                    }
                 });
+            });
+    }
+
+    fn apply_tag_action(&mut self, action: TagAction) {
+        let before = self.images.get(self.current_image_idx).map(|image| image.tags.clone());
+        self.apply_tag_action_unchecked(action);
+
+        // Single-image edits may not drop a required tag either
+        let (Some(before), Some(image)) = (before, self.images.get_mut(self.current_image_idx)) else {
+            return;
+        };
+        let broken = self.dataset_settings.tag_policy.broken_requirements(&before, &image.tags);
+        if !broken.is_empty() {
+            image.tags = before;
+            self.feedback_message = Some(format!("{} is required in this dataset", broken.join(", ")));
+            self.feedback_timer = Some(std::time::Instant::now());
+        }
+    }
 
+    fn apply_tag_action_unchecked(&mut self, action: TagAction) {
+        match action {
+            TagAction::Add(tag) => self.handle_tag_addition_for_image(tag),
+            TagAction::Remove(tag) => self.handle_tag_removal_for_image(tag),
+            TagAction::RemoveFromAll(tag) => self.remove_tag_from_all(&tag),
+            TagAction::Search(tag) => self.search_tag = tag_syntax::lookup_name(&tag),
+            TagAction::AddToVocabulary(tag) => {
+                self.custom_tag_form = CustomTagForm {
+                    name: tag_syntax::lookup_name(&tag),
+                    ..CustomTagForm::default()
+                };
+                self.add_custom_tag();
+            }
+            TagAction::Rename { idx, text } => {
+                let text = self.dataset_settings.tag_style.apply(text.trim());
+                if let Some(image) = self.images.get_mut(self.current_image_idx) {
+                    if idx >= image.tags.len() || image.tags[idx] == text {
+                        return;
+                    }
+                    if text.is_empty() {
+                        image.tags.remove(idx);
+                    } else {
+                        image.tags[idx] = text;
+                        let mut seen = HashSet::new();
+                        image.tags.retain(|t| seen.insert(t.clone()));
+                    }
+                    self.modified_files.insert(image.path.clone(), true);
+                    self.tags_revision += 1;
+                }
+            }
+            TagAction::Reweight { idx, text } => {
+                if let Some(image) = self.images.get_mut(self.current_image_idx) {
+                    if let Some(tag) = image.tags.get_mut(idx) {
+                        *tag = text;
+                        self.modified_files.insert(image.path.clone(), true);
+                        self.tags_revision += 1;
+                    }
+                }
+            }
+            TagAction::Move { from, to } => {
+                if let Some(image) = self.images.get_mut(self.current_image_idx) {
+                    // `to` is the index the tag is dropped before; the length drops it at the end
+                    if from == to || from + 1 == to || from >= image.tags.len() || to > image.tags.len() {
+                        return;
+                    }
+                    let tag = image.tags.remove(from);
+                    let to = if from < to { to - 1 } else { to };
+                    image.tags.insert(to, tag);
+                    self.modified_files.insert(image.path.clone(), true);
+                    self.tags_revision += 1;
+                    // A manual order would be undone by re-sorting on the next image change
+                    self.current_sort_type = None;
+                }
+            }
         }
     }
 
     fn handle_tag_addition_for_image(&mut self, tag: String) {
+        let policy = &self.dataset_settings.tag_policy;
+        if policy.is_forbidden(&tag) {
+            if policy.strip_forbidden {
+                self.feedback_message = Some(format!("'{}' is forbidden in this dataset", tag));
+                self.feedback_timer = Some(std::time::Instant::now());
+                return;
+            }
+            self.feedback_message = Some(format!("Warning: '{}' is forbidden in this dataset", tag));
+            self.feedback_timer = Some(std::time::Instant::now());
+        }
         if let Some(current_image) = self.images.get_mut(self.current_image_idx) {
+            if current_image.tags.contains(&tag) {
+                return;
+            }
             if self.prepend_tags {
                 current_image.tags.insert(0, tag);
             } else {
                 current_image.tags.push(tag);
             }
-            let mut seen = std::collections::HashSet::new();
-            current_image.tags.retain(|t| seen.insert(t.clone()));
             self.modified_files.insert(current_image.path.clone(), true);
+            self.tags_revision += 1;
         }
     }
 
@@ -1011,6 +4663,7 @@ impl ImageTagger {
         if let Some(current_image) = self.images.get_mut(self.current_image_idx) {
             current_image.tags.retain(|t| t != &tag);
             self.modified_files.insert(current_image.path.clone(), true);
+            self.tags_revision += 1;
         }
     }
 }
@@ -1024,7 +4677,181 @@ impl eframe::App for ImageTagger {
 }
 
 
+// `apply-rules <rules-file> <dataset-dir> [--dry-run] [--all-variants]`
+fn run_rules_cli(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (Some(rules_path), Some(dataset_dir)) = (args.first(), args.get(1)) else {
+        return Err("usage: apply-rules <rules-file> <dataset-dir> [--dry-run] [--all-variants]".into());
+    };
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let rules = RuleSet::load(Path::new(rules_path))?;
+
+    let mut tagger = ImageTagger {
+        bulk_all_variants: args.iter().any(|arg| arg == "--all-variants"),
+        ..ImageTagger::default()
+    };
+    tagger.read_dataset(Path::new(dataset_dir));
+    let report = tagger.run_rules(&rules, dry_run);
+
+    let mut per_rule: BTreeMap<&str, usize> = BTreeMap::new();
+    for (path, changes) in &report.images {
+        println!("{}", path.display());
+        for change in changes {
+            println!("  [{}] {}", change.rule, change.description);
+            *per_rule.entry(&change.rule).or_insert(0) += 1;
+        }
+    }
+    for (rule, count) in &per_rule {
+        println!("{}: {} edits", rule, count);
+    }
+
+    let verb = if dry_run { "would change" } else { "changed" };
+    println!("{} {} of {} images", verb, report.images.len(), tagger.images.len());
+    if dry_run {
+        return Ok(());
+    }
+    tagger.enforce_output_rules();
+    for image in &tagger.images {
+        if tagger.modified_files.contains_key(&image.path) {
+            tagger.save_tags_for_image(image)?;
+        }
+    }
+    Ok(())
+}
+
+// `validate <dataset-dir> [--json FILE] [--csv FILE] [--decode] [--tags CSV]...`; a FILE of `-`
+// writes to stdout. Returns whether the dataset is clean.
+fn run_validate_cli(args: &[String]) -> Result<bool, Box<dyn std::error::Error>> {
+    let usage = "usage: validate <dataset-dir> [--json FILE] [--csv FILE] [--decode] [--tags CSV]...";
+    let Some(dataset_dir) = args.first().filter(|arg| !arg.starts_with("--")) else {
+        return Err(usage.into());
+    };
+    let mut json_path = None;
+    let mut csv_path = None;
+    let mut decode = false;
+    let mut tag_csvs = Vec::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--json" => json_path = Some(rest.next().ok_or(usage)?.clone()),
+            "--csv" => csv_path = Some(rest.next().ok_or(usage)?.clone()),
+            "--tags" => tag_csvs.push(PathBuf::from(rest.next().ok_or(usage)?)),
+            "--decode" => decode = true,
+            _ => return Err(format!("unknown argument {}\n{}", arg, usage).into()),
+        }
+    }
+
+    let mut tagger = ImageTagger {
+        app_settings: AppSettings::load(),
+        ..ImageTagger::default()
+    };
+    // Same tag databases as the editor unless given explicitly
+    if tag_csvs.is_empty() {
+        tag_csvs = tagger.app_settings.tag_sources.iter().filter(|path| path.exists()).cloned().collect();
+        if tag_csvs.is_empty() {
+            tag_csvs.extend(ImageTagger::bundled_tag_csv());
+        }
+    }
+    for path in &tag_csvs {
+        // Canonical like the GUI's sources, so the dataset's disabled sources match
+        let path = path.canonicalize().unwrap_or_else(|_| path.clone());
+        tagger.booru_manager.load_from_csv(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    let dataset_dir = Path::new(dataset_dir);
+    tagger.read_dataset(dataset_dir);
+    tagger.load_custom_tags(dataset_dir);
+    tagger.booru_manager.set_disabled_sources(&tagger.dataset_settings.disabled_tag_sources);
+    let report = tagger.lint(decode).unwrap_or_default();
+
+    let to_stdout = [&json_path, &csv_path].iter().any(|path| path.as_deref() == Some("-"));
+    match json_path.as_deref() {
+        Some("-") => println!("{}", report.to_json()?),
+        Some(path) => report.write_json(Path::new(path))?,
+        None => {}
+    }
+    match csv_path.as_deref() {
+        Some("-") => report.write_csv(std::io::stdout())?,
+        Some(path) => report.write_csv(fs::File::create(path)?)?,
+        None => {}
+    }
+
+    if !to_stdout {
+        for issue in &report.issues {
+            println!("{}: {} ({})", issue.kind.label(), issue.path.display(), issue.detail);
+        }
+        for (kind, count) in report.counts() {
+            println!("{}: {}", kind.label(), count);
+        }
+        if tag_csvs.is_empty() {
+            println!("No tag database found, unknown tags were not checked");
+        }
+        println!("{} problems in {} images", report.issues.len(), report.images);
+    }
+    Ok(report.issues.is_empty())
+}
+
+// Old tag line with removed tags struck through, new one with added tags highlighted
+fn draw_tag_diff(ui: &mut egui::Ui, before: &[String], after: &[String]) {
+    let names = |tags: &[String]| -> HashSet<String> { tags.iter().map(|tag| tag_syntax::lookup_name(tag)).collect() };
+    let (old, new) = (names(before), names(after));
+    let removed_color = ui.visuals().error_fg_color;
+    let added_color = egui::Color32::from_rgb(90, 170, 90);
+    ui.horizontal_wrapped(|ui| {
+        ui.monospace("-");
+        for tag in before {
+            let mut text = egui::RichText::new(tag);
+            if !new.contains(&tag_syntax::lookup_name(tag)) {
+                text = text.color(removed_color).strikethrough();
+            }
+            ui.label(text);
+        }
+    });
+    ui.horizontal_wrapped(|ui| {
+        ui.monospace("+");
+        for tag in after {
+            let mut text = egui::RichText::new(tag);
+            if !old.contains(&tag_syntax::lookup_name(tag)) {
+                text = text.color(added_color);
+            }
+            ui.label(text);
+        }
+    });
+}
+
+fn scope_picker(ui: &mut egui::Ui, id: &str, scope: &mut AutoTagScope, search_count: usize, image_count: usize) {
+    let label = |scope| match scope {
+        AutoTagScope::CurrentImage => "Current image".to_string(),
+        AutoTagScope::SearchResults => format!("Search results ({})", search_count),
+        AutoTagScope::AllImages => format!("All images ({})", image_count),
+    };
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(label(*scope))
+        .show_ui(ui, |ui| {
+            for option in [AutoTagScope::CurrentImage, AutoTagScope::SearchResults, AutoTagScope::AllImages] {
+                ui.selectable_value(scope, option, label(option));
+            }
+        });
+}
+
 fn main() -> Result<(), eframe::Error> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("validate") {
+        match run_validate_cli(&args[2..]) {
+            Ok(true) => return Ok(()),
+            Ok(false) => std::process::exit(1),
+            Err(err) => {
+                eprintln!("validate failed: {}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+    if args.get(1).map(String::as_str) == Some("apply-rules") {
+        if let Err(err) = run_rules_cli(&args[2..]) {
+            eprintln!("apply-rules failed: {}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([1600.0, 800.0]),
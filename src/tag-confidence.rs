@@ -0,0 +1,133 @@
+// Per-tag confidences from auto-taggers, kept for review. External tools write them either inline
+// as `tag:0.87` caption lists or as an `image.confidences.json` sidecar; the editor keeps the
+// unreviewed ones in that sidecar.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+
+use crate::tag_syntax::lookup_name;
+
+// Lookup name -> confidence in 0..=1
+pub type Confidences = HashMap<String, f32>;
+
+pub fn sidecar_path(image_path: &Path) -> PathBuf {
+    image_path.with_extension("confidences.json")
+}
+
+// `tag:0.87` -> ("tag", 0.87); `rating:general` and weights like `(tag:1.2)` are left alone
+fn split_confidence(tag: &str) -> Option<(&str, f32)> {
+    let (name, value) = tag.rsplit_once(':')?;
+    let confidence: f32 = value.trim().parse().ok()?;
+    let name = name.trim();
+    ((0.0..=1.0).contains(&confidence) && !name.is_empty()).then_some((name, confidence))
+}
+
+// Strips the confidences from a `tag:0.87` list. Only lists where every tag carries one count, so
+// ordinary captions with colons in them are never touched.
+pub fn strip_inline(tags: &mut [String]) -> Option<Confidences> {
+    if tags.is_empty() {
+        return None;
+    }
+    let parsed: Vec<(String, f32)> = tags.iter()
+        .map(|tag| split_confidence(tag).map(|(name, confidence)| (name.to_string(), confidence)))
+        .collect::<Option<_>>()?;
+
+    let mut confidences = Confidences::new();
+    for (tag, (name, confidence)) in tags.iter_mut().zip(parsed) {
+        confidences.insert(lookup_name(&name), confidence);
+        *tag = name;
+    }
+    Some(confidences)
+}
+
+// Accepts `{"tag": 0.87}`, `{"tags": {...}}`, `[["tag", 0.87]]` or
+// `[{"tag": "tag", "confidence": 0.87}]` (also `name`, `score` and `probability`); the tags come
+// back most confident first
+pub fn load_sidecar(path: &Path) -> Result<Vec<(String, f32)>, Box<dyn std::error::Error>> {
+    let value: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let value = match value {
+        Value::Object(mut object) if object.contains_key("tags") => object.remove("tags").unwrap_or_default(),
+        value => value,
+    };
+
+    let entry = |value: &Value| -> Option<(String, f32)> {
+        match value {
+            Value::Array(pair) => Some((pair.first()?.as_str()?.to_string(), pair.get(1)?.as_f64()? as f32)),
+            Value::Object(object) => {
+                let name = ["tag", "name"].iter().find_map(|key| object.get(*key)?.as_str())?;
+                let confidence = ["confidence", "score", "probability"].iter().find_map(|key| object.get(*key)?.as_f64())?;
+                Some((name.to_string(), confidence as f32))
+            }
+            _ => None,
+        }
+    };
+    let mut tags: Vec<(String, f32)> = match &value {
+        Value::Object(object) => object.iter()
+            .map(|(name, confidence)| Some((name.clone(), confidence.as_f64()? as f32)))
+            .collect::<Option<_>>(),
+        Value::Array(entries) => entries.iter().map(entry).collect::<Option<_>>(),
+        _ => None,
+    }
+    .ok_or("expected tag confidences")?;
+
+    if tags.iter().any(|(_, confidence)| !(0.0..=1.0).contains(confidence)) {
+        return Err("confidences must be between 0 and 1".into());
+    }
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+    Ok(tags)
+}
+
+// Writes the confidences of `tags`, or removes a confidence sidecar that has none left. Files that
+// share the name but do not hold confidences are never overwritten or removed.
+pub fn save_sidecar(image_path: &Path, confidences: &Confidences, tags: &[&String]) -> Result<(), std::io::Error> {
+    let path = sidecar_path(image_path);
+    if path.exists() && load_sidecar(&path).is_err() {
+        eprintln!("Leaving {} alone, it does not hold tag confidences", path.display());
+        return Ok(());
+    }
+    let kept: BTreeMap<String, f32> = tags.iter()
+        .map(|tag| lookup_name(tag))
+        .filter_map(|name| confidences.get(&name).map(|&confidence| (name, confidence)))
+        .collect();
+
+    if kept.is_empty() {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    fs::write(path, serde_json::to_string_pretty(&kept)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_foreign_sidecars() {
+        let dir = std::env::temp_dir().join(format!("tag-confidence-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("a.png");
+        let path = sidecar_path(&image);
+        assert_eq!(path, dir.join("a.confidences.json"));
+
+        let (solo, smile) = ("solo".to_string(), "smile".to_string());
+        let confidences = Confidences::from([("solo".to_string(), 0.4)]);
+        save_sidecar(&image, &confidences, &[&solo, &smile]).unwrap();
+        assert_eq!(load_sidecar(&path).unwrap(), [("solo".to_string(), 0.4)]);
+
+        // Nothing left to keep removes our own file
+        save_sidecar(&image, &confidences, &[&smile]).unwrap();
+        assert!(!path.exists());
+
+        // Another tool's file is neither overwritten nor removed
+        fs::write(&path, r#"{"source": "gallery"}"#).unwrap();
+        save_sidecar(&image, &confidences, &[&solo]).unwrap();
+        save_sidecar(&image, &confidences, &[]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"source": "gallery"}"#);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
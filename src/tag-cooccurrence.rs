@@ -0,0 +1,227 @@
+// Sparse tag co-occurrence counts for "frequently used with" suggestions, built from the dataset's
+// own captions or loaded from a `tag,other_tag,count` table exported from a booru.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use csv::ReaderBuilder;
+
+use crate::tag_syntax::lookup_name;
+
+#[derive(Debug, Clone)]
+pub struct RelatedTag {
+    pub name: String,
+    // Average share of the given tags' images that also carry this tag, 0..=1
+    pub score: f32,
+    // Images (or posts) carrying this tag together with any of the given tags
+    pub count: u32,
+}
+
+#[derive(Default)]
+pub struct CooccurrenceMatrix {
+    ids: HashMap<String, u32>,
+    names: Vec<String>,
+    // Images carrying each tag; 0 when only pair counts are known
+    counts: Vec<u32>,
+    neighbors: Vec<HashMap<u32, u32>>,
+    // Pairs seen fewer times than this are treated as noise
+    min_support: u32,
+}
+
+impl CooccurrenceMatrix {
+    pub fn from_tag_lists<'a>(lists: impl IntoIterator<Item = &'a [String]>) -> Self {
+        let mut matrix = Self { min_support: 2, ..Self::default() };
+        for tags in lists {
+            let mut ids: Vec<u32> = tags.iter()
+                .map(|tag| lookup_name(tag))
+                .filter(|name| !name.is_empty())
+                .map(|name| matrix.id(&name))
+                .collect();
+            ids.sort_unstable();
+            ids.dedup();
+
+            for (pos, &a) in ids.iter().enumerate() {
+                matrix.counts[a as usize] += 1;
+                for &b in &ids[pos + 1..] {
+                    matrix.add_pair(a, b, 1);
+                }
+            }
+        }
+        matrix
+    }
+
+    // Rows of `tag,other_tag,count`, optionally with a header; each pair is used in both directions
+    pub fn load_table(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(path)?;
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(contents.as_bytes());
+
+        let mut matrix = Self { min_support: 1, ..Self::default() };
+        for (row, record) in rdr.records().enumerate() {
+            let record = record?;
+            let (Some(a), Some(b), Some(count)) = (record.get(0), record.get(1), record.get(2)) else {
+                continue;
+            };
+            let Ok(count) = count.trim().parse::<u32>() else {
+                if row == 0 {
+                    continue;
+                }
+                return Err(format!("Invalid count on line {}: {}", row + 1, count).into());
+            };
+            let (a, b) = (lookup_name(a), lookup_name(b));
+            if a.is_empty() || b.is_empty() || a == b {
+                continue;
+            }
+            let (a, b) = (matrix.id(&a), matrix.id(&b));
+            matrix.add_pair(a, b, count);
+        }
+        Ok(matrix)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn tag_count(&self) -> usize {
+        self.names.len()
+    }
+
+    // Tags that most often appear alongside `tags` but are not among them. `tag_count` supplies
+    // the number of images per tag when the matrix only has pair counts.
+    pub fn related(
+        &self,
+        tags: &[String],
+        limit: usize,
+        tag_count: impl Fn(&str) -> Option<u64>,
+    ) -> Vec<RelatedTag> {
+        let present: HashSet<String> = tags.iter().map(|tag| lookup_name(tag)).collect();
+        let known: Vec<u32> = present.iter().filter_map(|name| self.ids.get(name).copied()).collect();
+        if known.is_empty() {
+            return Vec::new();
+        }
+
+        let mut scores: HashMap<u32, (f32, u32)> = HashMap::new();
+        for &tag in &known {
+            let neighbors = &self.neighbors[tag as usize];
+            let total = match self.counts[tag as usize] {
+                0 => tag_count(&self.names[tag as usize])
+                    .map(|count| count as f32)
+                    .unwrap_or_else(|| neighbors.values().copied().max().unwrap_or(1) as f32),
+                count => count as f32,
+            };
+            for (&other, &count) in neighbors {
+                if count < self.min_support || present.contains(&self.names[other as usize]) {
+                    continue;
+                }
+                let entry = scores.entry(other).or_insert((0.0, 0));
+                entry.0 += (count as f32 / total).min(1.0);
+                entry.1 = entry.1.max(count);
+            }
+        }
+
+        let mut related: Vec<RelatedTag> = scores.into_iter()
+            .map(|(id, (score, count))| RelatedTag {
+                name: self.names[id as usize].clone(),
+                score: score / known.len() as f32,
+                count,
+            })
+            .collect();
+        related.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+        related.truncate(limit);
+        related
+    }
+
+    fn id(&mut self, name: &str) -> u32 {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = self.names.len() as u32;
+        self.ids.insert(name.to_string(), id);
+        self.names.push(name.to_string());
+        self.counts.push(0);
+        self.neighbors.push(HashMap::new());
+        id
+    }
+
+    fn add_pair(&mut self, a: u32, b: u32, count: u32) {
+        *self.neighbors[a as usize].entry(b).or_insert(0) += count;
+        *self.neighbors[b as usize].entry(a).or_insert(0) += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(list: &[&str]) -> Vec<String> {
+        list.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    fn names(related: &[RelatedTag]) -> Vec<&str> {
+        related.iter().map(|tag| tag.name.as_str()).collect()
+    }
+
+    fn load(name: &str, contents: &str) -> Result<CooccurrenceMatrix, Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join(format!("tag-cooccurrence-{}-{}.csv", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        let matrix = CooccurrenceMatrix::load_table(&path);
+        let _ = fs::remove_file(&path);
+        matrix
+    }
+
+    #[test]
+    fn ranks_dataset_tags_by_share_of_images() {
+        let lists = [
+            tags(&["a", "b", "c"]),
+            tags(&["a", "b"]),
+            tags(&["(a:1.2)", "b", "d"]),
+            tags(&["a", "c", "c"]),
+            tags(&["e"]),
+        ];
+        let matrix = CooccurrenceMatrix::from_tag_lists(lists.iter().map(Vec::as_slice));
+        assert_eq!(matrix.tag_count(), 5);
+
+        // `d` was seen with `a` only once, under the minimum support
+        let related = matrix.related(&tags(&["a"]), 10, |_| None);
+        assert_eq!(names(&related), ["b", "c"]);
+        assert_eq!((related[0].score, related[0].count), (0.75, 3));
+        assert_eq!((related[1].score, related[1].count), (0.5, 2));
+
+        // Scores average over the given tags and skip the ones already present
+        let related = matrix.related(&tags(&["a", "b"]), 10, |_| None);
+        assert_eq!(names(&related), ["c"]);
+        assert_eq!(related[0].score, 0.25);
+
+        assert_eq!(names(&matrix.related(&tags(&["a"]), 1, |_| None)), ["b"]);
+        assert!(matrix.related(&tags(&["e"]), 10, |_| None).is_empty());
+        assert!(matrix.related(&tags(&["unknown"]), 10, |_| None).is_empty());
+    }
+
+    #[test]
+    fn loads_tables_and_skips_malformed_rows() {
+        let matrix = load("table", "tag,other_tag,count\n\
+            long_hair,very_long_hair,50\n\
+            long_hair,blue_eyes,30\n\
+            short_row,\n\
+            long_hair,long_hair,9\n\
+            ,blue_eyes,3\n\
+            blue eyes,smile,10\n").unwrap();
+        assert_eq!(matrix.tag_count(), 4);
+
+        let related = matrix.related(&tags(&["long_hair"]), 10, |tag| (tag == "long_hair").then_some(100));
+        assert_eq!(names(&related), ["very_long_hair", "blue_eyes"]);
+        assert_eq!(related[0].score, 0.5);
+
+        // Without a tag count the strongest pair stands in for it, and pairs work both ways
+        let related = matrix.related(&tags(&["smile"]), 10, |_| None);
+        assert_eq!(names(&related), ["blue_eyes"]);
+        assert_eq!(related[0].score, 1.0);
+        let related = matrix.related(&tags(&["blue_eyes"]), 10, |_| None);
+        assert_eq!(names(&related), ["long_hair", "smile"]);
+
+        let err = load("bad-count", "long_hair,blue_eyes,30\nlong_hair,smile,many\n").err().unwrap();
+        assert_eq!(err.to_string(), "Invalid count on line 2: many");
+    }
+}
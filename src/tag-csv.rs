@@ -0,0 +1,505 @@
+// Tag database CSV layouts: headerless Danbooru-style exports, e621 exports with headers,
+// and reduced 1-3 column lists. The layout is detected from the first rows of the file.
+
+use std::collections::HashMap;
+use std::path::Path;
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+
+use crate::booru_tag_manager::{BooruTag, TagCategory};
+
+// Rows sampled when guessing the meaning of headerless columns
+const SAMPLE_ROWS: usize = 200;
+// Skipped rows kept for the report; the total is always counted
+const MAX_REPORTED_ROWS: usize = 1000;
+
+const NAME_HEADERS: &[&str] = &["name", "tag", "tag_name"];
+const CATEGORY_HEADERS: &[&str] = &["category", "type", "tag_type", "tag_category"];
+const POST_COUNT_HEADERS: &[&str] = &["post_count", "count", "posts"];
+const ALIAS_HEADERS: &[&str] = &["aliases", "alias"];
+const TRANSLATION_HEADERS: &[&str] = &["translation", "translations"];
+const DESCRIPTION_HEADERS: &[&str] = &["description", "wiki", "body"];
+const OTHER_HEADERS: &[&str] = &["id", "is_locked", "created_at", "updated_at"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CategoryScheme {
+    // 0 general, 1 artist, 3 copyright, 4 character, 5 meta
+    Danbooru,
+    // 0 general, 1 artist, 3 copyright, 4 character, 5 species, 6 invalid, 7 meta, 8 lore
+    E621,
+}
+
+impl CategoryScheme {
+    // None for rows that should not be offered as tags at all
+    pub fn category(&self, tag_type: i32) -> Option<TagCategory> {
+        let category = match (self, tag_type) {
+            (_, 1) => TagCategory::Artist,
+            (_, 3) => TagCategory::Copyright,
+            (_, 4) => TagCategory::Character,
+            (CategoryScheme::Danbooru, 5) => TagCategory::Meta,
+            (CategoryScheme::E621, 5) => TagCategory::Species,
+            (CategoryScheme::E621, 6) => return None,
+            (CategoryScheme::E621, 7) => TagCategory::Meta,
+            (CategoryScheme::E621, 8) => TagCategory::Lore,
+            _ => TagCategory::General,
+        };
+        Some(category)
+    }
+
+    // Category id written for `category`; categories the scheme lacks, including the editor's own
+    // custom category, are stored as general so other tools can read the file
+    pub fn tag_type(&self, category: TagCategory) -> i32 {
+        match (self, category) {
+            (_, TagCategory::General) => 0,
+            (_, TagCategory::Artist) => 1,
+            (_, TagCategory::Copyright) => 3,
+            (_, TagCategory::Character) => 4,
+            (CategoryScheme::Danbooru, TagCategory::Meta) => 5,
+            (CategoryScheme::E621, TagCategory::Species) => 5,
+            (CategoryScheme::E621, TagCategory::Meta) => 7,
+            (CategoryScheme::E621, TagCategory::Lore) => 8,
+            _ => 0,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            CategoryScheme::Danbooru => "Danbooru",
+            CategoryScheme::E621 => "e621",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvSchema {
+    pub has_header: bool,
+    pub name: usize,
+    pub category: Option<usize>,
+    pub post_count: Option<usize>,
+    pub aliases: Option<usize>,
+    pub description: Option<usize>,
+    pub translations: Option<usize>,
+    pub scheme: CategoryScheme,
+}
+
+impl CsvSchema {
+    // The layout of the bundled Danbooru list: `name,category,post_count,aliases` without a header
+    pub fn danbooru() -> Self {
+        Self {
+            has_header: false,
+            name: 0,
+            category: Some(1),
+            post_count: Some(2),
+            aliases: Some(3),
+            description: None,
+            translations: None,
+            scheme: CategoryScheme::Danbooru,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut columns = vec![(self.name, "name")];
+        columns.extend(self.category.map(|idx| (idx, "category")));
+        columns.extend(self.post_count.map(|idx| (idx, "post_count")));
+        columns.extend(self.aliases.map(|idx| (idx, "aliases")));
+        columns.extend(self.description.map(|idx| (idx, "description")));
+        columns.extend(self.translations.map(|idx| (idx, "translations")));
+        columns.sort();
+        let columns: Vec<&str> = columns.into_iter().map(|(_, name)| name).collect();
+
+        format!(
+            "{} categories, {}, columns {}",
+            self.scheme.label(),
+            if self.has_header { "with header" } else { "no header" },
+            columns.join(",")
+        )
+    }
+
+    // Guesses the layout from the first records; `file_name` is used to spot e621 exports
+    pub fn detect(records: &[StringRecord], file_name: &str) -> Self {
+        let mut schema = match records.first().and_then(Self::from_header) {
+            Some(schema) => schema,
+            None => Self::from_values(records),
+        };
+        if file_name.to_lowercase().contains("e621") {
+            schema.scheme = CategoryScheme::E621;
+        }
+        schema
+    }
+
+    fn from_header(record: &StringRecord) -> Option<Self> {
+        let fields: Vec<String> = record.iter().map(|f| f.trim().to_lowercase()).collect();
+        let is_header = fields.iter().any(|f| NAME_HEADERS.contains(&f.as_str()))
+            && fields.iter().all(|f| {
+                NAME_HEADERS.contains(&f.as_str())
+                    || CATEGORY_HEADERS.contains(&f.as_str())
+                    || POST_COUNT_HEADERS.contains(&f.as_str())
+                    || ALIAS_HEADERS.contains(&f.as_str())
+                    || DESCRIPTION_HEADERS.contains(&f.as_str())
+                    || TRANSLATION_HEADERS.contains(&f.as_str())
+                    || OTHER_HEADERS.contains(&f.as_str())
+            });
+        if !is_header {
+            return None;
+        }
+
+        let column = |names: &[&str]| fields.iter().position(|f| names.contains(&f.as_str()));
+        Some(Self {
+            has_header: true,
+            name: column(NAME_HEADERS)?,
+            category: column(CATEGORY_HEADERS),
+            post_count: column(POST_COUNT_HEADERS),
+            aliases: column(ALIAS_HEADERS),
+            description: column(DESCRIPTION_HEADERS),
+            translations: column(TRANSLATION_HEADERS),
+            // An `id` column is how e621's db_export tag dumps start
+            scheme: if fields.first().map(String::as_str) == Some("id") {
+                CategoryScheme::E621
+            } else {
+                CategoryScheme::Danbooru
+            },
+        })
+    }
+
+    // Headerless files: the first column is the name, small integers are categories,
+    // other integers are post counts and text columns are aliases
+    fn from_values(records: &[StringRecord]) -> Self {
+        let width = records.iter().map(StringRecord::len).max().unwrap_or(1);
+        if width >= 4 {
+            return Self::danbooru();
+        }
+
+        let mut schema = Self {
+            has_header: false,
+            name: 0,
+            category: None,
+            post_count: None,
+            aliases: None,
+            description: None,
+            translations: None,
+            scheme: CategoryScheme::Danbooru,
+        };
+        for column in 1..width {
+            let values: Vec<&str> = records.iter()
+                .filter_map(|record| record.get(column))
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .collect();
+            let numbers: Option<Vec<u64>> = values.iter().map(|value| value.parse().ok()).collect();
+            match numbers {
+                Some(numbers) if schema.category.is_none()
+                    && schema.post_count.is_none()
+                    && numbers.iter().all(|&n| n <= 10) => schema.category = Some(column),
+                Some(_) if schema.post_count.is_none() => schema.post_count = Some(column),
+                None if schema.aliases.is_none() => schema.aliases = Some(column),
+                _ => {}
+            }
+        }
+        schema
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SkippedRow {
+    pub line: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    pub schema: String,
+    pub loaded: usize,
+    pub skipped_total: usize,
+    // The first `MAX_REPORTED_ROWS` skipped rows
+    pub skipped: Vec<SkippedRow>,
+}
+
+impl LoadReport {
+    fn skip(&mut self, line: u64, reason: String) {
+        self.skipped_total += 1;
+        if self.skipped.len() < MAX_REPORTED_ROWS {
+            self.skipped.push(SkippedRow { line, reason });
+        }
+    }
+}
+
+// Parses a tag database in any supported layout, reporting the fraction of the input read so far
+pub fn parse_tag_csv(
+    contents: &str,
+    file_name: &str,
+    mut progress: impl FnMut(f32),
+) -> (HashMap<String, BooruTag>, LoadReport) {
+    let total_bytes = contents.len().max(1) as f32;
+    let mut report = LoadReport::default();
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(contents.as_bytes());
+    let mut all = Vec::new();
+    let mut record = StringRecord::new();
+    loop {
+        match rdr.read_record(&mut record) {
+            Ok(true) => all.push(record.clone()),
+            Ok(false) => break,
+            // Errors without a position happened on the row the reader stopped at
+            Err(err) => {
+                let line = err.position().unwrap_or(rdr.position()).line();
+                report.skip(line, err.to_string());
+            }
+        }
+    }
+
+    let schema = CsvSchema::detect(&all[..all.len().min(SAMPLE_ROWS)], file_name);
+    report.schema = schema.describe();
+
+    // Raw category ids decide the scheme when the file name did not, so map them at the end
+    let mut rows: Vec<(u64, BooruTag)> = Vec::new();
+    let mut seen_e621_category = false;
+    let all = &all[usize::from(schema.has_header).min(all.len())..];
+
+    for (row, record) in all.iter().enumerate() {
+        let line = record.position().map_or(0, |p| p.line());
+        if row % 4096 == 0 {
+            let position = record.position().map_or(0, |p| p.byte());
+            progress(position as f32 / total_bytes);
+        }
+        match parse_row(record, &schema) {
+            Ok(tag) => {
+                seen_e621_category |= (6..=8).contains(&tag.tag_type);
+                rows.push((line, tag));
+            }
+            Err(reason) => report.skip(line, reason),
+        }
+    }
+
+    let scheme = if seen_e621_category { CategoryScheme::E621 } else { schema.scheme };
+    if scheme != schema.scheme {
+        report.schema = CsvSchema { scheme, ..schema }.describe();
+    }
+
+    let mut tags = HashMap::with_capacity(rows.len());
+    for (line, mut tag) in rows {
+        let Some(category) = scheme.category(tag.tag_type) else {
+            report.skip(line, format!("`{}` is in e621's invalid category", tag.name));
+            continue;
+        };
+        tag.category = category;
+        if tags.contains_key(&tag.name) {
+            report.skip(line, format!("duplicate tag `{}`", tag.name));
+            continue;
+        }
+        tags.insert(tag.name.clone(), tag);
+    }
+    report.loaded = tags.len();
+    report.skipped.sort_by_key(|row| row.line);
+    progress(1.0);
+
+    (tags, report)
+}
+
+fn parse_row(record: &StringRecord, schema: &CsvSchema) -> Result<BooruTag, String> {
+    let field = |idx: Option<usize>| idx.and_then(|idx| record.get(idx)).map(str::trim).unwrap_or("");
+
+    let name = field(Some(schema.name));
+    if name.is_empty() {
+        return Err(if record.len() <= schema.name {
+            format!("only {} column(s)", record.len())
+        } else {
+            "empty tag name".to_string()
+        });
+    }
+
+    let tag_type = match field(schema.category) {
+        "" => 0,
+        value => value.parse::<i32>().map_err(|_| format!("invalid category `{}`", value))?,
+    };
+    let post_count = match field(schema.post_count) {
+        "" => 0,
+        value => value.parse::<u64>().map_err(|_| format!("invalid post count `{}`", value))?,
+    };
+    let aliases = split_list(field(schema.aliases));
+    let translations = split_list(field(schema.translations));
+
+    let description = Some(field(schema.description))
+        .filter(|text| !text.is_empty())
+        .map(crate::tag_wiki::summarize);
+
+    Ok(BooruTag {
+        name: name.to_string(),
+        tag_type,
+        category: TagCategory::General,
+        post_count,
+        aliases,
+        description,
+        translations,
+    })
+}
+
+fn split_list(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+// Translation files list a canonical tag followed by one or more localized names per row,
+// as in the `tag,translation` files used by web UI tag autocompletion
+pub fn parse_translation_csv(contents: &str) -> Result<HashMap<String, Vec<String>>, Box<dyn std::error::Error>> {
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(contents.as_bytes());
+
+    let mut translations: HashMap<String, Vec<String>> = HashMap::new();
+    for (row, record) in rdr.records().enumerate() {
+        let record = record?;
+        let Some(tag) = record.get(0).map(str::trim).filter(|tag| !tag.is_empty()) else {
+            continue;
+        };
+        if row == 0 && NAME_HEADERS.contains(&tag.to_lowercase().as_str()) {
+            continue;
+        }
+        let names = record.iter()
+            .skip(1)
+            .flat_map(split_list)
+            .filter(|name| name != tag);
+        translations.entry(tag.replace(' ', "_")).or_default().extend(names);
+    }
+    translations.retain(|_, names| !names.is_empty());
+    Ok(translations)
+}
+
+// Writes tags in the bundled headerless `name,category,post_count,aliases` layout
+pub fn write_tag_csv(path: &Path, tags: &[BooruTag]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = WriterBuilder::new().has_headers(false).from_path(path)?;
+    for tag in tags {
+        writer.write_record([
+            tag.name.clone(),
+            tag.tag_type.to_string(),
+            tag.post_count.to_string(),
+            tag.aliases.join(","),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(text: &str) -> Vec<StringRecord> {
+        ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(text.as_bytes())
+            .records()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn parse(text: &str, file_name: &str) -> (HashMap<String, BooruTag>, LoadReport) {
+        parse_tag_csv(text, file_name, |_| {})
+    }
+
+    #[test]
+    fn detects_headerless_danbooru_exports() {
+        let rows = records("1girl,0,5975112,\"1girls,sole_female\"\nhighres,5,5214520,\"high_res,hires\"\n");
+        assert_eq!(CsvSchema::detect(&rows, "danbooru.csv"), CsvSchema::danbooru());
+    }
+
+    #[test]
+    fn detects_e621_exports() {
+        let rows = records("id,name,category,post_count\n1,solo,0,100\n");
+        let schema = CsvSchema::detect(&rows, "tags-2024-01-01.csv");
+        assert!(schema.has_header);
+        assert_eq!((schema.name, schema.category, schema.post_count), (1, Some(2), Some(3)));
+        assert_eq!(schema.scheme, CategoryScheme::E621);
+
+        // Headerless files are recognized by name
+        let rows = records("solo,0,100,\n");
+        assert_eq!(CsvSchema::detect(&rows, "E621_tags.csv").scheme, CategoryScheme::E621);
+    }
+
+    #[test]
+    fn detects_reduced_headerless_layouts() {
+        let schema = CsvSchema::detect(&records("smile,0,500\nsolo,4,1200\n"), "tags.csv");
+        assert_eq!((schema.category, schema.post_count, schema.aliases), (Some(1), Some(2), None));
+
+        let schema = CsvSchema::detect(&records("smile,500\nsolo,1200\n"), "tags.csv");
+        assert_eq!((schema.category, schema.post_count), (None, Some(1)));
+
+        let schema = CsvSchema::detect(&records("smile,happy\nsolo,alone\n"), "tags.csv");
+        assert_eq!((schema.category, schema.post_count, schema.aliases), (None, None, Some(1)));
+
+        let schema = CsvSchema::detect(&records("tag,count\nsmile,500\n"), "tags.csv");
+        assert!(schema.has_header);
+        assert_eq!((schema.name, schema.post_count), (0, Some(1)));
+    }
+
+    #[test]
+    fn parses_rows_and_reports_skipped_ones() {
+        let (tags, report) = parse(
+            "1girl,0,5975112,\"1girls,sole_female\"\n\
+             ,0,10,\n\
+             smile,0,lots,\n\
+             1girl,0,1,\n\
+             hatsune_miku,4,100,miku\n",
+            "danbooru.csv",
+        );
+        assert_eq!(report.loaded, 2);
+        assert_eq!(tags["1girl"].aliases, ["1girls", "sole_female"]);
+        assert_eq!(tags["1girl"].post_count, 5975112);
+        assert_eq!(tags["hatsune_miku"].category, TagCategory::Character);
+
+        let skipped: Vec<(u64, &str)> = report.skipped.iter().map(|row| (row.line, row.reason.as_str())).collect();
+        assert_eq!(skipped, [
+            (2, "empty tag name"),
+            (3, "invalid post count `lots`"),
+            (4, "duplicate tag `1girl`"),
+        ]);
+        assert_eq!(report.skipped_total, 3);
+    }
+
+    #[test]
+    fn parses_translation_rows() {
+        let translations = parse_translation_csv(
+            "tag,translation\n\
+             cat_ears,猫耳\n\
+             long hair,\"長髪, ロングヘア\",長い髪\n\
+             smile,smile\n\
+             ,孤児\n\
+             cat_ears,ネコミミ\n",
+        ).unwrap();
+        assert_eq!(translations.len(), 2);
+        assert_eq!(translations["cat_ears"], ["猫耳", "ネコミミ"]);
+        assert_eq!(translations["long_hair"], ["長髪", "ロングヘア", "長い髪"]);
+
+        // A first row that is not a header is kept
+        let translations = parse_translation_csv("name,名前\n").unwrap();
+        assert!(translations.is_empty());
+        let translations = parse_translation_csv("solo,ソロ\n").unwrap();
+        assert_eq!(translations["solo"], ["ソロ"]);
+    }
+
+    #[test]
+    fn maps_categories_by_scheme() {
+        let (tags, report) = parse("id,name,category,post_count\n1,wolf,5,10\n2,bad_tag,6,1\n3,lore_tag,8,2\n", "tags.csv");
+        assert_eq!(tags["wolf"].category, TagCategory::Species);
+        assert_eq!(tags["lore_tag"].category, TagCategory::Lore);
+        assert!(!tags.contains_key("bad_tag"));
+        assert_eq!(report.skipped_total, 1);
+
+        // e621-only ids switch a headerless file to e621 categories
+        let (tags, _) = parse("wolf,5,10\nlore_tag,8,2\n", "tags.csv");
+        assert_eq!(tags["wolf"].category, TagCategory::Species);
+
+        let (tags, _) = parse("absurdres,5,10\nrating_tag,9,0\n", "tags.csv");
+        assert_eq!(tags["absurdres"].category, TagCategory::Meta);
+        assert_eq!(tags["rating_tag"].category, TagCategory::General);
+
+        assert_eq!(CategoryScheme::Danbooru.tag_type(TagCategory::Custom), 0);
+        assert_eq!(CategoryScheme::Danbooru.tag_type(TagCategory::Species), 0);
+        assert_eq!(CategoryScheme::E621.tag_type(TagCategory::Species), 5);
+    }
+}
@@ -0,0 +1,378 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::booru_tag_manager::BooruTag;
+
+// Match quality, lower is better. Fuzzy matches add their edit distance.
+const TIER_EXACT: u8 = 0;
+const TIER_NAME_PREFIX: u8 = 1;
+const TIER_ALIAS_PREFIX: u8 = 2;
+const TIER_SUBSTRING: u8 = 3;
+const TIER_FUZZY: u8 = 4;
+const NO_MATCH: u8 = u8::MAX;
+
+// Trailing query characters a fuzzy prefix match has to keep as typed
+const TYPED_TAIL: usize = 2;
+
+struct IndexKey {
+    text: String,
+    tag_id: u32,
+    is_alias: bool,
+}
+
+// Prebuilt search structure over tag names and aliases:
+// a sorted key array for prefix lookups and trigram postings for substring and typo-tolerant matching
+#[derive(Default)]
+pub struct TagIndex {
+    keys: Vec<IndexKey>,
+    names: Vec<String>,
+    post_counts: Vec<u64>,
+    trigrams: HashMap<u32, Vec<u32>>,
+    // ASCII characters used in keys, for generating single-edit query variants
+    alphabet: Vec<char>,
+}
+
+impl TagIndex {
+    pub fn build(tags: &HashMap<String, BooruTag>) -> Self {
+        let mut sorted: Vec<&BooruTag> = tags.values().collect();
+        sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut index = Self::default();
+        for (tag_id, tag) in sorted.into_iter().enumerate() {
+            let tag_id = tag_id as u32;
+            index.names.push(tag.name.clone());
+            index.post_counts.push(tag.post_count);
+            index.keys.push(IndexKey { text: tag.name.to_lowercase(), tag_id, is_alias: false });
+            for alias in tag.aliases.iter().chain(&tag.translations) {
+                index.keys.push(IndexKey { text: alias.to_lowercase(), tag_id, is_alias: true });
+            }
+        }
+        index.keys.sort_by(|a, b| a.text.cmp(&b.text));
+
+        let mut seen_chars = [false; 128];
+        for key in &index.keys {
+            for byte in key.text.bytes().filter(u8::is_ascii) {
+                seen_chars[byte as usize] = true;
+            }
+        }
+        index.alphabet = (0u8..128).filter(|&b| seen_chars[b as usize]).map(char::from).collect();
+
+        for (key_idx, key) in index.keys.iter().enumerate() {
+            let mut seen = Vec::new();
+            for trigram in trigrams(&key.text) {
+                if !seen.contains(&trigram) {
+                    seen.push(trigram);
+                    index.trigrams.entry(trigram).or_default().push(key_idx as u32);
+                }
+            }
+        }
+
+        index
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn name(&self, tag_id: u32) -> &str {
+        &self.names[tag_id as usize]
+    }
+
+    // The tag an alias (or translation) spelled exactly like `alias` belongs to
+    pub fn alias_target(&self, alias: &str) -> Option<&str> {
+        let alias = alias.to_lowercase();
+        let start = self.keys.partition_point(|key| key.text < alias);
+        self.keys[start..].iter()
+            .take_while(|key| key.text == alias)
+            .find(|key| key.is_alias)
+            .map(|key| self.name(key.tag_id))
+    }
+
+    // Best `limit` (tag id, match tier) pairs for the query: exact, prefix, substring,
+    // then fuzzy matches, each tier ordered by post count
+    pub fn search(&self, query: &str, limit: usize) -> Vec<(u32, u8)> {
+        let query = query.to_lowercase();
+        if query.is_empty() || self.is_empty() {
+            return Vec::new();
+        }
+
+        let mut best = vec![NO_MATCH; self.names.len()];
+        let mut matched = Vec::new();
+
+        let start = self.keys.partition_point(|key| key.text.as_str() < query.as_str());
+        for key in self.keys[start..].iter().take_while(|key| key.text.starts_with(&query)) {
+            let tier = match (key.text == query, key.is_alias) {
+                (true, false) => TIER_EXACT,
+                (false, false) => TIER_NAME_PREFIX,
+                (_, true) => TIER_ALIAS_PREFIX,
+            };
+            mark(&mut best, &mut matched, key.tag_id, tier);
+        }
+
+        // Lower tiers can only fill up the list, so skip them once it is full
+        if matched.len() < limit {
+            for key_idx in self.substring_matches(&query) {
+                mark(&mut best, &mut matched, self.keys[key_idx as usize].tag_id, TIER_SUBSTRING);
+            }
+        }
+        if matched.len() < limit {
+            for (key_idx, distance) in self.fuzzy_matches(&query) {
+                mark(&mut best, &mut matched, self.keys[key_idx as usize].tag_id, TIER_FUZZY + distance);
+            }
+        }
+
+        let rank = |tag_id: &u32| (best[*tag_id as usize], Reverse(self.post_counts[*tag_id as usize]), *tag_id);
+        if matched.len() > limit {
+            matched.select_nth_unstable_by_key(limit, rank);
+            matched.truncate(limit);
+        }
+        matched.sort_unstable_by_key(rank);
+        matched.into_iter().map(|tag_id| (tag_id, best[tag_id as usize])).collect()
+    }
+
+    fn substring_matches(&self, query: &str) -> Vec<u32> {
+        let query_trigrams: Vec<u32> = trigrams(query).collect();
+        if query_trigrams.is_empty() {
+            // Too short for trigrams; fall back to a scan
+            return self.keys.iter()
+                .enumerate()
+                .filter(|(_, key)| key.text.contains(query))
+                .map(|(key_idx, _)| key_idx as u32)
+                .collect();
+        }
+
+        // Verify candidates from the rarest trigram's posting list
+        let rarest = query_trigrams.iter()
+            .map(|trigram| self.trigrams.get(trigram).map_or(&[][..], Vec::as_slice))
+            .min_by_key(|postings| postings.len())
+            .unwrap_or(&[]);
+        rarest.iter()
+            .copied()
+            .filter(|&key_idx| self.keys[key_idx as usize].text.contains(query))
+            .collect()
+    }
+
+    // Keys whose prefix is within a small edit distance of the query, e.g. `lnog_hair` -> `long_hair`
+    fn fuzzy_matches(&self, query: &str) -> Vec<(u32, u8)> {
+        let query_chars: Vec<char> = query.chars().collect();
+        if query_chars.len() < 3 {
+            return Vec::new();
+        }
+        if query_chars.len() <= 10 {
+            return self.single_edit_matches(&query_chars);
+        }
+
+        // Longer queries allow two edits; each edit destroys at most three trigrams
+        let max_distance = 2;
+        let mut query_trigrams: Vec<u32> = trigrams(query).collect();
+        query_trigrams.sort_unstable();
+        query_trigrams.dedup();
+        let required = query_trigrams.len().saturating_sub(3 * max_distance as usize).max(1);
+
+        let mut shared = vec![0u8; self.keys.len()];
+        let mut candidates = Vec::new();
+        for trigram in &query_trigrams {
+            for &key_idx in self.trigrams.get(trigram).map_or(&[][..], Vec::as_slice) {
+                let count = &mut shared[key_idx as usize];
+                *count = count.saturating_add(1);
+                if *count as usize == required {
+                    candidates.push(key_idx);
+                }
+            }
+        }
+
+        candidates.into_iter()
+            .filter(|&key_idx| self.keys[key_idx as usize].text.starts_with(query_chars[0]))
+            .filter_map(|key_idx| {
+                prefix_edit_distance(&query_chars, &self.keys[key_idx as usize].text, max_distance)
+                    .map(|distance| (key_idx, distance))
+            })
+            .collect()
+    }
+
+    // Any key whose prefix is one edit away from the query starts with one of the query's
+    // single-edit variants, so a prefix lookup per variant finds them all. The first character is
+    // taken as typed, and an edit among the last two characters has to match a whole key: while
+    // typing, `long_ha` is not yet a typo of `long_pants`.
+    fn single_edit_matches(&self, query: &[char]) -> Vec<(u32, u8)> {
+        let variant = |before: &[char], middle: &[char], after: &[char]| -> String {
+            before.iter().chain(middle).chain(after).collect()
+        };
+
+        // (variant, whole keys only)
+        let mut variants = Vec::new();
+        for idx in 1..query.len() {
+            let rest = query.len() - idx - 1;
+            variants.push((variant(&query[..idx], &[], &query[idx + 1..]), rest < TYPED_TAIL));
+            if idx + 1 < query.len() {
+                variants.push((variant(&query[..idx], &[query[idx + 1], query[idx]], &query[idx + 2..]), rest - 1 < TYPED_TAIL));
+            }
+            for &c in self.alphabet.iter().filter(|&&c| c != query[idx]) {
+                variants.push((variant(&query[..idx], &[c], &query[idx + 1..]), rest < TYPED_TAIL));
+                variants.push((variant(&query[..idx], &[c], &query[idx..]), rest + 1 < TYPED_TAIL));
+            }
+        }
+
+        let mut matches = Vec::new();
+        for (variant, whole_key) in variants {
+            let start = self.keys.partition_point(|key| key.text.as_str() < variant.as_str());
+            let count = if whole_key {
+                self.keys[start..].partition_point(|key| key.text == variant)
+            } else {
+                self.keys[start..].partition_point(|key| key.text.starts_with(&variant))
+            };
+            matches.extend((start..start + count).map(|key_idx| (key_idx as u32, 1)));
+        }
+        matches
+    }
+}
+
+// Records the best tier seen for a tag
+fn mark(best: &mut [u8], matched: &mut Vec<u32>, tag_id: u32, tier: u8) {
+    let slot = &mut best[tag_id as usize];
+    if *slot == NO_MATCH {
+        matched.push(tag_id);
+    }
+    *slot = (*slot).min(tier);
+}
+
+fn trigrams(text: &str) -> impl Iterator<Item = u32> + '_ {
+    text.as_bytes()
+        .windows(3)
+        .map(|w| (w[0] as u32) << 16 | (w[1] as u32) << 8 | w[2] as u32)
+}
+
+// Smallest edit distance between the query and any prefix of `key`, if within `max_distance`.
+// Adjacent transpositions (`smlie` -> `smile`) count as a single edit.
+fn prefix_edit_distance(query: &[char], key: &str, max_distance: u8) -> Option<u8> {
+    let max_distance = max_distance as usize;
+    let width = query.len() + 1;
+    let mut before = vec![0; width];
+    let mut previous: Vec<usize> = (0..width).collect();
+    let mut current = vec![0; width];
+    let mut best = previous[query.len()];
+    let mut previous_key_char = None;
+
+    for (row, key_char) in key.chars().enumerate() {
+        current[0] = row + 1;
+        for (col, query_char) in query.iter().enumerate() {
+            let mut distance = (previous[col] + usize::from(*query_char != key_char))
+                .min(previous[col + 1] + 1)
+                .min(current[col] + 1);
+            if col > 0 && previous_key_char == Some(*query_char) && query[col - 1] == key_char {
+                distance = distance.min(before[col - 1] + 1);
+            }
+            current[col + 1] = distance;
+        }
+        best = best.min(current[query.len()]);
+        if current.iter().min().copied().unwrap_or(0) > max_distance {
+            break;
+        }
+
+        previous_key_char = Some(key_char);
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    (best <= max_distance).then_some(best as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::booru_tag_manager::TagCategory;
+
+    fn index(tags: &[(&str, u64, &[&str])]) -> TagIndex {
+        let tags = tags.iter()
+            .map(|&(name, post_count, aliases)| {
+                let tag = BooruTag {
+                    name: name.to_string(),
+                    tag_type: 0,
+                    category: TagCategory::General,
+                    post_count,
+                    aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+                    description: None,
+                    translations: Vec::new(),
+                };
+                (name.to_string(), tag)
+            })
+            .collect();
+        TagIndex::build(&tags)
+    }
+
+    fn search<'a>(index: &'a TagIndex, query: &str) -> Vec<(&'a str, u8)> {
+        index.search(query, 10).into_iter().map(|(tag_id, tier)| (index.name(tag_id), tier)).collect()
+    }
+
+    #[test]
+    fn orders_by_tier_then_post_count() {
+        let index = index(&[
+            ("long_hair", 100, &[]),
+            ("long_hair_between_eyes", 500, &[]),
+            ("very_long_hair", 900, &[]),
+            ("hair_ornament", 50, &["long_ribbon"]),
+            ("longan", 10, &[]),
+        ]);
+
+        assert_eq!(search(&index, "long_hair"), [
+            ("long_hair", TIER_EXACT),
+            ("long_hair_between_eyes", TIER_NAME_PREFIX),
+            ("very_long_hair", TIER_SUBSTRING),
+        ]);
+        assert_eq!(search(&index, "long"), [
+            ("long_hair_between_eyes", TIER_NAME_PREFIX),
+            ("long_hair", TIER_NAME_PREFIX),
+            ("longan", TIER_NAME_PREFIX),
+            ("hair_ornament", TIER_ALIAS_PREFIX),
+            ("very_long_hair", TIER_SUBSTRING),
+        ]);
+        // Long queries allow two edits, ranked after every other match
+        assert_eq!(search(&index, "LONG_HAIR_B"), [
+            ("long_hair_between_eyes", TIER_NAME_PREFIX),
+            ("long_hair", TIER_FUZZY + 2),
+        ]);
+        assert!(search(&index, "").is_empty());
+        assert_eq!(index.alias_target("Long_Ribbon"), Some("hair_ornament"));
+    }
+
+    #[test]
+    fn finds_typos_with_fuzzy_matching() {
+        let index = index(&[("long_hair", 100, &[]), ("smile", 200, &[]), ("thighhighs", 50, &[])]);
+
+        assert_eq!(search(&index, "lnog_hair"), [("long_hair", TIER_FUZZY + 1)]);
+        assert_eq!(search(&index, "lnog_ha"), [("long_hair", TIER_FUZZY + 1)]);
+        assert_eq!(search(&index, "smlie"), [("smile", TIER_FUZZY + 1)]);
+        assert_eq!(search(&index, "long_hiar"), [("long_hair", TIER_FUZZY + 1)]);
+        assert_eq!(search(&index, "thigghighss"), [("thighhighs", TIER_FUZZY + 2)]);
+    }
+
+    #[test]
+    fn fuzzy_matching_does_not_guess_wildly() {
+        let index = index(&[
+            ("1girl", 5000, &["1girls"]),
+            ("yuri", 200, &["girl_love", "girls_love"]),
+            ("long_hair", 1000, &[]),
+            ("pants", 400, &["long_pants"]),
+        ]);
+
+        // Not a typo of `girl_love` with the first character dropped
+        assert_eq!(search(&index, "1girl"), [("1girl", TIER_EXACT)]);
+        // Still being typed, not a typo of `long_pants`
+        assert_eq!(search(&index, "long_ha"), [("long_hair", TIER_NAME_PREFIX)]);
+        assert!(search(&index, "qirl").is_empty());
+    }
+
+    #[test]
+    fn prefix_edit_distances() {
+        let distance = |query: &str, key: &str, max| {
+            prefix_edit_distance(&query.chars().collect::<Vec<_>>(), key, max)
+        };
+        assert_eq!(distance("long", "long_hair", 2), Some(0));
+        assert_eq!(distance("lnog", "long_hair", 2), Some(1));
+        assert_eq!(distance("smlie", "smile", 2), Some(1));
+        assert_eq!(distance("lon_hair", "long_hair", 2), Some(1));
+        assert_eq!(distance("longg_hairr", "long_hair", 2), Some(2));
+        assert_eq!(distance("short", "long_hair", 2), None);
+        assert_eq!(distance("abc", "", 3), Some(3));
+    }
+}
@@ -0,0 +1,162 @@
+// Per-dataset tag policy: tags that must not appear and tags every image must carry. A required
+// entry may list alternatives separated by `|`, e.g. `rating:general | rating:sensitive`. Tags
+// are compared by name, ignoring weights, escapes and spaces vs underscores.
+
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+
+use crate::tag_syntax::lookup_name;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TagPolicy {
+    pub forbidden: Vec<String>,
+    // Strip forbidden tags when they are added or saved instead of only flagging them
+    pub strip_forbidden: bool,
+    pub required: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PolicyViolation {
+    // Required entries with none of their alternatives present
+    pub missing: Vec<String>,
+    pub forbidden: Vec<String>,
+}
+
+impl TagPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.forbidden.is_empty() && self.required.is_empty()
+    }
+
+    pub fn is_forbidden(&self, tag: &str) -> bool {
+        let name = lookup_name(tag);
+        self.forbidden.iter().any(|forbidden| lookup_name(forbidden) == name)
+    }
+
+    pub fn is_required(&self, tag: &str) -> bool {
+        let name = lookup_name(tag);
+        self.required.iter().any(|entry| alternatives(entry).any(|alt| alt == name))
+    }
+
+    pub fn add_required(&mut self, tag: &str) {
+        if !self.is_required(tag) {
+            self.required.push(tag.to_string());
+        }
+    }
+
+    pub fn missing(&self, tags: &[String]) -> Vec<String> {
+        let names: HashSet<String> = tags.iter().map(|tag| lookup_name(tag)).collect();
+        self.required.iter()
+            .filter(|entry| !alternatives(entry).any(|alt| names.contains(&alt)))
+            .cloned()
+            .collect()
+    }
+
+    pub fn check(&self, tags: &[String]) -> Option<PolicyViolation> {
+        let violation = PolicyViolation {
+            missing: self.missing(tags),
+            forbidden: tags.iter().filter(|tag| self.is_forbidden(tag)).cloned().collect(),
+        };
+        (!violation.missing.is_empty() || !violation.forbidden.is_empty()).then_some(violation)
+    }
+
+    // Required entries `before` satisfied that `after` no longer does
+    pub fn broken_requirements(&self, before: &[String], after: &[String]) -> Vec<String> {
+        if self.required.is_empty() {
+            return Vec::new();
+        }
+        let already_missing = self.missing(before);
+        self.missing(after).into_iter()
+            .filter(|entry| !already_missing.contains(entry))
+            .collect()
+    }
+
+    // Returns whether anything was removed
+    pub fn strip_forbidden_tags(&self, tags: &mut Vec<String>) -> bool {
+        let before = tags.len();
+        tags.retain(|tag| !self.is_forbidden(tag));
+        tags.len() != before
+    }
+
+    // Adds missing required tags that have a single option, in policy order at the front; entries
+    // with alternatives need a manual choice. Returns whether anything was added.
+    pub fn add_missing_tags(&self, tags: &mut Vec<String>) -> bool {
+        let added: Vec<String> = self.missing(tags).into_iter()
+            .filter(|entry| !entry.contains('|'))
+            .map(|entry| entry.trim().to_string())
+            .collect();
+        let changed = !added.is_empty();
+        tags.splice(0..0, added);
+        changed
+    }
+}
+
+fn alternatives(entry: &str) -> impl Iterator<Item = String> + '_ {
+    entry.split('|').map(lookup_name).filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(list: &[&str]) -> Vec<String> {
+        list.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    fn policy() -> TagPolicy {
+        TagPolicy {
+            forbidden: tags(&["watermark", "bad hands"]),
+            strip_forbidden: false,
+            required: tags(&["my_character", "rating:general | rating:sensitive"]),
+        }
+    }
+
+    #[test]
+    fn forbidden_tags_match_in_any_spelling() {
+        let policy = policy();
+        assert!(policy.is_forbidden("watermark"));
+        assert!(policy.is_forbidden("bad_hands"));
+        assert!(policy.is_forbidden("(bad hands:1.3)"));
+        assert!(!policy.is_forbidden("watermarked"));
+    }
+
+    #[test]
+    fn any_alternative_satisfies_a_required_entry() {
+        let policy = policy();
+        assert!(policy.is_required("rating:sensitive"));
+        assert!(policy.is_required("my character"));
+        assert!(policy.missing(&tags(&["my_character", "rating:sensitive"])).is_empty());
+        assert_eq!(policy.missing(&tags(&["my_character"])), ["rating:general | rating:sensitive"]);
+        assert_eq!(policy.missing(&tags(&["rating:general"])), ["my_character"]);
+    }
+
+    #[test]
+    fn broken_requirements_ignore_entries_that_were_already_missing() {
+        let policy = policy();
+        let before = tags(&["my_character", "1girl"]);
+        let after = tags(&["1girl"]);
+        assert_eq!(policy.broken_requirements(&before, &after), ["my_character"]);
+
+        // Swapping one alternative for another keeps the entry satisfied
+        let before = tags(&["my_character", "rating:general"]);
+        let after = tags(&["my_character", "rating:sensitive"]);
+        assert!(policy.broken_requirements(&before, &after).is_empty());
+
+        let before = tags(&["rating:general"]);
+        assert!(policy.broken_requirements(&before, &[]).contains(&"rating:general | rating:sensitive".to_string()));
+        assert!(!policy.broken_requirements(&before, &[]).contains(&"my_character".to_string()));
+    }
+
+    #[test]
+    fn adds_only_single_option_entries_at_the_front() {
+        let policy = policy();
+        let mut list = tags(&["1girl", "solo"]);
+        assert!(policy.add_missing_tags(&mut list));
+        assert_eq!(list, ["my_character", "1girl", "solo"]);
+        assert!(!policy.add_missing_tags(&mut list));
+
+        let mut list = tags(&["(my character:1.2)"]);
+        assert!(!policy.add_missing_tags(&mut list));
+        assert_eq!(list, ["(my character:1.2)"]);
+    }
+}
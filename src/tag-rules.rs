@@ -0,0 +1,216 @@
+// Declarative cleanup rules evaluated over an image's tags, loaded from TOML or JSON:
+//
+//   [[rule]]
+//   name = "hetero"
+//   if_all = ["1girl", "1boy"]
+//   add = ["hetero"]
+//
+// Rules run in file order and see the changes made by earlier ones. Tags are compared by
+// name, ignoring weights, escapes and spaces vs underscores.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use serde::Deserialize;
+
+use crate::tag_syntax::{lookup_name, WeightedTag};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Rule {
+    pub name: String,
+    // Conditions; a rule without any always applies
+    pub if_all: Vec<String>,
+    pub if_any: Vec<String>,
+    pub if_none: Vec<String>,
+    // Actions, applied in this order
+    pub replace: BTreeMap<String, String>,
+    pub remove: Vec<String>,
+    pub add: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RuleSet {
+    #[serde(default, rename = "rule", alias = "rules")]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleChange {
+    pub rule: String,
+    pub description: String,
+}
+
+impl Rule {
+    fn matches(&self, names: &[String]) -> bool {
+        let has = |tag: &String| names.contains(&lookup_name(tag));
+        self.if_all.iter().all(has)
+            && (self.if_any.is_empty() || self.if_any.iter().any(has))
+            && !self.if_none.iter().any(has)
+    }
+}
+
+impl RuleSet {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(path)?;
+        let rules: Self = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&contents)?,
+            _ => toml::from_str(&contents)?,
+        };
+        Ok(rules)
+    }
+
+    // Applies every matching rule to `tags`, returning what changed
+    pub fn apply(&self, tags: &mut Vec<String>) -> Vec<RuleChange> {
+        let mut changes = Vec::new();
+        for (idx, rule) in self.rules.iter().enumerate() {
+            let names: Vec<String> = tags.iter().map(|tag| lookup_name(tag)).collect();
+            if !rule.matches(&names) {
+                continue;
+            }
+            let rule_name = if rule.name.is_empty() { format!("rule {}", idx + 1) } else { rule.name.clone() };
+            let mut record = |description: String| changes.push(RuleChange { rule: rule_name.clone(), description });
+
+            let mut replaced = false;
+            for (from, to) in &rule.replace {
+                let from_name = lookup_name(from);
+                for tag in tags.iter_mut().filter(|tag| lookup_name(tag) == from_name) {
+                    // Keep any weight the tag had
+                    let mut parsed = WeightedTag::parse(tag);
+                    parsed.raw_name = to.clone();
+                    let text = parsed.to_text();
+                    record(format!("{} → {}", tag, text));
+                    *tag = text;
+                    replaced = true;
+                }
+            }
+
+            for remove in &rule.remove {
+                let remove_name = lookup_name(remove);
+                tags.retain(|tag| {
+                    let keep = lookup_name(tag) != remove_name;
+                    if !keep {
+                        record(format!("- {}", tag));
+                    }
+                    keep
+                });
+            }
+
+            for add in &rule.add {
+                let add_name = lookup_name(add);
+                if !tags.iter().any(|tag| lookup_name(tag) == add_name) {
+                    record(format!("+ {}", add));
+                    tags.push(add.clone());
+                }
+            }
+
+            if !replaced {
+                continue;
+            }
+            // A replacement can collide with a tag that was already present
+            let mut seen = Vec::new();
+            tags.retain(|tag| {
+                let name = lookup_name(tag);
+                let first = !seen.contains(&name);
+                seen.push(name);
+                first
+            });
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(source: &str) -> RuleSet {
+        toml::from_str(source).unwrap()
+    }
+
+    fn tags(list: &[&str]) -> Vec<String> {
+        list.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn conditions_gate_rules() {
+        let set = rules(r#"
+            [[rule]]
+            name = "hetero"
+            if_all = ["1girl", "1boy"]
+            if_none = ["yuri"]
+            add = ["hetero"]
+
+            [[rule]]
+            if_any = ["smile", "grin"]
+            add = ["happy"]
+        "#);
+
+        let mut list = tags(&["1girl", "1boy", "grin"]);
+        let changes = set.apply(&mut list);
+        assert_eq!(list, tags(&["1girl", "1boy", "grin", "hetero", "happy"]));
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].rule, "hetero");
+        // Unnamed rules are numbered from one
+        assert_eq!(changes[1].rule, "rule 2");
+
+        let mut list = tags(&["1girl", "1boy", "yuri"]);
+        assert!(set.apply(&mut list).is_empty());
+        let mut list = tags(&["1girl"]);
+        assert!(set.apply(&mut list).is_empty());
+    }
+
+    #[test]
+    fn matches_by_lookup_name() {
+        let set = rules(r#"
+            [[rule]]
+            if_all = ["long_hair"]
+            remove = ["blurry background"]
+            add = ["looking at viewer"]
+        "#);
+
+        let mut list = tags(&["(long hair:1.2)", "blurry_background", "looking_at_viewer"]);
+        let changes = set.apply(&mut list);
+        assert_eq!(list, tags(&["(long hair:1.2)", "looking_at_viewer"]));
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].description, "- blurry_background");
+    }
+
+    #[test]
+    fn replace_keeps_weights_and_drops_collisions() {
+        let set = rules(r#"
+            [[rule]]
+            replace = { "smiling" = "smile", "blonde" = "blonde_hair" }
+        "#);
+
+        let mut list = tags(&["(smiling:1.3)", "blonde", "blonde_hair", "solo"]);
+        set.apply(&mut list);
+        assert_eq!(list, tags(&["(smile:1.3)", "blonde_hair", "solo"]));
+    }
+
+    #[test]
+    fn later_rules_see_earlier_changes() {
+        let set = rules(r#"
+            [[rule]]
+            replace = { "girl" = "1girl" }
+
+            [[rule]]
+            if_all = ["1girl"]
+            if_none = ["multiple_girls"]
+            add = ["solo"]
+        "#);
+
+        let mut list = tags(&["girl"]);
+        let changes = set.apply(&mut list);
+        assert_eq!(list, tags(&["1girl", "solo"]));
+        assert_eq!(changes.len(), 2);
+    }
+
+    #[test]
+    fn loads_json_rules() {
+        let set: RuleSet = serde_json::from_str(r#"{"rules": [{"name": "a", "add": ["b"]}]}"#).unwrap();
+        let mut list = Vec::new();
+        set.apply(&mut list);
+        assert_eq!(list, tags(&["b"]));
+    }
+}
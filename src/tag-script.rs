@@ -0,0 +1,192 @@
+// Rhai scripts for one-off tag transforms. A script runs once per image with that image's `tags`
+// and `caption` in scope; whatever it leaves in them becomes the image's new captions. `images`
+// holds the whole dataset for cross-image logic, and the tag databases are available through
+// lookup functions. See the README for the full list.
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use rhai::{Array, Dynamic, Engine, Map, Scope};
+
+use crate::booru_tag_manager::TagLookup;
+use crate::tag_syntax::lookup_name;
+
+// Per image, so a runaway loop on one image fails the run without waiting for a cancel
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+pub struct ScriptImage {
+    pub path: PathBuf,
+    pub tags: Vec<String>,
+    pub caption: String,
+}
+
+pub struct ScriptChange {
+    pub path: PathBuf,
+    pub before_tags: Vec<String>,
+    pub before_caption: String,
+    pub tags: Vec<String>,
+    pub caption: String,
+}
+
+#[derive(Default)]
+pub struct ScriptRun {
+    // Images whose tags or caption the script changed; empty when it failed
+    pub changes: Vec<ScriptChange>,
+    // `print` and `debug` output
+    pub output: Vec<String>,
+    pub error: Option<String>,
+}
+
+// Runs `source` over `images[idx]` for each of `targets`, reporting how many images are done.
+// Setting `cancel` stops the run, even in the middle of an image.
+pub fn run(
+    source: &str,
+    images: &[ScriptImage],
+    targets: &[usize],
+    tags: TagLookup,
+    cancel: Arc<AtomicBool>,
+    mut progress: impl FnMut(usize),
+) -> ScriptRun {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    let stop = cancel.clone();
+    engine.on_progress(move |_| stop.load(Ordering::Relaxed).then_some(Dynamic::UNIT));
+    let log = output.clone();
+    engine.on_print(move |text| log.borrow_mut().push(text.to_string()));
+    let log = output.clone();
+    engine.on_debug(move |text, _, position| log.borrow_mut().push(format!("[{}] {}", position, text)));
+    register_lookups(&mut engine, Rc::new(tags));
+
+    let result = engine.compile(source)
+        .map_err(|err| err.to_string())
+        .and_then(|ast| {
+            let all: Dynamic = Dynamic::from_array(images.iter().map(image_map).collect()).into_shared();
+            let mut changes = Vec::new();
+            for (done, &idx) in targets.iter().enumerate() {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err("cancelled".to_string());
+                }
+                let image = &images[idx];
+                let name = image.path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                let mut scope = Scope::new();
+                scope.push_constant_dynamic("images", all.clone());
+                scope.push_constant("index", idx as i64);
+                scope.push_constant("path", image.path.to_string_lossy().into_owned());
+                scope.push_constant("name", name.clone());
+                scope.push("tags", string_array(&image.tags));
+                scope.push("caption", image.caption.clone());
+
+                engine.run_ast_with_scope(&mut scope, &ast).map_err(|err| match *err {
+                    rhai::EvalAltResult::ErrorTerminated(..) => "cancelled".to_string(),
+                    err => format!("{}: {}", name, err),
+                })?;
+                let (tags, caption) = read_back(&scope).map_err(|err| format!("{}: {}", name, err))?;
+                if tags != image.tags || caption != image.caption {
+                    changes.push(ScriptChange {
+                        path: image.path.clone(),
+                        before_tags: image.tags.clone(),
+                        before_caption: image.caption.clone(),
+                        tags,
+                        caption,
+                    });
+                }
+                progress(done + 1);
+            }
+            Ok(changes)
+        });
+    drop(engine);
+
+    let output = output.take();
+    match result {
+        Ok(changes) => ScriptRun { changes, output, error: None },
+        Err(error) => ScriptRun { changes: Vec::new(), output, error: Some(error) },
+    }
+}
+
+fn register_lookups(engine: &mut Engine, tags: Rc<TagLookup>) {
+    engine.register_fn("tag_name", |tag: &str| lookup_name(tag));
+    let db = tags.clone();
+    engine.register_fn("is_known", move |tag: &str| db.lookup(&lookup_name(tag)).is_some());
+    let db = tags.clone();
+    engine.register_fn("category", move |tag: &str| {
+        db.lookup(&lookup_name(tag)).map(|tag| tag.category.label().to_lowercase()).unwrap_or_default()
+    });
+    let db = tags.clone();
+    engine.register_fn("post_count", move |tag: &str| db.lookup(&lookup_name(tag)).map_or(0, |tag| tag.post_count as i64));
+    let db = tags.clone();
+    engine.register_fn("canonical", move |tag: &str| {
+        db.canonical_name(&lookup_name(tag)).unwrap_or_else(|| tag.to_string())
+    });
+    let db = tags;
+    engine.register_fn("aliases", move |tag: &str| {
+        db.lookup(&lookup_name(tag)).map(|tag| string_array(&tag.aliases)).unwrap_or_default()
+    });
+}
+
+fn image_map(image: &ScriptImage) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("path".into(), image.path.to_string_lossy().into_owned().into());
+    map.insert("name".into(), image.path.file_name().unwrap_or_default().to_string_lossy().into_owned().into());
+    map.insert("tags".into(), string_array(&image.tags).into());
+    map.insert("caption".into(), image.caption.clone().into());
+    map.into()
+}
+
+fn string_array(items: &[String]) -> Array {
+    items.iter().map(|item| item.clone().into()).collect()
+}
+
+fn read_back(scope: &Scope) -> Result<(Vec<String>, String), String> {
+    let tags = scope.get_value::<Array>("tags").ok_or("`tags` must stay an array")?;
+    let tags = tags.into_iter()
+        .map(|tag| tag.into_string().map_err(|kind| format!("tags must be strings, found {}", kind)))
+        .collect::<Result<Vec<String>, String>>()?
+        .into_iter()
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    let caption = scope.get_value::<String>("caption").ok_or("`caption` must stay a string")?;
+    Ok((tags, caption))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn images() -> Vec<ScriptImage> {
+        ["a.png", "b.png"].iter()
+            .map(|name| ScriptImage { path: PathBuf::from(name), tags: vec!["solo".to_string()], caption: String::new() })
+            .collect()
+    }
+
+    fn run_script(source: &str, cancel: bool) -> (ScriptRun, Vec<usize>) {
+        let mut done = Vec::new();
+        let cancel = Arc::new(AtomicBool::new(cancel));
+        let run = run(source, &images(), &[0, 1], TagLookup::default(), cancel, |count| done.push(count));
+        (run, done)
+    }
+
+    #[test]
+    fn collects_changes_and_progress() {
+        let (run, done) = run_script(r#"if name == "b.png" { tags.push("smile"); } print(index);"#, false);
+        assert_eq!(run.error, None);
+        assert_eq!(run.changes.len(), 1);
+        assert_eq!(run.changes[0].tags, ["solo", "smile"]);
+        assert_eq!(run.output, ["0", "1"]);
+        assert_eq!(done, [1, 2]);
+    }
+
+    #[test]
+    fn stops_runaway_and_cancelled_scripts() {
+        let (run, _) = run_script("loop { tags.push(\"x\"); tags.clear(); }", false);
+        assert!(run.error.is_some_and(|error| error.starts_with("a.png")));
+        assert!(run.changes.is_empty());
+
+        let (run, done) = run_script("tags.push(\"smile\");", true);
+        assert_eq!(run.error.as_deref(), Some("cancelled"));
+        assert!(run.changes.is_empty() && done.is_empty());
+    }
+}
@@ -0,0 +1,483 @@
+// Prompt-style tag syntax: `(tag:1.2)`, `((tag))`, `[tag]` and `\(`-escaped parentheses.
+// Tags are stored as written; `WeightedTag` only rewrites a tag when its weight or name is edited.
+
+use serde::{Deserialize, Serialize};
+
+const PAREN_MULTIPLIER: f32 = 1.1;
+
+// Emoticon tags whose underscores are part of the face, never converted to spaces
+const KAOMOJI: &[&str] = &[
+    "0_0", "(o)_(o)", "+_+", "+_-", "._.", "<o>_<o>", "<|>_<|>", "=_=", ">_<",
+    "3_3", "6_9", ">_o", "@_@", "^_^", "o_o", "u_u", "x_x", "|_|", "||_||",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Emphasis {
+    None,
+    // `(tag)` nested n times, each level multiplies by 1.1
+    Parens(u8),
+    // `[tag]` nested n times, each level divides by 1.1
+    Brackets(u8),
+    // `(tag:1.2)`, keeping the weight exactly as written
+    Explicit(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedTag {
+    // The tag text as written inside the emphasis, escapes included
+    pub raw_name: String,
+    pub emphasis: Emphasis,
+}
+
+impl WeightedTag {
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+
+        if let Some(inner) = strip_wrapper(text, '(', ')') {
+            if let Some(colon) = last_unescaped(inner, ':') {
+                let weight = inner[colon + 1..].trim();
+                if weight.parse::<f32>().is_ok() {
+                    return Self {
+                        raw_name: inner[..colon].trim().to_string(),
+                        emphasis: Emphasis::Explicit(weight.to_string()),
+                    };
+                }
+            }
+        }
+
+        for (open, close) in [('(', ')'), ('[', ']')] {
+            let mut depth = 0u8;
+            let mut inner = text;
+            while let Some(stripped) = strip_wrapper(inner, open, close) {
+                inner = stripped;
+                depth += 1;
+            }
+            if depth > 0 {
+                let emphasis = if open == '(' { Emphasis::Parens(depth) } else { Emphasis::Brackets(depth) };
+                return Self { raw_name: inner.to_string(), emphasis };
+            }
+        }
+
+        Self { raw_name: text.to_string(), emphasis: Emphasis::None }
+    }
+
+    // The tag without emphasis and escapes, e.g. `artist (series)`
+    pub fn name(&self) -> String {
+        unescape(&self.raw_name)
+    }
+
+    pub fn weight(&self) -> f32 {
+        match &self.emphasis {
+            Emphasis::None => 1.0,
+            Emphasis::Parens(depth) => PAREN_MULTIPLIER.powi(*depth as i32),
+            Emphasis::Brackets(depth) => PAREN_MULTIPLIER.powi(-(*depth as i32)),
+            Emphasis::Explicit(weight) => weight.parse().unwrap_or(1.0),
+        }
+    }
+
+    pub fn is_weighted(&self) -> bool {
+        self.emphasis != Emphasis::None
+    }
+
+    pub fn set_weight(&mut self, weight: f32) {
+        let weight = (weight * 100.0).round() / 100.0;
+        self.emphasis = if (weight - 1.0).abs() < f32::EPSILON {
+            Emphasis::None
+        } else {
+            Emphasis::Explicit(weight.to_string())
+        };
+    }
+
+    pub fn to_text(&self) -> String {
+        match &self.emphasis {
+            Emphasis::None => self.raw_name.clone(),
+            Emphasis::Parens(depth) => format!(
+                "{}{}{}", "(".repeat(*depth as usize), self.raw_name, ")".repeat(*depth as usize)
+            ),
+            Emphasis::Brackets(depth) => format!(
+                "{}{}{}", "[".repeat(*depth as usize), self.raw_name, "]".repeat(*depth as usize)
+            ),
+            Emphasis::Explicit(weight) => format!("({}:{})", self.raw_name, weight),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WordSeparator {
+    #[default]
+    Keep,
+    Underscores,
+    Spaces,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ParenStyle {
+    #[default]
+    Keep,
+    // `artist \(series\)`, safe for prompt-weighting trainers
+    Escaped,
+    // `artist (series)`
+    Plain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TagStyle {
+    pub separator: WordSeparator,
+    pub parentheses: ParenStyle,
+}
+
+impl WordSeparator {
+    pub const ALL: [WordSeparator; 3] = [WordSeparator::Keep, WordSeparator::Underscores, WordSeparator::Spaces];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            WordSeparator::Keep => "Keep as written",
+            WordSeparator::Underscores => "Underscores (long_hair)",
+            WordSeparator::Spaces => "Spaces (long hair)",
+        }
+    }
+}
+
+impl ParenStyle {
+    pub const ALL: [ParenStyle; 3] = [ParenStyle::Keep, ParenStyle::Escaped, ParenStyle::Plain];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ParenStyle::Keep => "Keep as written",
+            ParenStyle::Escaped => "Escaped \\( \\)",
+            ParenStyle::Plain => "Plain ( )",
+        }
+    }
+}
+
+impl TagStyle {
+    pub fn is_keep(&self) -> bool {
+        self.separator == WordSeparator::Keep && self.parentheses == ParenStyle::Keep
+    }
+
+    // Rewrites the tag name in this style, leaving any weight syntax untouched
+    pub fn apply(&self, text: &str) -> String {
+        if self.is_keep() {
+            return text.to_string();
+        }
+
+        let mut parsed = WeightedTag::parse(text);
+        let mut name = parsed.raw_name.clone();
+
+        if !KAOMOJI.contains(&unescape(&name).as_str()) {
+            match self.separator {
+                WordSeparator::Keep => {}
+                WordSeparator::Underscores => name = name.replace(' ', "_"),
+                WordSeparator::Spaces => name = name.replace('_', " "),
+            }
+        }
+
+        match self.parentheses {
+            ParenStyle::Keep => {}
+            ParenStyle::Escaped => name = escape_parentheses(&unescape(&name)),
+            ParenStyle::Plain => name = unescape(&name),
+        }
+
+        parsed.raw_name = name;
+        parsed.to_text()
+    }
+}
+
+// Splits a caption on commas that are not inside parentheses/brackets or escaped. A bracket only
+// opens a group when it starts a tag or is closed before the next comma, so emoticon tags like
+// `>:(` stay on their own; a group that is never closed falls back to splitting on every comma.
+pub fn split_tags(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tags = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0i32;
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        let c = chars[idx];
+        idx += 1;
+        match c {
+            '\\' => {
+                current.push(c);
+                if let Some(&escaped) = chars.get(idx) {
+                    current.push(escaped);
+                    idx += 1;
+                }
+                continue;
+            }
+            '(' | '[' if depth > 0 || opens_group(&current, &chars[idx..]) => depth += 1,
+            ')' | ']' => depth = (depth - 1).max(0),
+            ',' if depth == 0 => {
+                tags.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    tags.push(current);
+
+    if depth > 0 {
+        return split_plain(text);
+    }
+    tags.into_iter()
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+// Whether a bracket at the top level starts a group: it comes first in its tag (after other
+// opening brackets), or its closing bracket follows before the next comma
+fn opens_group(before: &str, after: &[char]) -> bool {
+    if before.trim().chars().all(|c| c == '(' || c == '[') {
+        return true;
+    }
+    let mut depth = 1;
+    let mut chars = after.iter();
+    while let Some(&c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '(' | '[' => depth += 1,
+            ')' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return true;
+                }
+            }
+            ',' => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+// Splits on every unescaped comma
+fn split_plain(text: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                current.push(c);
+                current.extend(chars.next());
+            }
+            ',' => tags.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    tags.push(current);
+    tags.into_iter()
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+// Plain tag name used for database lookups and suggestion matching.
+// Tolerates half-typed input such as `(long_ha` or `(smile:1.`
+pub fn lookup_name(text: &str) -> String {
+    let parsed = WeightedTag::parse(text);
+    let mut name = parsed.raw_name.as_str();
+    if parsed.emphasis == Emphasis::None {
+        // A weight only follows an opening parenthesis, so tags like `>:3` keep their colon
+        let opened = name.starts_with('(');
+        name = name.trim_start_matches(['(', '[']);
+        if let Some(colon) = last_unescaped(name, ':').filter(|&colon| opened && colon > 0) {
+            let weight = &name[colon + 1..];
+            if weight.chars().all(|c| c.is_ascii_digit() || c == '.' || c == ')') {
+                name = &name[..colon];
+            }
+        }
+    }
+    unescape(name.trim()).replace(' ', "_")
+}
+
+pub fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                result.push(escaped);
+                continue;
+            }
+        }
+        result.push(c);
+    }
+    result
+}
+
+pub fn escape_parentheses(text: &str) -> String {
+    text.replace('(', "\\(").replace(')', "\\)")
+}
+
+fn is_escaped(text: &str, idx: usize) -> bool {
+    text[..idx].chars().rev().take_while(|&c| c == '\\').count() % 2 == 1
+}
+
+fn last_unescaped(text: &str, target: char) -> Option<usize> {
+    text.char_indices()
+        .rev()
+        .find(|&(idx, c)| c == target && !is_escaped(text, idx))
+        .map(|(idx, _)| idx)
+}
+
+// Removes one level of `open ... close` if it wraps the whole text
+fn strip_wrapper(text: &str, open: char, close: char) -> Option<&str> {
+    if text.len() < 2 || !text.starts_with(open) || !text.ends_with(close) {
+        return None;
+    }
+    let close_idx = text.len() - close.len_utf8();
+    if is_escaped(text, close_idx) {
+        return None;
+    }
+
+    // The opening bracket must be closed by the final one, not earlier as in `(a) (b)`
+    let mut depth = 0;
+    for (idx, c) in text.char_indices() {
+        if is_escaped(text, idx) {
+            continue;
+        }
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 && idx != close_idx {
+                return None;
+            }
+        }
+    }
+
+    if depth == 0 {
+        Some(&text[open.len_utf8()..close_idx])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_explicit_weights() {
+        let tag = WeightedTag::parse("(smile:1.2)");
+        assert_eq!(tag.raw_name, "smile");
+        assert_eq!(tag.emphasis, Emphasis::Explicit("1.2".to_string()));
+        assert!((tag.weight() - 1.2).abs() < 1e-6);
+        assert_eq!(tag.to_text(), "(smile:1.2)");
+
+        // Not a number, so the parentheses are plain emphasis
+        let tag = WeightedTag::parse("(rating:general)");
+        assert_eq!(tag.raw_name, "rating:general");
+        assert_eq!(tag.emphasis, Emphasis::Parens(1));
+    }
+
+    #[test]
+    fn parses_nested_emphasis() {
+        let tag = WeightedTag::parse("((blue eyes))");
+        assert_eq!(tag.raw_name, "blue eyes");
+        assert_eq!(tag.emphasis, Emphasis::Parens(2));
+        assert!((tag.weight() - 1.21).abs() < 1e-6);
+
+        let tag = WeightedTag::parse("[[blurry]]");
+        assert_eq!(tag.raw_name, "blurry");
+        assert_eq!(tag.emphasis, Emphasis::Brackets(2));
+        assert!((tag.weight() - 1.0 / 1.21).abs() < 1e-6);
+
+        let tag = WeightedTag::parse("[tag]");
+        assert_eq!(tag.emphasis, Emphasis::Brackets(1));
+        assert_eq!(tag.to_text(), "[tag]");
+
+        // Two groups side by side are not one wrapped tag
+        assert_eq!(WeightedTag::parse("(a) (b)").emphasis, Emphasis::None);
+    }
+
+    #[test]
+    fn keeps_escaped_parentheses_in_the_name() {
+        let tag = WeightedTag::parse("artist \\(series\\)");
+        assert_eq!(tag.emphasis, Emphasis::None);
+        assert_eq!(tag.raw_name, "artist \\(series\\)");
+        assert_eq!(tag.name(), "artist (series)");
+
+        let tag = WeightedTag::parse("(artist \\(series\\):0.8)");
+        assert_eq!(tag.raw_name, "artist \\(series\\)");
+        assert_eq!(tag.emphasis, Emphasis::Explicit("0.8".to_string()));
+        assert_eq!(tag.name(), "artist (series)");
+
+        // The escaped closing parenthesis does not close the group
+        assert_eq!(WeightedTag::parse("(tag\\)").emphasis, Emphasis::None);
+    }
+
+    #[test]
+    fn set_weight_round_trips() {
+        let mut tag = WeightedTag::parse("smile");
+        tag.set_weight(1.25);
+        assert_eq!(tag.to_text(), "(smile:1.25)");
+        tag.set_weight(1.0);
+        assert_eq!(tag.to_text(), "smile");
+    }
+
+    #[test]
+    fn splits_on_top_level_commas() {
+        assert_eq!(split_tags("1girl, (smile, happy:1.2), solo"), ["1girl", "(smile, happy:1.2)", "solo"]);
+        assert_eq!(split_tags("a\\, b, c"), ["a\\, b", "c"]);
+        assert_eq!(split_tags(" ,a,, [b, c] ,"), ["a", "[b, c]"]);
+        assert!(split_tags("").is_empty());
+    }
+
+    #[test]
+    fn keeps_emoticon_tags_apart() {
+        assert_eq!(split_tags("1girl, >:(, solo"), ["1girl", ">:(", "solo"]);
+        assert_eq!(split_tags("1girl, ;(, solo"), ["1girl", ";(", "solo"]);
+        assert_eq!(split_tags("1girl, :), solo"), ["1girl", ":)", "solo"]);
+        assert_eq!(split_tags(">:(, (smile, happy:1.2), ;("), [">:(", "(smile, happy:1.2)", ";("]);
+        assert_eq!(split_tags("artist_(name), solo"), ["artist_(name)", "solo"]);
+
+        // An unclosed group falls back to plain commas
+        assert_eq!(split_tags("(long_ha, solo, smile"), ["(long_ha", "solo", "smile"]);
+        assert_eq!(split_tags("a\\, b, [c"), ["a\\, b", "[c"]);
+    }
+
+    #[test]
+    fn applies_tag_styles() {
+        let spaces = TagStyle { separator: WordSeparator::Spaces, parentheses: ParenStyle::Keep };
+        assert_eq!(spaces.apply("long_hair"), "long hair");
+        assert_eq!(spaces.apply("(long_hair:1.2)"), "(long hair:1.2)");
+        assert_eq!(spaces.apply("^_^"), "^_^");
+
+        let underscores = TagStyle { separator: WordSeparator::Underscores, parentheses: ParenStyle::Keep };
+        assert_eq!(underscores.apply("((long hair))"), "((long_hair))");
+
+        let escaped = TagStyle { separator: WordSeparator::Keep, parentheses: ParenStyle::Escaped };
+        assert_eq!(escaped.apply("artist (series)"), "artist \\(series\\)");
+        assert_eq!(escaped.apply("artist \\(series\\)"), "artist \\(series\\)");
+
+        let plain = TagStyle { separator: WordSeparator::Spaces, parentheses: ParenStyle::Plain };
+        assert_eq!(plain.apply("artist_\\(series\\)"), "artist (series)");
+
+        assert_eq!(TagStyle::default().apply("Any_Tag "), "Any_Tag ");
+    }
+
+    #[test]
+    fn lookup_names() {
+        assert_eq!(lookup_name("long hair"), "long_hair");
+        assert_eq!(lookup_name("(long hair:1.2)"), "long_hair");
+        assert_eq!(lookup_name("((smile))"), "smile");
+        assert_eq!(lookup_name("artist \\(series\\)"), "artist_(series)");
+
+        // Half-typed input while editing
+        assert_eq!(lookup_name("(long_ha"), "long_ha");
+        assert_eq!(lookup_name("(smile:1."), "smile");
+        assert_eq!(lookup_name("[blurry"), "blurry");
+
+        // Colons outside explicit parentheses belong to the tag
+        assert_eq!(lookup_name(">:3"), ">:3");
+        assert_eq!(lookup_name(":3"), ":3");
+        assert_eq!(lookup_name("tag:0.8"), "tag:0.8");
+        assert_eq!(lookup_name("rating:general"), "rating:general");
+    }
+}
@@ -0,0 +1,196 @@
+// Offline tag wiki dumps, e.g. Danbooru's `wiki_pages` export, used for tag description tooltips.
+// Accepts a JSON array, JSON lines, or a CSV of `title,body` with or without a header.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use csv::ReaderBuilder;
+use serde::Deserialize;
+
+// Longest description shown in a tooltip
+const MAX_DESCRIPTION_CHARS: usize = 600;
+
+#[derive(Deserialize)]
+struct WikiPage {
+    #[serde(alias = "name", alias = "tag")]
+    title: String,
+    #[serde(alias = "description", default)]
+    body: String,
+    #[serde(default)]
+    is_deleted: bool,
+}
+
+// Tag name -> short plain-text description
+pub fn load_wiki_dump(path: &Path) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let contents = fs::read_to_string(path)?;
+    let trimmed = contents.trim_start();
+
+    let pages: Vec<WikiPage> = if trimmed.starts_with('[') {
+        serde_json::from_str(trimmed)?
+    } else if trimmed.starts_with('{') {
+        trimmed.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?
+    } else {
+        read_csv_pages(&contents)?
+    };
+
+    Ok(pages.into_iter()
+        .filter(|page| !page.is_deleted)
+        .filter_map(|page| {
+            let description = summarize(&page.body);
+            (!description.is_empty()).then(|| (page.title.trim().to_lowercase().replace(' ', "_"), description))
+        })
+        .collect())
+}
+
+fn read_csv_pages(contents: &str) -> Result<Vec<WikiPage>, Box<dyn std::error::Error>> {
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(contents.as_bytes());
+
+    let mut title_col = 0;
+    let mut body_col = 1;
+    let mut pages = Vec::new();
+    for (row, record) in rdr.records().enumerate() {
+        let record = record?;
+        if row == 0 {
+            let fields: Vec<String> = record.iter().map(|f| f.trim().to_lowercase()).collect();
+            let title = fields.iter().position(|f| matches!(f.as_str(), "title" | "name" | "tag"));
+            let body = fields.iter().position(|f| matches!(f.as_str(), "body" | "description"));
+            if let (Some(title), Some(body)) = (title, body) {
+                title_col = title;
+                body_col = body;
+                continue;
+            }
+        }
+        if let (Some(title), Some(body)) = (record.get(title_col), record.get(body_col)) {
+            pages.push(WikiPage { title: title.to_string(), body: body.to_string(), is_deleted: false });
+        }
+    }
+    Ok(pages)
+}
+
+// First paragraph of a DText wiki body with links and markup reduced to plain text
+pub fn summarize(body: &str) -> String {
+    let mut paragraph = Vec::new();
+    for line in body.lines().map(str::trim) {
+        // Section headings such as `h4. See also` end the introduction
+        if line.starts_with('h') && line.get(2..4) == Some(". ") && line.as_bytes()[1].is_ascii_digit() {
+            break;
+        }
+        if line.is_empty() {
+            if paragraph.is_empty() {
+                continue;
+            }
+            break;
+        }
+        paragraph.push(line);
+    }
+
+    let text = strip_dtext(&paragraph.join(" "));
+    if text.chars().count() <= MAX_DESCRIPTION_CHARS {
+        return text;
+    }
+    let mut truncated: String = text.chars().take(MAX_DESCRIPTION_CHARS).collect();
+    truncated.push('…');
+    truncated
+}
+
+// `[[tag|label]]` -> label, `[[tag]]` -> tag, `"text":url` -> text, `[b]`/`[/i]`-style tags removed
+fn strip_dtext(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['[', '"']) {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(inner) = rest.strip_prefix("[[") {
+            if let Some(end) = inner.find("]]") {
+                let link = &inner[..end];
+                let label = link.split_once('|').map_or(link, |(_, label)| label);
+                // Labels may carry their own markup, e.g. `[[cat_ears|[i]cat[/i] ears]]`
+                result.push_str(&strip_dtext(label).replace('_', " "));
+                rest = &inner[end + 2..];
+                continue;
+            }
+        } else if rest.starts_with('[') {
+            if let Some(end) = rest.find(']') {
+                let tag = &rest[1..end];
+                if !tag.is_empty() && tag.len() <= 12 && !tag.contains(' ') {
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        } else if let Some(inner) = rest.strip_prefix('"') {
+            if let Some(end) = inner.find('"').filter(|&end| inner[end + 1..].starts_with(':')) {
+                let after = &inner[end + 2..];
+                let url_end = after.find(char::is_whitespace).unwrap_or(after.len());
+                result.push_str(&inner[..end]);
+                rest = &after[url_end..];
+                continue;
+            }
+        }
+
+        let c = rest.chars().next().unwrap_or_default();
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_links_and_markup() {
+        assert_eq!(strip_dtext("Wearing [[school_uniform]]."), "Wearing school uniform.");
+        assert_eq!(strip_dtext("See [[long_hair|long]] and [[hair_ribbon|ribbons]]"), "See long and ribbons");
+        assert_eq!(strip_dtext("[b]Not[/b] to be confused with [i][[cat_ears|[b]cat[/b] ears]][/i]."),
+            "Not to be confused with cat ears.");
+        assert_eq!(strip_dtext("From \"the artist\":https://example.com/page , [u]2019[/u]"),
+            "From the artist , 2019");
+        // Brackets and quotes that are not markup stay
+        assert_eq!(strip_dtext("A \"quoted\" [word with spaces] or [[unclosed"),
+            "A \"quoted\" [word with spaces] or [[unclosed");
+    }
+
+    #[test]
+    fn summary_is_the_first_paragraph_up_to_the_limit() {
+        let body = "\n[b]Blue eyes[/b]: a character\nwith [[blue|blue]] eyes.\n\nSecond paragraph.";
+        assert_eq!(summarize(body), "Blue eyes: a character with blue eyes.");
+        assert_eq!(summarize("Short intro.\nh4. See also\n* [[green_eyes]]"), "Short intro.");
+        assert_eq!(summarize("h4. See also\n* [[green_eyes]]"), "");
+
+        let long = "é".repeat(MAX_DESCRIPTION_CHARS);
+        assert_eq!(summarize(&long), long);
+        let summary = summarize(&format!("{}x", long));
+        assert_eq!(summary.chars().count(), MAX_DESCRIPTION_CHARS + 1);
+        assert!(summary.ends_with("é…"));
+    }
+
+    #[test]
+    fn loads_each_dump_format() {
+        let dir = std::env::temp_dir();
+        let pid = std::process::id();
+        let dumps = [
+            ("json", r#"[{"title": "Blue Eyes", "body": "Eyes that are [[blue]]."}, {"title": "gone", "body": "x", "is_deleted": true}]"#),
+            ("jsonl", "{\"name\": \"blue_eyes\", \"description\": \"Eyes that are [[blue]].\"}\n\n{\"name\": \"empty\"}\n"),
+            ("csv", "id,body,title\n1,Eyes that are [[blue]].,blue eyes\n2,,empty\n"),
+            ("headless.csv", "blue_eyes,Eyes that are [[blue]].\n"),
+        ];
+        for (ext, contents) in dumps {
+            let path = dir.join(format!("tag-wiki-{}.{}", pid, ext));
+            fs::write(&path, contents).unwrap();
+            let wiki = load_wiki_dump(&path);
+            let _ = fs::remove_file(&path);
+            let wiki = wiki.unwrap();
+            assert_eq!(wiki.len(), 1, "{}", ext);
+            assert_eq!(wiki["blue_eyes"], "Eyes that are blue.", "{}", ext);
+        }
+    }
+}